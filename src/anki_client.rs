@@ -1,7 +1,13 @@
+use std::collections::HashMap;
+
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 
+use crate::config::{DuplicateConfig, DuplicatePolicy, HttpConfig};
 use crate::errors::AppError;
+use crate::notetype::NoteTypeDefinition;
+use crate::retry::{self, RetryPolicy};
+use crate::suggest;
 use crate::types::CardFields;
 
 #[derive(Serialize)]
@@ -18,9 +24,59 @@ struct AnkiResponse {
     error: Option<String>,
 }
 
+/// A single field of an existing note, as returned by `notesInfo`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct NoteField {
+    pub value: String,
+    #[serde(default)]
+    pub order: usize,
+}
+
+/// An existing note, as returned by `notesInfo`. AnkiConnect returns an empty
+/// object for ids that don't exist, which leaves `note_id` at 0.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NoteInfo {
+    #[serde(default)]
+    pub note_id: u64,
+    #[serde(default)]
+    pub model_name: String,
+    #[serde(default)]
+    pub fields: HashMap<String, NoteField>,
+}
+
+impl NoteInfo {
+    /// Field names in note type order.
+    pub fn field_names(&self) -> Vec<String> {
+        let mut names: Vec<(&String, usize)> =
+            self.fields.iter().map(|(k, f)| (k, f.order)).collect();
+        names.sort_by_key(|(_, order)| *order);
+        names.into_iter().map(|(k, _)| k.clone()).collect()
+    }
+}
+
+/// Names confirmed by `preflight`, as they are spelled in Anki.
+pub struct PreflightResult {
+    pub deck: String,
+    pub note_type: String,
+    pub fields: Vec<String>,
+    /// Every field of the note type, in order.
+    pub all_fields: Vec<String>,
+}
+
+/// Actions that change Anki in a way that repeating would duplicate.
+const NON_IDEMPOTENT_ACTIONS: &[&str] = &["addNote", "addNotes", "createModel"];
+
+/// Anki's answer while a profile is loading or a sync is running.
+const COLLECTION_UNAVAILABLE: &str = "collection is not available";
+
+/// Up to this many names are listed when none is close to the requested one.
+const MAX_LISTED_NAMES: usize = 10;
+
 pub struct AnkiConnectClient {
     url: String,
     client: reqwest::Client,
+    retry: RetryPolicy,
 }
 
 impl AnkiConnectClient {
@@ -28,11 +84,23 @@ impl AnkiConnectClient {
         Self {
             url,
             client: reqwest::Client::new(),
+            retry: RetryPolicy::none(),
         }
     }
 
+    /// Apply timeouts and retry transient failures. Fails if the HTTP client can't be
+    /// built, rather than falling back to one without timeouts.
+    pub fn with_http(mut self, config: &HttpConfig) -> Result<Self, AppError> {
+        self.client = retry::client(config)?;
+        self.retry = RetryPolicy::from_config(config);
+        Ok(self)
+    }
+
+    /// Not retried, so `check` reports a stopped Anki right away.
     pub async fn ping(&self) -> Result<u64, AppError> {
-        let anki_resp = self.request("version", serde_json::json!({})).await?;
+        let anki_resp = self
+            .request_with("version", serde_json::json!({}), &RetryPolicy::none())
+            .await?;
         if let Some(err) = anki_resp.error {
             return Err(AppError::Anki(err));
         }
@@ -41,14 +109,52 @@ impl AnkiConnectClient {
     }
 
     async fn request(&self, action: &str, params: Value) -> Result<AnkiResponse, AppError> {
+        self.request_with(action, params, &self.retry).await
+    }
+
+    /// Send an action, retrying refused connections and an unavailable collection.
+    /// Actions that are harmless to repeat are also retried after timeouts and 5xx
+    /// responses; the others may already have taken effect then, e.g. added a note.
+    async fn request_with(
+        &self,
+        action: &str,
+        params: Value,
+        retry: &RetryPolicy,
+    ) -> Result<AnkiResponse, AppError> {
         let req = AnkiRequest {
             action: action.to_string(),
             version: 6,
             params,
         };
-        let resp = self.client.post(&self.url).json(&req).send().await?;
-        let anki_resp: AnkiResponse = resp.json().await?;
-        Ok(anki_resp)
+        let idempotent = !NON_IDEMPOTENT_ACTIONS.contains(&action);
+        let mut failures = 0;
+        loop {
+            failures += 1;
+            let reason = match self.client.post(&self.url).json(&req).send().await {
+                Err(e) if e.is_connect() || (idempotent && e.is_timeout()) => {
+                    AppError::request("AnkiConnect", &self.url, e)
+                }
+                Err(e) => return Err(AppError::request("AnkiConnect", &self.url, e)),
+                Ok(resp) if idempotent && resp.status().is_server_error() => {
+                    AppError::Anki(format!("HTTP status {}", resp.status()))
+                }
+                Ok(resp) => {
+                    let anki_resp: AnkiResponse = resp
+                        .json()
+                        .await
+                        .map_err(|e| AppError::request("AnkiConnect", &self.url, e))?;
+                    match anki_resp.error {
+                        Some(ref err) if err.contains(COLLECTION_UNAVAILABLE) => {
+                            AppError::Anki(err.clone())
+                        }
+                        _ => return Ok(anki_resp),
+                    }
+                }
+            };
+            if !retry.wait(failures, "AnkiConnect", &reason).await {
+                return Err(reason);
+            }
+        }
     }
 
     pub async fn get_deck_names(&self) -> Result<Vec<String>, AppError> {
@@ -83,6 +189,12 @@ impl AnkiConnectClient {
             )
             .await?;
         if let Some(err) = anki_resp.error {
+            if err.starts_with("model was not found") {
+                return Err(AppError::NoteTypeNotFound(format!(
+                    "Note type '{}' not found",
+                    model
+                )));
+            }
             return Err(AppError::Anki(err));
         }
         let names: Vec<String> = anki_resp
@@ -92,60 +204,140 @@ impl AnkiConnectClient {
         Ok(names)
     }
 
-    /// Validate deck, note type, and fields exist. Returns all fields of the note type.
+    /// Search notes with an Anki browser query. Returns note ids.
+    pub async fn find_notes(&self, query: &str) -> Result<Vec<u64>, AppError> {
+        let anki_resp = self
+            .request("findNotes", serde_json::json!({ "query": query }))
+            .await?;
+        if let Some(err) = anki_resp.error {
+            return Err(AppError::Anki(err));
+        }
+        let ids: Vec<u64> = anki_resp
+            .result
+            .map(|v| serde_json::from_value(v).unwrap_or_default())
+            .unwrap_or_default();
+        Ok(ids)
+    }
+
+    pub async fn notes_info(&self, ids: &[u64]) -> Result<Vec<NoteInfo>, AppError> {
+        if ids.is_empty() {
+            return Ok(Vec::new());
+        }
+        let anki_resp = self
+            .request("notesInfo", serde_json::json!({ "notes": ids }))
+            .await?;
+        if let Some(err) = anki_resp.error {
+            return Err(AppError::Anki(err));
+        }
+        let notes = match anki_resp.result {
+            Some(v) => serde_json::from_value(v)?,
+            None => Vec::new(),
+        };
+        Ok(notes)
+    }
+
+    /// Check that the deck, note type and fields exist. Returns their names as found
+    /// in Anki (resolved when `auto_resolve` is set) and all fields of the note type.
     pub async fn preflight(
         &self,
         deck: &str,
         note_type: &str,
         fields: &[String],
-    ) -> Result<Vec<String>, AppError> {
+        create_missing_deck: bool,
+        auto_resolve: bool,
+    ) -> Result<PreflightResult, AppError> {
         // Check deck exists
         let decks = self.get_deck_names().await?;
-        if !decks.iter().any(|d| d == deck) {
-            return Err(AppError::Anki(format!(
-                "Deck '{}' not found. Available: {}",
-                deck,
-                decks.join(", ")
-            )));
-        }
+        let deck = match Self::find_name("Deck", deck, "", &decks, auto_resolve) {
+            Ok(found) => found,
+            Err(_) if create_missing_deck => {
+                let id = self.create_deck(deck).await?;
+                eprintln!("  Created deck '{}' (id {})", deck, id);
+                deck.to_string()
+            }
+            Err(message) => return Err(AppError::DeckNotFound(message)),
+        };
 
         // Check note type exists
         let models = self.get_model_names().await?;
-        if !models.iter().any(|m| m == note_type) {
-            return Err(AppError::Anki(format!(
-                "Note type '{}' not found. Available: {}",
-                note_type,
-                models.join(", ")
-            )));
-        }
+        let note_type = Self::find_name("Note type", note_type, "", &models, auto_resolve)
+            .map_err(AppError::NoteTypeNotFound)?;
 
         // Check fields match
-        let model_fields = self.get_model_field_names(note_type).await?;
-        let missing: Vec<&String> = fields
-            .iter()
-            .filter(|f| !model_fields.contains(f))
-            .collect();
+        let model_fields = self.get_model_field_names(&note_type).await?;
+        let context = format!(" in note type '{}'", note_type);
+        let mut resolved = Vec::new();
+        let mut missing = Vec::new();
+        for field in fields {
+            match Self::find_name("Field", field, &context, &model_fields, auto_resolve) {
+                Ok(found) => resolved.push(found),
+                Err(message) => missing.push(message),
+            }
+        }
         if !missing.is_empty() {
-            return Err(AppError::Anki(format!(
-                "Fields {:?} not found in note type '{}'. Available fields: {}",
-                missing,
-                note_type,
-                model_fields.join(", ")
-            )));
+            return Err(AppError::FieldMismatch(missing.join(" ")));
         }
 
         // Warn if sort field (first field) is not in the user's requested fields
-        if let Some(sort_field) = model_fields.first() {
-            if !fields.contains(sort_field) {
-                eprintln!(
-                    "  WARNING: Sort field '{}' is not in your --fields list. \
-                     It will be left empty unless other fields cover it.",
-                    sort_field
-                );
-            }
+        if let Some(sort_field) = model_fields.first()
+            && !resolved.contains(sort_field)
+        {
+            eprintln!(
+                "  WARNING: Sort field '{}' is not in your --fields list. \
+                 It will be left empty unless other fields cover it.",
+                sort_field
+            );
+        }
+
+        Ok(PreflightResult {
+            deck,
+            note_type,
+            fields: resolved,
+            all_fields: model_fields,
+        })
+    }
+
+    /// `name` if it exists, or its unambiguous case/Unicode-insensitive match when
+    /// `auto_resolve` is set. Otherwise an error message with suggestions.
+    fn find_name(
+        kind: &str,
+        name: &str,
+        context: &str,
+        available: &[String],
+        auto_resolve: bool,
+    ) -> Result<String, String> {
+        if available.iter().any(|a| a == name) {
+            return Ok(name.to_string());
+        }
+
+        let resolved = suggest::resolve(name, available);
+        if let Some(found) = resolved.filter(|_| auto_resolve) {
+            eprintln!(
+                "  Resolved {} '{}' to '{}'",
+                kind.to_lowercase(),
+                name,
+                found
+            );
+            return Ok(found.clone());
         }
 
-        Ok(model_fields)
+        let mut message = format!(
+            "{} '{}'{} not found{}",
+            kind,
+            name,
+            context,
+            suggest::did_you_mean(name, available)
+        );
+        if resolved.is_some() {
+            message.push_str(" Use --auto-resolve to pick it automatically.");
+        } else if suggest::closest(name, available).is_empty() {
+            if available.len() <= MAX_LISTED_NAMES {
+                message.push_str(&format!(". Available: {}.", available.join(", ")));
+            } else {
+                message.push_str(&format!(" ({} available).", available.len()));
+            }
+        }
+        Err(message)
     }
 
     pub async fn add_note(
@@ -154,7 +346,9 @@ impl AnkiConnectClient {
         note_type: &str,
         deck: &str,
         all_model_fields: &[String],
-    ) -> Result<(), AppError> {
+        tags: &[String],
+        duplicates: &DuplicateConfig,
+    ) -> Result<u64, AppError> {
         // Build full fields map — every note type field present, empty if not provided
         let mut full_fields = serde_json::Map::new();
         for field_name in all_model_fields {
@@ -165,23 +359,150 @@ impl AnkiConnectClient {
             full_fields.insert(field_name.clone(), Value::String(value));
         }
 
+        let options = match duplicates.policy {
+            DuplicatePolicy::Allow => serde_json::json!({ "allowDuplicate": true }),
+            DuplicatePolicy::AllowOtherDeck => serde_json::json!({
+                "allowDuplicate": false,
+                "duplicateScope": "deck",
+                "duplicateScopeOptions": {
+                    "deckName": deck,
+                    "checkChildren": duplicates.check_children,
+                    "checkAllModels": duplicates.check_all_models
+                }
+            }),
+            _ => serde_json::json!({ "allowDuplicate": false }),
+        };
+
         let note = serde_json::json!({
             "deckName": deck,
             "modelName": note_type,
             "fields": full_fields,
-            "options": {
-                "allowDuplicate": false
-            }
+            "tags": tags,
+            "options": options
         });
 
         let anki_resp = self
             .request("addNote", serde_json::json!({ "note": note }))
             .await?;
 
+        if let Some(err) = anki_resp.error {
+            if err.contains("duplicate") {
+                let key = all_model_fields.first();
+                return Err(AppError::Duplicate(format!(
+                    "a '{}' note with {} '{}' already exists",
+                    note_type,
+                    key.map_or("first field", |k| k.as_str()),
+                    key.and_then(|k| fields.get(k)).map_or("", |v| v.as_str())
+                )));
+            }
+            if err.starts_with("deck was not found") {
+                return Err(AppError::DeckNotFound(err));
+            }
+            if err.starts_with("model was not found") {
+                return Err(AppError::NoteTypeNotFound(err));
+            }
+            return Err(AppError::Anki(err));
+        }
+
+        anki_resp
+            .result
+            .and_then(|v| v.as_u64())
+            .ok_or_else(|| AppError::Anki("addNote returned no note id".into()))
+    }
+
+    /// The existing note of this type whose `field` equals `value`, if any.
+    pub async fn find_note_by_field(
+        &self,
+        note_type: &str,
+        field: &str,
+        value: &str,
+    ) -> Result<Option<NoteInfo>, AppError> {
+        let query = format!(
+            "note:\"{}\" \"{}:{}\"",
+            escape_search(note_type),
+            escape_search(field),
+            escape_search(value)
+        );
+        let ids = self.find_notes(&query).await?;
+        Ok(self.notes_info(&ids[..ids.len().min(1)]).await?.pop())
+    }
+
+    /// Store a file in Anki's media folder. Returns the name Anki stored it under.
+    pub async fn store_media_file(&self, filename: &str, base64: &str) -> Result<String, AppError> {
+        let anki_resp = self
+            .request(
+                "storeMediaFile",
+                serde_json::json!({ "filename": filename, "data": base64 }),
+            )
+            .await?;
+        if let Some(err) = anki_resp.error {
+            return Err(AppError::Anki(err));
+        }
+        Ok(anki_resp
+            .result
+            .and_then(|v| v.as_str().map(String::from))
+            .unwrap_or_else(|| filename.to_string()))
+    }
+
+    /// Overwrite some fields of an existing note.
+    pub async fn update_note_fields(&self, id: u64, fields: &CardFields) -> Result<(), AppError> {
+        let anki_resp = self
+            .request(
+                "updateNoteFields",
+                serde_json::json!({ "note": { "id": id, "fields": fields } }),
+            )
+            .await?;
+        if let Some(err) = anki_resp.error {
+            return Err(AppError::Anki(err));
+        }
+        Ok(())
+    }
+
+    /// Create a deck. `Parent::Child` names create the parents as needed.
+    pub async fn create_deck(&self, deck: &str) -> Result<u64, AppError> {
+        let anki_resp = self
+            .request("createDeck", serde_json::json!({ "deck": deck }))
+            .await?;
         if let Some(err) = anki_resp.error {
             return Err(AppError::Anki(err));
         }
+        Ok(anki_resp.result.and_then(|v| v.as_u64()).unwrap_or(0))
+    }
 
+    /// Create a note type with its fields, card templates and CSS.
+    pub async fn create_model(&self, definition: &NoteTypeDefinition) -> Result<(), AppError> {
+        let templates: Vec<Value> = definition
+            .templates
+            .iter()
+            .map(|t| serde_json::json!({ "Name": t.name, "Front": t.front, "Back": t.back }))
+            .collect();
+        let anki_resp = self
+            .request(
+                "createModel",
+                serde_json::json!({
+                    "modelName": definition.name,
+                    "inOrderFields": definition.fields,
+                    "css": definition.css,
+                    "isCloze": definition.is_cloze,
+                    "cardTemplates": templates,
+                }),
+            )
+            .await?;
+        if let Some(err) = anki_resp.error {
+            return Err(AppError::Anki(err));
+        }
         Ok(())
     }
 }
+
+/// Quote-safe text for an Anki search term, with wildcards matched literally.
+pub(crate) fn escape_search(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if matches!(c, '"' | '\\' | '*' | '_') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
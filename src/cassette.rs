@@ -0,0 +1,194 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::config::ModelOptions;
+use crate::errors::AppError;
+use crate::types::ChatMessage;
+
+/// Whether the cassette captures live responses or serves them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CassetteMode {
+    Record,
+    Replay,
+}
+
+/// Everything that determines a model response. Its hash is the cassette key.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct CassetteRequest {
+    pub model: String,
+    pub messages: Vec<ChatMessage>,
+    pub schema: serde_json::Value,
+    #[serde(default, skip_serializing_if = "ModelOptions::is_empty")]
+    pub options: ModelOptions,
+}
+
+/// An embedding request, as made by semantic dedup.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct EmbeddingRequest {
+    pub model: String,
+    pub prompt: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct CassetteEntry {
+    request: CassetteRequest,
+    response: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct EmbeddingEntry {
+    request: EmbeddingRequest,
+    embedding: Vec<f32>,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct CassetteFile {
+    entries: BTreeMap<String, CassetteEntry>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    embeddings: BTreeMap<String, EmbeddingEntry>,
+}
+
+/// Recorded model responses, keyed by a hash of the request.
+pub struct Cassette {
+    path: PathBuf,
+    mode: CassetteMode,
+    file: Mutex<CassetteFile>,
+}
+
+impl Cassette {
+    /// Open a cassette for recording. New responses are added to any existing entries.
+    pub fn record(path: PathBuf) -> Result<Self, AppError> {
+        let file = if path.exists() {
+            Self::load(&path)?
+        } else {
+            CassetteFile::default()
+        };
+        Ok(Self {
+            path,
+            mode: CassetteMode::Record,
+            file: Mutex::new(file),
+        })
+    }
+
+    /// Open an existing cassette for replay.
+    pub fn replay(path: PathBuf) -> Result<Self, AppError> {
+        let file = Self::load(&path)?;
+        Ok(Self {
+            path,
+            mode: CassetteMode::Replay,
+            file: Mutex::new(file),
+        })
+    }
+
+    fn load(path: &PathBuf) -> Result<CassetteFile, AppError> {
+        let data = fs::read_to_string(path)?;
+        Ok(serde_json::from_str(&data)?)
+    }
+
+    pub fn mode(&self) -> CassetteMode {
+        self.mode
+    }
+
+    pub fn len(&self) -> usize {
+        let file = self.file.lock().unwrap();
+        file.entries.len() + file.embeddings.len()
+    }
+
+    pub fn key(request: &impl Serialize) -> String {
+        // serde_json maps are sorted, so the serialisation is stable
+        let canonical = serde_json::to_string(request).unwrap_or_default();
+        Sha256::digest(canonical.as_bytes())
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect()
+    }
+
+    /// In replay mode, the recorded response for this request (a miss is an error).
+    /// In record mode, always `None` so the caller goes to the live model.
+    pub fn lookup(&self, request: &CassetteRequest) -> Result<Option<String>, AppError> {
+        if self.mode != CassetteMode::Replay {
+            return Ok(None);
+        }
+
+        let key = Self::key(request);
+        match self.file.lock().unwrap().entries.get(&key) {
+            Some(entry) => Ok(Some(entry.response.clone())),
+            None => Err(self.miss(&key)),
+        }
+    }
+
+    /// `lookup` for embeddings.
+    pub fn lookup_embedding(
+        &self,
+        request: &EmbeddingRequest,
+    ) -> Result<Option<Vec<f32>>, AppError> {
+        if self.mode != CassetteMode::Replay {
+            return Ok(None);
+        }
+
+        let key = Self::key(request);
+        match self.file.lock().unwrap().embeddings.get(&key) {
+            Some(entry) => Ok(Some(entry.embedding.clone())),
+            None => Err(self.miss(&key)),
+        }
+    }
+
+    fn miss(&self, key: &str) -> AppError {
+        AppError::Model(format!(
+            "No cassette entry for this request (key {}) in '{}'. Re-record it with --record",
+            &key[..12],
+            self.path.display()
+        ))
+    }
+
+    /// Store a live response and write the cassette. No-op in replay mode.
+    pub fn store(&self, request: &CassetteRequest, response: &str) -> Result<(), AppError> {
+        if self.mode != CassetteMode::Record {
+            return Ok(());
+        }
+
+        let mut file = self.file.lock().unwrap();
+        file.entries.insert(
+            Self::key(request),
+            CassetteEntry {
+                request: request.clone(),
+                response: response.to_string(),
+            },
+        );
+        self.save(&file)
+    }
+
+    /// `store` for embeddings.
+    pub fn store_embedding(
+        &self,
+        request: &EmbeddingRequest,
+        embedding: &[f32],
+    ) -> Result<(), AppError> {
+        if self.mode != CassetteMode::Record {
+            return Ok(());
+        }
+
+        let mut file = self.file.lock().unwrap();
+        file.embeddings.insert(
+            Self::key(request),
+            EmbeddingEntry {
+                request: request.clone(),
+                embedding: embedding.to_vec(),
+            },
+        );
+        self.save(&file)
+    }
+
+    fn save(&self, file: &CassetteFile) -> Result<(), AppError> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(&self.path, serde_json::to_string_pretty(file)?)?;
+        Ok(())
+    }
+}
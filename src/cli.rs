@@ -1,4 +1,12 @@
-use clap::{Parser, Subcommand};
+use std::path::PathBuf;
+
+use chrono::NaiveDate;
+use clap::{Args, Parser, Subcommand};
+
+use crate::config::{DuplicatePolicy, OllamaApi};
+use crate::history::HistoryFilter;
+use crate::metrics::GroupBy;
+use crate::output::OutputFormat;
 
 #[derive(Parser)]
 #[command(name = "anki_gen")]
@@ -15,10 +23,50 @@ pub struct Cli {
     #[arg(long, global = true)]
     pub ollama_url: Option<String>,
 
+    /// Ollama endpoint to use for generation
+    #[arg(long, global = true, value_enum)]
+    pub ollama_api: Option<OllamaApi>,
+
     /// AnkiConnect URL
     #[arg(long, global = true)]
     pub anki_url: Option<String>,
 
+    /// Config profile to apply (model and option overrides)
+    #[arg(long, global = true)]
+    pub profile: Option<String>,
+
+    /// Sampling temperature
+    #[arg(long, global = true)]
+    pub temperature: Option<f32>,
+
+    /// Random seed (use with a fixed temperature for reproducible runs)
+    #[arg(long, global = true)]
+    pub seed: Option<i64>,
+
+    /// Context window size in tokens
+    #[arg(long, global = true)]
+    pub num_ctx: Option<u32>,
+
+    /// How long Ollama keeps the model loaded (e.g. "10m", "-1" for forever)
+    #[arg(long, global = true)]
+    pub keep_alive: Option<String>,
+
+    /// Output format: human-readable text, or JSON lines on stdout for scripting
+    #[arg(long, global = true, value_enum)]
+    pub output_format: Option<OutputFormat>,
+
+    /// Generate and validate cards without adding them to Anki or history
+    #[arg(long, global = true)]
+    pub dry_run: bool,
+
+    /// Record every model response to this cassette file
+    #[arg(long, global = true, conflicts_with = "replay")]
+    pub record: Option<PathBuf>,
+
+    /// Serve model responses from this cassette file instead of calling Ollama
+    #[arg(long, global = true)]
+    pub replay: Option<PathBuf>,
+
     /// Anki deck name
     #[arg(long, short)]
     pub deck: Option<String>,
@@ -27,13 +75,49 @@ pub struct Cli {
     #[arg(long, short)]
     pub note_type: Option<String>,
 
+    /// Create the deck (and parent decks) if it doesn't exist
+    #[arg(long)]
+    pub create_deck: bool,
+
+    /// Use deck, note type and field names that match ignoring case, when unambiguous
+    #[arg(long)]
+    pub auto_resolve: bool,
+
+    /// What to do when Anki reports a note as a duplicate
+    #[arg(long, value_enum)]
+    pub duplicates: Option<DuplicatePolicy>,
+
     /// Card fields (comma-separated)
     #[arg(long, short, value_delimiter = ',')]
     pub fields: Vec<String>,
 
+    /// Note field to store source images in (see --image)
+    #[arg(long)]
+    pub image_field: Option<String>,
+
     /// Allow optional fields (model can skip or leave empty non-crucial fields)
     #[arg(long)]
     pub optional_fields: bool,
+
+    /// Token budget for `next` prompts (older history is summarised to fit)
+    #[arg(long)]
+    pub max_prompt_tokens: Option<usize>,
+
+    /// Skip cards that are semantically similar to history or existing notes (uses embeddings)
+    #[arg(long)]
+    pub semantic_dedup: bool,
+
+    /// Cosine similarity threshold for --semantic-dedup (0.0-1.0)
+    #[arg(long)]
+    pub dedup_threshold: Option<f32>,
+
+    /// Have a reviewer model grade each card (see `review` in config)
+    #[arg(long)]
+    pub review: bool,
+
+    /// Model used by --review (defaults to --model)
+    #[arg(long)]
+    pub review_model: Option<String>,
 }
 
 #[derive(Subcommand)]
@@ -44,6 +128,9 @@ pub enum Commands {
     Generate {
         /// Description of the card to generate
         description: String,
+        /// Image for a multimodal model to base the card on (repeatable)
+        #[arg(long)]
+        image: Vec<PathBuf>,
     },
     /// Generate the next card in a sequence (auto-skips already generated)
     Next {
@@ -52,9 +139,54 @@ pub enum Commands {
     },
     /// Generate cards from a list (comma-separated or @filename)
     Batch {
-        /// Comma-separated list of items, or @filename to read from file
+        /// Comma-separated list of items, or @filename to read from file. An item can
+        /// be an image path, optionally followed by `| description`
         items: String,
     },
+    /// Manage note types
+    Notetype {
+        #[command(subcommand)]
+        action: NotetypeCommand,
+    },
+    /// Regenerate some fields of existing notes
+    Regen {
+        /// Note id, or an Anki search query (e.g. "deck:Japanese Grammar:ておく")
+        target: String,
+        /// Fields to regenerate (comma-separated); the others are kept as context
+        #[arg(long, short, value_delimiter = ',', required = true)]
+        fields: Vec<String>,
+    },
+    /// Restore the fields overwritten by the latest regen of a note
+    Revert {
+        /// Note id
+        note_id: u64,
+    },
+    /// Inspect and edit the history of used items
+    History {
+        #[command(subcommand)]
+        action: HistoryCommand,
+    },
+    /// Show generation metrics aggregated across runs
+    Stats {
+        /// Group by model, deck and/or command (comma-separated)
+        #[arg(long, value_delimiter = ',', default_value = "model")]
+        by: Vec<GroupBy>,
+        /// Only runs started on or after this date (YYYY-MM-DD)
+        #[arg(long)]
+        since: Option<NaiveDate>,
+        /// Only runs started on or before this date (YYYY-MM-DD)
+        #[arg(long)]
+        until: Option<NaiveDate>,
+    },
+    /// Serve a local JSON HTTP API for generate, next, batch, history and check
+    Serve {
+        /// Address to listen on
+        #[arg(long, default_value = "127.0.0.1")]
+        host: String,
+        /// Port to listen on
+        #[arg(long, short, default_value_t = 8080)]
+        port: u16,
+    },
     /// Generate example configuration file
     Config {
         /// Output format (yaml or json)
@@ -62,3 +194,84 @@ pub enum Commands {
         format: String,
     },
 }
+
+#[derive(Subcommand)]
+pub enum NotetypeCommand {
+    /// Create a note type (fields, card templates, CSS) from a YAML or JSON file
+    Create {
+        /// Definition file
+        file: PathBuf,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum HistoryCommand {
+    /// List used items, oldest first
+    List {
+        #[command(flatten)]
+        filter: HistoryFilterArgs,
+    },
+    /// List used items containing a text
+    Search {
+        /// Text to look for (case and width are ignored)
+        query: String,
+        #[command(flatten)]
+        filter: HistoryFilterArgs,
+    },
+    /// Forget items, so `next` may suggest them again
+    Remove {
+        /// Item names
+        #[arg(required = true)]
+        items: Vec<String>,
+    },
+    /// Forget all items, or those matching the filters
+    Clear {
+        #[command(flatten)]
+        filter: HistoryFilterArgs,
+        /// Actually remove them (otherwise only count them)
+        #[arg(long)]
+        yes: bool,
+    },
+    /// Write the history as JSON to a file, or to stdout
+    Export {
+        /// Output file
+        file: Option<PathBuf>,
+    },
+    /// Add items from an exported history or a text file with one item per line
+    Import {
+        /// Input file
+        file: PathBuf,
+        /// Replace the current items instead of adding to them
+        #[arg(long)]
+        replace: bool,
+    },
+}
+
+/// Filters for `history` commands. Items recorded before history had metadata
+/// don't match any of them.
+#[derive(Args)]
+pub struct HistoryFilterArgs {
+    /// Only items added to this deck (or its subdecks)
+    #[arg(long)]
+    pub deck: Option<String>,
+    /// Only items from `next` runs whose description contains this text
+    #[arg(long)]
+    pub topic: Option<String>,
+    /// Only items added on or after this date (YYYY-MM-DD)
+    #[arg(long)]
+    pub since: Option<NaiveDate>,
+    /// Only items added on or before this date (YYYY-MM-DD)
+    #[arg(long)]
+    pub until: Option<NaiveDate>,
+}
+
+impl From<&HistoryFilterArgs> for HistoryFilter {
+    fn from(args: &HistoryFilterArgs) -> Self {
+        Self {
+            deck: args.deck.clone(),
+            topic: args.topic.clone(),
+            since: args.since,
+            until: args.until,
+        }
+    }
+}
@@ -1,6 +1,28 @@
-use serde::{Deserialize, Serialize};
+use clap::ValueEnum;
+use regex::Regex;
+use serde::de::Error as _;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::{BTreeMap, HashMap};
 use std::fs;
 use std::path::Path;
+use std::str::FromStr;
+
+use crate::errors::AppError;
+use crate::field_map::FieldSource;
+use crate::output::OutputFormat;
+use crate::static_fields::StaticValue;
+
+/// Tokens kept free in the context window for the model's answer.
+const RESPONSE_RESERVE_TOKENS: usize = 1024;
+
+/// Where `load_or_default` looks for a config file, in order.
+const CONFIG_PATHS: &[&str] = &[
+    "config.yaml",
+    "config.yml",
+    "config.json",
+    ".anki_gen.yaml",
+    ".anki_gen.json",
+];
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Config {
@@ -10,23 +32,450 @@ pub struct Config {
     #[serde(default = "default_ollama_url")]
     pub ollama_url: String,
 
+    #[serde(default)]
+    pub ollama_api: OllamaApi,
+
     #[serde(default = "default_anki_url")]
     pub anki_url: String,
 
+    /// Timeouts and retries for requests to Ollama and AnkiConnect.
+    #[serde(default)]
+    pub http: HttpConfig,
+
     #[serde(default)]
     pub deck: Option<String>,
 
     #[serde(default = "default_note_type")]
     pub note_type: String,
 
+    /// Create the deck (and its parents) during preflight if it doesn't exist.
+    #[serde(default)]
+    pub create_missing_deck: bool,
+
+    /// Use a deck, note type or field that matches ignoring case and Unicode form,
+    /// when exactly one does.
+    #[serde(default)]
+    pub auto_resolve_names: bool,
+
     #[serde(default)]
     pub fields: Vec<String>,
 
+    /// Note field that receives the source images of image-based cards, stored in
+    /// Anki's media folder. The model doesn't fill it.
+    #[serde(default)]
+    pub image_field: Option<String>,
+
+    /// Note fields filled from other generated keys, templates or constants, instead
+    /// of a key with the field's name.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub field_map: BTreeMap<String, FieldSource>,
+
+    /// Note fields set without the model: fixed text with placeholders, or the output
+    /// of a command.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub static_fields: BTreeMap<String, StaticValue>,
+
+    /// More notes made from each generated card, e.g. the reverse of a vocab card.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub siblings: Vec<NoteMapping>,
+
     #[serde(default = "default_storage_path")]
     pub storage_path: String,
 
     #[serde(default = "default_optional_fields")]
     pub optional_fields: bool,
+
+    #[serde(default)]
+    pub output_format: OutputFormat,
+
+    /// What to do when Anki reports the note as a duplicate.
+    #[serde(default)]
+    pub duplicates: DuplicateConfig,
+
+    #[serde(default)]
+    pub dedup: DedupConfig,
+
+    /// Optional second pass where a (possibly stronger) model grades each card.
+    #[serde(default)]
+    pub review: ReviewConfig,
+
+    /// Per-field processing applied to model output before it is added to Anki.
+    #[serde(default)]
+    pub postprocess: PostprocessConfig,
+
+    /// Upper bound for the estimated size of `next` prompts, in tokens.
+    #[serde(default = "default_max_prompt_tokens")]
+    pub max_prompt_tokens: usize,
+
+    /// How many times `next` may regenerate when the model repeats a history item.
+    #[serde(default = "default_next_max_attempts")]
+    pub next_max_attempts: u32,
+
+    /// Rules checked on each field's value, by field name.
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub validation: HashMap<String, Vec<Rule>>,
+
+    /// How many times a card may be generated when it breaks validation rules.
+    #[serde(default = "default_validation_max_attempts")]
+    pub validation_max_attempts: u32,
+
+    /// Ollama sampling/runtime options sent with every generation request.
+    #[serde(default)]
+    pub options: ModelOptions,
+
+    /// How long Ollama keeps the model loaded after a request (e.g. "5m", "-1").
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub keep_alive: Option<String>,
+
+    /// Named overrides selected with `--profile`.
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub profiles: HashMap<String, Profile>,
+
+    /// Generate and validate cards without touching Anki or history (CLI only).
+    #[serde(skip)]
+    pub dry_run: bool,
+}
+
+/// Ollama `options`. Unset values fall back to the model's defaults.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ModelOptions {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f32>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seed: Option<i64>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub num_ctx: Option<u32>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub num_predict: Option<i32>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub top_p: Option<f32>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub top_k: Option<u32>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub repeat_penalty: Option<f32>,
+
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub stop: Vec<String>,
+}
+
+/// A named set of model settings layered over the base config.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Profile {
+    #[serde(default)]
+    pub model: Option<String>,
+
+    #[serde(default)]
+    pub options: ModelOptions,
+
+    #[serde(default)]
+    pub keep_alive: Option<String>,
+}
+
+impl ModelOptions {
+    pub fn is_empty(&self) -> bool {
+        *self == ModelOptions::default()
+    }
+
+    /// Overlay every option that is set in `other`.
+    pub fn merge(&mut self, other: &ModelOptions) {
+        if other.temperature.is_some() {
+            self.temperature = other.temperature;
+        }
+        if other.seed.is_some() {
+            self.seed = other.seed;
+        }
+        if other.num_ctx.is_some() {
+            self.num_ctx = other.num_ctx;
+        }
+        if other.num_predict.is_some() {
+            self.num_predict = other.num_predict;
+        }
+        if other.top_p.is_some() {
+            self.top_p = other.top_p;
+        }
+        if other.top_k.is_some() {
+            self.top_k = other.top_k;
+        }
+        if other.repeat_penalty.is_some() {
+            self.repeat_penalty = other.repeat_penalty;
+        }
+        if !other.stop.is_empty() {
+            self.stop = other.stop.clone();
+        }
+    }
+}
+
+/// Which Ollama endpoint generation requests go to.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum OllamaApi {
+    /// `/api/chat` with a system message and a chat transcript.
+    #[default]
+    Chat,
+    /// `/api/generate` with the system preamble passed as `system`.
+    Generate,
+}
+
+/// Timeouts are in seconds, 0 for none. The total timeout covers one attempt,
+/// including streaming the response.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HttpConfig {
+    #[serde(default = "default_connect_timeout_secs")]
+    pub connect_timeout_secs: f64,
+
+    /// Longest wait for the next bytes of a response, e.g. while a model loads.
+    #[serde(default = "default_read_timeout_secs")]
+    pub read_timeout_secs: f64,
+
+    #[serde(default = "default_total_timeout_secs")]
+    pub total_timeout_secs: f64,
+
+    /// Retries after a refused connection, a 5xx response or Anki reporting that
+    /// the collection is not available.
+    #[serde(default = "default_max_retries")]
+    pub max_retries: u32,
+
+    /// Delay before the first retry, doubled for each further one.
+    #[serde(default = "default_retry_backoff_ms")]
+    pub retry_backoff_ms: u64,
+
+    #[serde(default = "default_max_backoff_ms")]
+    pub max_backoff_ms: u64,
+}
+
+/// What to do when a note's first field matches an existing note.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum DuplicatePolicy {
+    /// Don't add the note, and don't count it as a failure.
+    Skip,
+    /// Report the duplicate as an error.
+    #[default]
+    Fail,
+    /// Add the note anyway.
+    Allow,
+    /// Add the note unless the duplicate is in the same deck.
+    AllowOtherDeck,
+    /// Fill the empty fields of the existing note instead.
+    UpdateEmpty,
+}
+
+/// A sibling note: where it goes and which generated key fills each of its fields.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NoteMapping {
+    /// Defaults to the deck of the main note.
+    #[serde(default)]
+    pub deck: Option<String>,
+    pub note_type: String,
+    /// Note field to the generated key (or template, constant, ...) that fills it.
+    pub fields: BTreeMap<String, FieldSource>,
+}
+
+/// Duplicate handling, mapped onto AnkiConnect's `addNote` options.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct DuplicateConfig {
+    #[serde(default)]
+    pub policy: DuplicatePolicy,
+
+    /// With `allow_other_deck`, also treat notes in child decks as the same deck.
+    #[serde(default)]
+    pub check_children: bool,
+
+    /// With `allow_other_deck`, compare against notes of every note type.
+    #[serde(default)]
+    pub check_all_models: bool,
+}
+
+/// What to do with a card whose key field is semantically close to an existing item.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DedupAction {
+    /// Don't add the card.
+    Skip,
+    /// Add the card anyway, tagged for manual review.
+    Flag,
+}
+
+/// Semantic duplicate detection using embeddings from the model backend.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DedupConfig {
+    #[serde(default)]
+    pub enabled: bool,
+
+    #[serde(default = "default_embedding_model")]
+    pub embedding_model: String,
+
+    /// Cosine similarity at or above which two items count as duplicates.
+    #[serde(default = "default_similarity_threshold")]
+    pub threshold: f32,
+
+    #[serde(default = "default_dedup_action")]
+    pub action: DedupAction,
+
+    /// Also compare against the key field of notes already in the deck.
+    #[serde(default = "default_check_existing_notes")]
+    pub check_existing_notes: bool,
+
+    #[serde(default = "default_embedding_cache_path")]
+    pub cache_path: String,
+}
+
+/// What to do with a card the reviewer scores below the threshold.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReviewAction {
+    /// Generate the card again with the issues as feedback; tag it if attempts run out.
+    Regenerate,
+    /// Don't add the card; append it to the review queue file instead.
+    Hold,
+    /// Add the card, tagged `anki_gen::needs_review`.
+    Tag,
+}
+
+/// LLM-as-judge review of generated cards.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReviewConfig {
+    #[serde(default)]
+    pub enabled: bool,
+
+    /// Reviewer model. Defaults to the generation model.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+
+    /// Criteria the reviewer grades against.
+    #[serde(default = "default_review_rubric")]
+    pub rubric: String,
+
+    /// Minimum acceptable score, on a 0-10 scale.
+    #[serde(default = "default_review_threshold")]
+    pub threshold: f32,
+
+    #[serde(default = "default_review_action")]
+    pub action: ReviewAction,
+
+    /// Generations allowed per card with `action: regenerate`.
+    #[serde(default = "default_review_max_attempts")]
+    pub max_attempts: u32,
+
+    /// Where held cards are written.
+    #[serde(default = "default_review_queue_path")]
+    pub queue_path: String,
+}
+
+/// A check on a generated field's value. Empty values are not checked.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Rule {
+    /// Must match this regular expression.
+    Pattern(Pattern),
+    /// At least this many characters.
+    MinLength(usize),
+    /// At most this many characters.
+    MaxLength(usize),
+    /// Must contain kana or kanji.
+    ContainsJapanese,
+    /// Must contain the card's key expression (its first field).
+    ContainsKey,
+    /// Must be exactly one of these values.
+    OneOf(Vec<String>),
+    /// Must be one of N5, N4, N3, N2, N1.
+    JlptLevel,
+    /// Must differ from the named field.
+    NotEqual(String),
+}
+
+/// A regular expression for the `pattern` rule, compiled when the config is loaded so
+/// a bad one is reported as a config error rather than as a failing card.
+#[derive(Debug, Clone)]
+pub struct Pattern(Regex);
+
+impl Pattern {
+    pub fn as_str(&self) -> &str {
+        self.0.as_str()
+    }
+
+    pub fn is_match(&self, value: &str) -> bool {
+        self.0.is_match(value)
+    }
+}
+
+impl FromStr for Pattern {
+    type Err = regex::Error;
+
+    fn from_str(pattern: &str) -> Result<Self, Self::Err> {
+        Regex::new(pattern).map(Self)
+    }
+}
+
+impl PartialEq for Pattern {
+    fn eq(&self, other: &Self) -> bool {
+        self.as_str() == other.as_str()
+    }
+}
+
+impl Eq for Pattern {}
+
+impl Serialize for Pattern {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for Pattern {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let pattern = String::deserialize(deserializer)?;
+        pattern
+            .parse()
+            .map_err(|e| D::Error::custom(format!("invalid pattern /{}/: {}", pattern, e)))
+    }
+}
+
+/// A step in a field's post-processing chain.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Processor {
+    /// Render Markdown (`**bold**`, lists, ...) as HTML.
+    Markdown,
+    /// Replace raw newlines with `<br>`.
+    NewlinesToBr,
+    /// Remove HTML tags not in `allowed_tags` and escape stray markup.
+    Sanitize,
+    /// Remove quotes wrapped around the whole value.
+    StripQuotes,
+    /// Remove a Markdown code fence wrapped around the whole value.
+    StripCodeFences,
+    /// `漢字[かんじ]` to `<ruby>漢字<rt>かんじ</rt></ruby>`.
+    FuriganaToRuby,
+    /// `<ruby>漢字<rt>かんじ</rt></ruby>` to `漢字[かんじ]`.
+    RubyToFurigana,
+}
+
+/// Post-processor chains, run in order on each field after validation.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PostprocessConfig {
+    /// Chain for fields without their own entry in `fields`.
+    #[serde(default)]
+    pub default: Vec<Processor>,
+
+    /// Chains for specific fields, by field name.
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub fields: HashMap<String, Vec<Processor>>,
+
+    /// Tags kept by `sanitize`. Attributes are always removed.
+    #[serde(default = "default_allowed_tags")]
+    pub allowed_tags: Vec<String>,
+}
+
+impl PostprocessConfig {
+    pub fn chain(&self, field: &str) -> &[Processor] {
+        self.fields.get(field).unwrap_or(&self.default)
+    }
 }
 
 // Default value functions
@@ -54,72 +503,261 @@ fn default_optional_fields() -> bool {
     false
 }
 
+fn default_max_prompt_tokens() -> usize {
+    4096
+}
+
+fn default_next_max_attempts() -> u32 {
+    3
+}
+
+fn default_embedding_model() -> String {
+    "nomic-embed-text".to_string()
+}
+
+fn default_similarity_threshold() -> f32 {
+    0.9
+}
+
+fn default_dedup_action() -> DedupAction {
+    DedupAction::Skip
+}
+
+fn default_check_existing_notes() -> bool {
+    true
+}
+
+fn default_embedding_cache_path() -> String {
+    "storage/embeddings.json".to_string()
+}
+
+fn default_review_rubric() -> String {
+    "- The meaning is correct for the key expression.\n\
+     - Example sentences are natural Japanese and actually use the key expression.\n\
+     - Readings and furigana are correct.\n\
+     - Each field's content matches what the field name asks for."
+        .to_string()
+}
+
+fn default_review_threshold() -> f32 {
+    7.0
+}
+
+fn default_review_action() -> ReviewAction {
+    ReviewAction::Tag
+}
+
+fn default_review_max_attempts() -> u32 {
+    2
+}
+
+fn default_review_queue_path() -> String {
+    "storage/review_queue.json".to_string()
+}
+
+impl Default for ReviewConfig {
+    fn default() -> Self {
+        ReviewConfig {
+            enabled: false,
+            model: None,
+            rubric: default_review_rubric(),
+            threshold: default_review_threshold(),
+            action: default_review_action(),
+            max_attempts: default_review_max_attempts(),
+            queue_path: default_review_queue_path(),
+        }
+    }
+}
+
+fn default_validation_max_attempts() -> u32 {
+    2
+}
+
+fn default_connect_timeout_secs() -> f64 {
+    5.0
+}
+
+fn default_read_timeout_secs() -> f64 {
+    120.0
+}
+
+fn default_total_timeout_secs() -> f64 {
+    600.0
+}
+
+fn default_max_retries() -> u32 {
+    4
+}
+
+fn default_retry_backoff_ms() -> u64 {
+    1000
+}
+
+fn default_max_backoff_ms() -> u64 {
+    16000
+}
+
+fn default_allowed_tags() -> Vec<String> {
+    [
+        "b", "i", "u", "s", "strong", "em", "del", "sub", "sup", "small", "mark", "code", "pre",
+        "br", "hr", "p", "div", "ul", "ol", "li", "ruby", "rt", "rp",
+    ]
+    .iter()
+    .map(|t| t.to_string())
+    .collect()
+}
+
+impl Default for PostprocessConfig {
+    fn default() -> Self {
+        PostprocessConfig {
+            default: Vec::new(),
+            fields: HashMap::new(),
+            allowed_tags: default_allowed_tags(),
+        }
+    }
+}
+
+impl Default for HttpConfig {
+    fn default() -> Self {
+        HttpConfig {
+            connect_timeout_secs: default_connect_timeout_secs(),
+            read_timeout_secs: default_read_timeout_secs(),
+            total_timeout_secs: default_total_timeout_secs(),
+            max_retries: default_max_retries(),
+            retry_backoff_ms: default_retry_backoff_ms(),
+            max_backoff_ms: default_max_backoff_ms(),
+        }
+    }
+}
+
+impl Default for DedupConfig {
+    fn default() -> Self {
+        DedupConfig {
+            enabled: false,
+            embedding_model: default_embedding_model(),
+            threshold: default_similarity_threshold(),
+            action: default_dedup_action(),
+            check_existing_notes: default_check_existing_notes(),
+            cache_path: default_embedding_cache_path(),
+        }
+    }
+}
+
 impl Default for Config {
     fn default() -> Self {
         Config {
             model: default_model(),
             ollama_url: default_ollama_url(),
+            ollama_api: OllamaApi::default(),
             anki_url: default_anki_url(),
+            http: HttpConfig::default(),
             deck: None,
             note_type: default_note_type(),
+            create_missing_deck: false,
+            auto_resolve_names: false,
             fields: Vec::new(),
+            image_field: None,
+            field_map: BTreeMap::new(),
+            static_fields: BTreeMap::new(),
+            siblings: Vec::new(),
             storage_path: default_storage_path(),
             optional_fields: default_optional_fields(),
+            output_format: OutputFormat::default(),
+            duplicates: DuplicateConfig::default(),
+            dedup: DedupConfig::default(),
+            review: ReviewConfig::default(),
+            postprocess: PostprocessConfig::default(),
+            max_prompt_tokens: default_max_prompt_tokens(),
+            next_max_attempts: default_next_max_attempts(),
+            validation: HashMap::new(),
+            validation_max_attempts: default_validation_max_attempts(),
+            options: ModelOptions::default(),
+            keep_alive: None,
+            profiles: HashMap::new(),
+            dry_run: false,
         }
     }
 }
 
 impl Config {
     /// Load config with priority: CLI args > config file > defaults
-    pub fn load_from_file<P: AsRef<Path>>(path: P) -> Result<Self, String> {
+    pub fn load_from_file<P: AsRef<Path>>(path: P) -> Result<Self, AppError> {
         let path = path.as_ref();
         let content = fs::read_to_string(path)
-            .map_err(|e| format!("Failed to read config file: {}", e))?;
+            .map_err(|e| AppError::Config(format!("Failed to read config file: {}", e)))?;
 
         // Determine format by extension
         let config = if path.extension().and_then(|s| s.to_str()) == Some("yaml")
             || path.extension().and_then(|s| s.to_str()) == Some("yml") {
             serde_yaml::from_str(&content)
-                .map_err(|e| format!("Failed to parse YAML config: {}", e))?
+                .map_err(|e| AppError::Config(format!("Failed to parse YAML config: {}", e)))?
         } else {
             serde_json::from_str(&content)
-                .map_err(|e| format!("Failed to parse JSON config: {}", e))?
+                .map_err(|e| AppError::Config(format!("Failed to parse JSON config: {}", e)))?
         };
 
         Ok(config)
     }
 
-    /// Try to load config from default locations, fallback to defaults if not found
-    pub fn load_or_default() -> Self {
-        // Try loading from common config file locations
-        let config_paths = [
-            "config.yaml",
-            "config.yml",
-            "config.json",
-            ".anki_gen.yaml",
-            ".anki_gen.json",
-        ];
-
-        for path in &config_paths {
-            if Path::new(path).exists() {
-                match Self::load_from_file(path) {
-                    Ok(config) => {
-                        eprintln!("✓ Loaded config from: {}", path);
-                        return config;
-                    }
-                    Err(e) => {
-                        eprintln!("⚠ Warning: {}", e);
-                    }
+    /// Try to load config from default locations, fallback to defaults if not found.
+    /// A config file that exists but can't be loaded is an error.
+    pub fn load_or_default() -> Result<Self, AppError> {
+        Self::load_first(CONFIG_PATHS, false)
+    }
+
+    /// `load_or_default` for `check`, which should still run to diagnose a broken
+    /// setup: a config file that can't be loaded is only a warning.
+    pub fn load_or_warn() -> Self {
+        Self::load_first(CONFIG_PATHS, true).unwrap_or_default()
+    }
+
+    /// Load the first of `paths` that exists. If `lenient`, one that can't be loaded
+    /// is skipped with a warning.
+    pub(crate) fn load_first<P: AsRef<Path>>(paths: &[P], lenient: bool) -> Result<Self, AppError> {
+        for path in paths.iter().map(AsRef::as_ref) {
+            if !path.exists() {
+                continue;
+            }
+            match Self::load_from_file(path) {
+                Ok(config) => {
+                    eprintln!("✓ Loaded config from: {}", path.display());
+                    return Ok(config);
                 }
+                Err(e) if lenient => eprintln!("⚠ Warning: {}", e),
+                Err(e) => return Err(e),
             }
         }
 
         // No config file found, use defaults
-        Config::default()
+        Ok(Config::default())
     }
 
     /// Merge CLI overrides into config (CLI args take priority)
-    pub fn merge_cli_overrides(&mut self, cli: &crate::cli::Cli) {
+    pub fn merge_cli_overrides(&mut self, cli: &crate::cli::Cli) -> Result<(), AppError> {
+        // Profile first, so explicit CLI args still win over it
+        if let Some(ref name) = cli.profile {
+            let profile = self.profiles.get(name).cloned().ok_or_else(|| {
+                let mut names: Vec<&String> = self.profiles.keys().collect();
+                names.sort();
+                AppError::Config(format!(
+                    "Profile '{}' not found in config. Available: {}",
+                    name,
+                    names
+                        .iter()
+                        .map(|n| n.as_str())
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                ))
+            })?;
+            if let Some(model) = profile.model {
+                self.model = model;
+            }
+            self.options.merge(&profile.options);
+            if profile.keep_alive.is_some() {
+                self.keep_alive = profile.keep_alive;
+            }
+        }
+
         // Only override if CLI arg was explicitly provided
 
         if let Some(ref model) = cli.model {
@@ -130,6 +768,14 @@ impl Config {
             self.ollama_url = ollama_url.clone();
         }
 
+        if let Some(api) = cli.ollama_api {
+            self.ollama_api = api;
+        }
+
+        if let Some(policy) = cli.duplicates {
+            self.duplicates.policy = policy;
+        }
+
         if let Some(ref anki_url) = cli.anki_url {
             self.anki_url = anki_url.clone();
         }
@@ -142,14 +788,89 @@ impl Config {
             self.note_type = note_type.clone();
         }
 
+        if cli.create_deck {
+            self.create_missing_deck = true;
+        }
+
+        if cli.auto_resolve {
+            self.auto_resolve_names = true;
+        }
+
         if !cli.fields.is_empty() {
             self.fields = cli.fields.clone();
         }
 
+        if let Some(ref image_field) = cli.image_field {
+            self.image_field = Some(image_field.clone());
+        }
+
         // CLI flag always overrides if present (default is false)
         if cli.optional_fields {
             self.optional_fields = true;
         }
+
+        if cli.semantic_dedup {
+            self.dedup.enabled = true;
+        }
+
+        if let Some(max_prompt_tokens) = cli.max_prompt_tokens {
+            self.max_prompt_tokens = max_prompt_tokens;
+        }
+
+        if let Some(threshold) = cli.dedup_threshold {
+            self.dedup.threshold = threshold;
+        }
+
+        if cli.review {
+            self.review.enabled = true;
+        }
+
+        if let Some(ref review_model) = cli.review_model {
+            self.review.model = Some(review_model.clone());
+        }
+
+        if cli.temperature.is_some() {
+            self.options.temperature = cli.temperature;
+        }
+
+        if cli.seed.is_some() {
+            self.options.seed = cli.seed;
+        }
+
+        if cli.num_ctx.is_some() {
+            self.options.num_ctx = cli.num_ctx;
+        }
+
+        if let Some(ref keep_alive) = cli.keep_alive {
+            self.keep_alive = Some(keep_alive.clone());
+        }
+
+        if cli.dry_run {
+            self.dry_run = true;
+        }
+
+        if let Some(format) = cli.output_format {
+            self.output_format = format;
+        }
+
+        Ok(())
+    }
+
+    /// Token budget for `next` prompts: `max_prompt_tokens`, capped so the prompt
+    /// plus the answer still fit in `num_ctx` when it is set.
+    pub fn prompt_token_budget(&self) -> usize {
+        match self.options.num_ctx {
+            Some(num_ctx) => {
+                let reserve = self
+                    .options
+                    .num_predict
+                    .filter(|n| *n > 0)
+                    .map_or(RESPONSE_RESERVE_TOKENS, |n| n as usize);
+                self.max_prompt_tokens
+                    .min((num_ctx as usize).saturating_sub(reserve))
+            }
+            None => self.max_prompt_tokens,
+        }
     }
 
     /// Generate example config files
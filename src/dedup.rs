@@ -0,0 +1,279 @@
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use serde::{Deserialize, Serialize};
+
+use crate::anki_client::{AnkiConnectClient, escape_search};
+use crate::config::{DedupAction, DedupConfig};
+use crate::errors::AppError;
+use crate::model_client::OllamaClient;
+use crate::output::Output;
+use crate::types::CardRequest;
+
+/// Embeddings persisted between runs, keyed by the embedded text.
+#[derive(Serialize, Deserialize, Default)]
+struct EmbeddingCache {
+    model: String,
+    vectors: HashMap<String, Vec<f32>>,
+    /// Vectors were added since the cache was last saved.
+    #[serde(skip)]
+    dirty: bool,
+}
+
+/// The closest existing item to a candidate card.
+pub struct DuplicateMatch {
+    pub existing: String,
+    pub similarity: f32,
+}
+
+/// Which existing notes were compared: deck, note type and key field.
+type NoteScope = (String, String, String);
+
+/// Flags cards whose key field is semantically close to history or existing notes.
+pub struct SemanticDedup {
+    config: DedupConfig,
+    cache_path: PathBuf,
+    cache: Mutex<EmbeddingCache>,
+    existing_notes: Mutex<HashMap<NoteScope, Vec<String>>>,
+    output: Output,
+}
+
+impl SemanticDedup {
+    pub fn new(config: DedupConfig) -> Result<Self, AppError> {
+        let cache_path = PathBuf::from(&config.cache_path);
+        // The cache only saves embedding calls, so a corrupt one is rebuilt rather than fatal
+        let mut cache = Self::read_cache(&cache_path).unwrap_or_else(|e| {
+            eprintln!(
+                "Warning: {} is unreadable ({}), starting with an empty embedding cache",
+                cache_path.display(),
+                e
+            );
+            EmbeddingCache::default()
+        });
+
+        // Vectors from a different model aren't comparable, start over
+        if cache.model != config.embedding_model {
+            cache = EmbeddingCache {
+                model: config.embedding_model.clone(),
+                ..EmbeddingCache::default()
+            };
+        }
+
+        Ok(Self {
+            config,
+            cache_path,
+            cache: Mutex::new(cache),
+            existing_notes: Mutex::new(HashMap::new()),
+            output: Output::default(),
+        })
+    }
+
+    /// Where progress is reported.
+    pub fn with_output(mut self, output: Output) -> Self {
+        self.output = output;
+        self
+    }
+
+    fn read_cache(path: &Path) -> Result<EmbeddingCache, AppError> {
+        if !path.exists() {
+            return Ok(EmbeddingCache::default());
+        }
+        let data = fs::read_to_string(path)?;
+        if data.trim().is_empty() {
+            return Ok(EmbeddingCache::default());
+        }
+        Ok(serde_json::from_str(&data)?)
+    }
+
+    pub fn action(&self) -> DedupAction {
+        self.config.action
+    }
+
+    /// Find the most similar item among history and existing notes, if it is above the
+    /// threshold. Existing notes are compared by their `key_field`, a note field name.
+    pub async fn find_duplicate(
+        &self,
+        model: &OllamaClient,
+        anki: &AnkiConnectClient,
+        req: &CardRequest,
+        key_field: &str,
+        candidate: &str,
+        history: &[String],
+    ) -> Result<Option<DuplicateMatch>, AppError> {
+        let candidate = candidate.trim();
+        if candidate.is_empty() {
+            return Ok(None);
+        }
+
+        let mut pool: Vec<String> = history.to_vec();
+        if self.config.check_existing_notes {
+            pool.extend(self.existing_note_values(anki, req, key_field).await?);
+        }
+        pool.sort();
+        pool.dedup();
+
+        let target = self.embedding(model, candidate).await?;
+        let mut best: Option<DuplicateMatch> = None;
+
+        for item in pool.iter().map(|i| i.trim()).filter(|i| !i.is_empty()) {
+            let similarity = if item == candidate {
+                1.0
+            } else {
+                cosine_similarity(&target, &self.embedding(model, item).await?)
+            };
+
+            if best.as_ref().is_none_or(|b| similarity > b.similarity) {
+                best = Some(DuplicateMatch {
+                    existing: item.to_string(),
+                    similarity,
+                });
+            }
+        }
+
+        self.save_cache()?;
+
+        Ok(best.filter(|b| b.similarity >= self.config.threshold))
+    }
+
+    async fn embedding(&self, model: &OllamaClient, text: &str) -> Result<Vec<f32>, AppError> {
+        if let Some(vector) = self.cache.lock().unwrap().vectors.get(text) {
+            return Ok(vector.clone());
+        }
+
+        let vector = model.embed(&self.config.embedding_model, text).await?;
+        let mut cache = self.cache.lock().unwrap();
+        cache.vectors.insert(text.to_string(), vector.clone());
+        cache.dirty = true;
+        Ok(vector)
+    }
+
+    /// Key field values of notes already in the deck. Fetched once per deck, note type
+    /// and key field, then kept up to date by `note_added`.
+    async fn existing_note_values(
+        &self,
+        anki: &AnkiConnectClient,
+        req: &CardRequest,
+        key_field: &str,
+    ) -> Result<Vec<String>, AppError> {
+        let scope = (req.deck.clone(), req.note_type.clone(), key_field.to_string());
+        if let Some(values) = self.existing_notes.lock().unwrap().get(&scope) {
+            return Ok(values.clone());
+        }
+
+        let query = format!(
+            "deck:\"{}\" note:\"{}\"",
+            escape_search(&req.deck),
+            escape_search(&req.note_type)
+        );
+        let ids = anki.find_notes(&query).await?;
+        let notes = anki.notes_info(&ids).await?;
+        let values: Vec<String> = notes
+            .iter()
+            .filter_map(|n| n.fields.get(key_field))
+            .map(|f| strip_html(&f.value))
+            .filter(|v| !v.is_empty())
+            .collect();
+
+        self.output.info(format_args!(
+            "  Dedup: comparing against {} existing notes in '{}'",
+            values.len(),
+            req.deck
+        ));
+        self.existing_notes
+            .lock()
+            .unwrap()
+            .insert(scope, values.clone());
+        Ok(values)
+    }
+
+    /// Remember a note added during the run, so later cards are compared against it.
+    pub fn note_added(&self, deck: &str, note_type: &str, key_field: &str, value: &str) {
+        let scope = (deck.to_string(), note_type.to_string(), key_field.to_string());
+        let value = strip_html(value);
+        if let Some(values) = self.existing_notes.lock().unwrap().get_mut(&scope)
+            && !value.is_empty()
+        {
+            values.push(value);
+        }
+    }
+
+    /// Save the cache if vectors were added. Written to a temp file and renamed over
+    /// the cache, so an interrupted save doesn't leave a partial file.
+    fn save_cache(&self) -> Result<(), AppError> {
+        let mut cache = self.cache.lock().unwrap();
+        if !cache.dirty {
+            return Ok(());
+        }
+        if let Some(parent) = self.cache_path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let data = serde_json::to_string(&*cache)?;
+        let mut name = self.cache_path.file_name().unwrap_or_default().to_os_string();
+        name.push(format!(".tmp.{}", std::process::id()));
+        let tmp = self.cache_path.with_file_name(name);
+        {
+            let mut file = File::create(&tmp)?;
+            file.write_all(data.as_bytes())?;
+            file.sync_all()?;
+        }
+        fs::rename(&tmp, &self.cache_path)?;
+        cache.dirty = false;
+        Ok(())
+    }
+}
+
+/// Normalise an item for exact repeat checks: drop leading ～/〜, whitespace and case.
+pub fn normalize_key(item: &str) -> String {
+    strip_html(item)
+        .trim_start_matches(['～', '〜', '~'])
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .flat_map(|c| c.to_lowercase())
+        .collect()
+}
+
+/// Find a history item that is the same as `candidate` after normalisation.
+pub fn find_repeat<'a>(candidate: &str, history: &'a [String]) -> Option<&'a String> {
+    let key = normalize_key(candidate);
+    if key.is_empty() {
+        return None;
+    }
+    history.iter().find(|h| normalize_key(h) == key)
+}
+
+fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
+    if a.len() != b.len() {
+        return 0.0;
+    }
+    let dot: f32 = a.iter().zip(b).map(|(x, y)| x * y).sum();
+    let norm_a = a.iter().map(|x| x * x).sum::<f32>().sqrt();
+    let norm_b = b.iter().map(|x| x * x).sum::<f32>().sqrt();
+    if norm_a == 0.0 || norm_b == 0.0 {
+        return 0.0;
+    }
+    dot / (norm_a * norm_b)
+}
+
+/// Drop HTML tags and common entities from a note field value.
+pub fn strip_html(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    let mut in_tag = false;
+    for c in value.chars() {
+        match c {
+            '<' => in_tag = true,
+            '>' if in_tag => in_tag = false,
+            _ if !in_tag => out.push(c),
+            _ => {}
+        }
+    }
+    out.replace("&nbsp;", " ")
+        .replace("&amp;", "&")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .trim()
+        .to_string()
+}
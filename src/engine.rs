@@ -1,32 +1,473 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::time::Instant;
+
+use chrono::{DateTime, Utc};
 use strsim::levenshtein;
 
 use crate::anki_client::AnkiConnectClient;
+use crate::config::{Config, DedupAction, DuplicatePolicy, PostprocessConfig, ReviewAction};
+use crate::dedup::{self, SemanticDedup};
 use crate::errors::AppError;
+use crate::field_map;
+use crate::history::{self, HistoryFilter};
+use crate::image::{self, SourceImage};
+use crate::metrics::CardMetrics;
 use crate::model_client::OllamaClient;
+use crate::notetype::NoteTypeDefinition;
+use crate::output::{Event, Output};
+use crate::postprocess;
 use crate::prompt_builder::PromptBuilder;
+use crate::review::Reviewer;
+use crate::shutdown::Shutdown;
+use crate::static_fields::{self, FieldContext};
+use crate::siblings::{self, Sibling};
 use crate::storage::FileStorage;
-use crate::types::{CardFields, CardRequest};
+use crate::suggest;
+use crate::types::{
+    BatchItem, BatchReport, CardFields, CardRequest, CardResult, ChatMessage, CheckReport, FailedItem,
+    FieldEdit, HistoryEntry, JournalEntry, JournalStatus, NoteAction, Role, RunRecord, SiblingNote,
+    StoredHistory,
+};
+use crate::validation;
 
 const MAX_EDIT_DISTANCE: usize = 2;
+const POSSIBLE_DUPLICATE_TAG: &str = "anki_gen::possible_duplicate";
+const NEEDS_REVIEW_TAG: &str = "anki_gen::needs_review";
 
 pub struct Engine {
     model: OllamaClient,
     anki: AnkiConnectClient,
     storage: FileStorage,
+    config: Config,
+    output: Output,
+    dedup: Option<SemanticDedup>,
+    reviewer: Option<Reviewer>,
+    shutdown: Shutdown,
 }
 
 impl Engine {
-    pub fn new(model: OllamaClient, anki: AnkiConnectClient, storage: FileStorage) -> Self {
+    pub fn new(
+        model: OllamaClient,
+        anki: AnkiConnectClient,
+        storage: FileStorage,
+        config: Config,
+    ) -> Self {
         Self {
             model,
             anki,
             storage,
+            output: Output::new(config.output_format),
+            config,
+            dedup: None,
+            reviewer: None,
+            shutdown: Shutdown::new(),
+        }
+    }
+
+    /// Replace the output sink (e.g. to collect events in memory).
+    #[cfg(test)]
+    pub fn with_output(mut self, output: Output) -> Self {
+        self.dedup = self.dedup.map(|d| d.with_output(output.clone()));
+        self.output = output;
+        self
+    }
+
+    /// Stop batches and regen between items once `shutdown` is requested.
+    pub fn with_shutdown(mut self, shutdown: Shutdown) -> Self {
+        self.shutdown = shutdown;
+        self
+    }
+
+    /// Enable the semantic duplicate check before notes are added.
+    pub fn with_dedup(mut self, dedup: SemanticDedup) -> Self {
+        self.dedup = Some(dedup.with_output(self.output.clone()));
+        self
+    }
+
+    /// Grade each card with a reviewer model before it is added.
+    pub fn with_reviewer(mut self, reviewer: Reviewer) -> Self {
+        self.reviewer = Some(reviewer);
+        self
+    }
+
+    /// Check that Ollama and AnkiConnect are reachable and the model is installed.
+    pub async fn check(&self) -> CheckReport {
+        let model = self.model.model_name().to_string();
+        let (models, ollama_error) = match self.model.ping().await {
+            Ok(models) => (Some(models), None),
+            Err(e) => (None, Some(e.to_string())),
+        };
+        let (anki_version, anki_error) = match self.anki.ping().await {
+            Ok(version) => (Some(version), None),
+            Err(e) => (None, Some(e.to_string())),
+        };
+
+        CheckReport {
+            model_found: models
+                .iter()
+                .flatten()
+                .any(|m| m.starts_with(model.as_str())),
+            model,
+            ollama_ok: models.is_some(),
+            models: models.unwrap_or_default(),
+            ollama_error,
+            anki_ok: anki_version.is_some(),
+            anki_version,
+            anki_error,
+        }
+    }
+
+    /// All field names of a note type, for callers that don't specify fields.
+    pub async fn note_type_fields(&self, note_type: &str) -> Result<Vec<String>, AppError> {
+        self.anki.get_model_field_names(note_type).await
+    }
+
+    /// Create a note type from a definition. Fails if one with that name exists.
+    pub async fn create_note_type(&self, definition: &NoteTypeDefinition) -> Result<(), AppError> {
+        let existing = self.anki.get_model_names().await?;
+        if existing.contains(&definition.name) {
+            return Err(AppError::Anki(format!(
+                "Note type '{}' already exists",
+                definition.name
+            )));
+        }
+        self.anki.create_model(definition).await
+    }
+
+    pub fn history(&self) -> Result<StoredHistory, AppError> {
+        self.storage.load_history()
+    }
+
+    /// Forget `items` so `next` may suggest them again. Returns the names removed.
+    pub fn remove_history(&self, items: &[String]) -> Result<Vec<String>, AppError> {
+        let wanted: Vec<String> = items.iter().map(|i| suggest::normalize(i)).collect();
+        self.update_history(|history| {
+            history::remove(history, |e| wanted.contains(&suggest::normalize(&e.item)))
+        })
+    }
+
+    /// Forget the items matching `filter`, or all of them if it's empty. Returns the
+    /// names removed.
+    pub fn clear_history(&self, filter: &HistoryFilter) -> Result<Vec<String>, AppError> {
+        self.update_history(|history| history::remove(history, |e| filter.matches(e)))
+    }
+
+    /// Merge items from another history (or replace ours). Returns how many were added.
+    pub fn import_history(
+        &self,
+        imported: &StoredHistory,
+        replace: bool,
+    ) -> Result<usize, AppError> {
+        self.update_history(|history| history::merge(history, imported, replace))
+    }
+
+    /// Metadata for the run history: the effective model and options, and what each
+    /// card took.
+    fn run_record(
+        &self,
+        command: &str,
+        started_at: DateTime<Utc>,
+        cards_added: usize,
+        cards: Vec<CardMetrics>,
+    ) -> RunRecord {
+        RunRecord {
+            started_at,
+            command: command.to_string(),
+            model: self.config.model.clone(),
+            options: self.config.options.clone(),
+            keep_alive: self.config.keep_alive.clone(),
+            cards_added,
+            interrupted: self.shutdown.requested(),
+            cards,
+        }
+    }
+
+    /// The value identifying a card: its first field, or the request description.
+    fn key_value(req: &CardRequest, fields: &CardFields) -> String {
+        req.fields
+            .first()
+            .and_then(|key| fields.get(key))
+            .filter(|v| !v.trim().is_empty())
+            .cloned()
+            .unwrap_or_else(|| req.description.clone())
+    }
+
+    /// Add the note, unless this is a dry run. Returns the note id and what was done,
+    /// which for a duplicate depends on the `duplicates` policy.
+    async fn add_note(
+        &self,
+        req: &CardRequest,
+        fields: &CardFields,
+        all_fields: &[String],
+        tags: &[String],
+    ) -> Result<(Option<u64>, NoteAction), AppError> {
+        if self.config.dry_run {
+            self.output.event(&Event::NoteAdded {
+                item: &req.description,
+                note_id: None,
+                dry_run: true,
+            });
+            return Ok((None, NoteAction::Added));
+        }
+
+        let duplicates = &self.config.duplicates;
+        let added = self
+            .anki
+            .add_note(
+                fields,
+                &req.note_type,
+                &req.deck,
+                all_fields,
+                tags,
+                duplicates,
+            )
+            .await;
+        match added {
+            Ok(id) => {
+                if let (Some(dedup), Some(key)) = (&self.dedup, all_fields.first()) {
+                    let value = fields.get(key).map(String::as_str).unwrap_or_default();
+                    dedup.note_added(&req.deck, &req.note_type, key, value);
+                }
+                self.output.event(&Event::NoteAdded {
+                    item: &req.description,
+                    note_id: Some(id),
+                    dry_run: false,
+                });
+                Ok((Some(id), NoteAction::Added))
+            }
+            Err(AppError::Duplicate(reason)) => match duplicates.policy {
+                DuplicatePolicy::Skip => {
+                    self.output
+                        .info(format_args!("  Skipped duplicate: {}", reason));
+                    self.output.event(&Event::Skipped {
+                        item: &req.description,
+                        reason: &reason,
+                    });
+                    Ok((None, NoteAction::Skipped))
+                }
+                DuplicatePolicy::UpdateEmpty => {
+                    self.fill_existing(req, fields, all_fields, reason).await
+                }
+                _ => Err(AppError::Duplicate(reason)),
+            },
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Copy generated values into the empty fields of the duplicate note.
+    async fn fill_existing(
+        &self,
+        req: &CardRequest,
+        fields: &CardFields,
+        all_fields: &[String],
+        reason: String,
+    ) -> Result<(Option<u64>, NoteAction), AppError> {
+        let key = all_fields.first().map(String::as_str).unwrap_or_default();
+        let value = fields.get(key).map(String::as_str).unwrap_or_default();
+        let Some(existing) = self
+            .anki
+            .find_note_by_field(&req.note_type, key, value)
+            .await?
+        else {
+            return Err(AppError::Duplicate(reason));
+        };
+
+        let empty: CardFields = fields
+            .iter()
+            .filter(|(_, v)| !v.trim().is_empty())
+            .filter(|(name, _)| {
+                existing
+                    .fields
+                    .get(name.as_str())
+                    .is_some_and(|f| f.value.trim().is_empty())
+            })
+            .map(|(name, v)| (name.clone(), v.clone()))
+            .collect();
+        if empty.is_empty() {
+            self.output.info(format_args!(
+                "Duplicate of note {} has no empty fields to fill",
+                existing.note_id
+            ));
+        } else {
+            self.anki
+                .update_note_fields(existing.note_id, &empty)
+                .await?;
+            let mut names: Vec<&String> = empty.keys().collect();
+            names.sort();
+            self.output.info(format_args!(
+                "Filled empty fields of duplicate note {}: {}",
+                existing.note_id,
+                names
+                    .iter()
+                    .map(|n| n.as_str())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ));
+        }
+        self.output.event(&Event::NoteUpdated {
+            item: &req.description,
+            note_id: existing.note_id,
+            fields: &empty,
+            dry_run: false,
+        });
+        Ok((Some(existing.note_id), NoteAction::Updated))
+    }
+
+    /// Print the note fields in `order`, and emit them as an event.
+    fn report_fields(&self, item: &str, order: &[String], fields: &CardFields) {
+        self.output.info("Generated fields:");
+        for name in order.iter().filter(|f| fields.contains_key(f.as_str())) {
+            self.output
+                .info(format_args!("  {}: {}", name, fields[name.as_str()]));
+        }
+        self.emit_fields(item, order, fields);
+    }
+
+    /// Emit the fields a note is about to get: the note fields in `order`, after the
+    /// field mapping and static fields are applied.
+    fn emit_fields(&self, item: &str, order: &[String], fields: &CardFields) {
+        let fields: CardFields = fields
+            .iter()
+            .filter(|(name, _)| order.contains(name))
+            .map(|(name, value)| (name.clone(), value.clone()))
+            .collect();
+        self.output.event(&Event::Fields {
+            item,
+            fields: &fields,
+        });
+    }
+
+    /// Apply `change` to the stored history. It works on the latest version on disk, so
+    /// runs in other terminals aren't overwritten. A dry run applies it to a copy that
+    /// isn't saved, so callers can still report what would change.
+    fn update_history<T>(
+        &self,
+        change: impl FnOnce(&mut StoredHistory) -> T,
+    ) -> Result<T, AppError> {
+        if self.config.dry_run {
+            let mut history = self.storage.load_history()?;
+            return Ok(change(&mut history));
+        }
+        self.storage.update(change)
+    }
+
+    /// Append to the batch journal. Like history, it isn't written on a dry run.
+    fn journal(
+        &self,
+        run: DateTime<Utc>,
+        item: &str,
+        status: JournalStatus,
+        note_id: Option<u64>,
+        error: Option<String>,
+    ) -> Result<(), AppError> {
+        if self.config.dry_run {
+            return Ok(());
+        }
+        self.storage.append_journal(&JournalEntry {
+            run,
+            at: Utc::now(),
+            item: item.to_string(),
+            status,
+            note_id,
+            error,
+        })
+    }
+
+    /// Record the run of `generate` or `next`, failed or not, and return its card.
+    fn finish_card(
+        &self,
+        command: &str,
+        started_at: DateTime<Utc>,
+        start: Instant,
+        mut meter: CardMetrics,
+        result: Result<CardResult, AppError>,
+    ) -> Result<CardResult, AppError> {
+        meter.wall_ms = start.elapsed().as_millis() as u64;
+        meter.failed = result.is_err();
+        if result.is_ok() {
+            self.output.info(format_args!("Took {}", meter));
+        }
+
+        let cards_added = usize::from(result.is_ok());
+        let run = self.run_record(command, started_at, cards_added, vec![meter.clone()]);
+        let saved = self.update_history(|history| history.runs.push(run));
+        // The card's own error matters more than one saving the run
+        let mut card = result?;
+        saved?;
+        card.metrics = meter;
+        Ok(card)
+    }
+
+    /// Metadata for an item about to be recorded as used.
+    fn history_entry(
+        &self,
+        command: &str,
+        req: &CardRequest,
+        item: &str,
+        note_id: Option<u64>,
+        topic: Option<&str>,
+    ) -> HistoryEntry {
+        HistoryEntry {
+            item: item.to_string(),
+            added_at: Some(Utc::now()),
+            command: Some(command.to_string()),
+            deck: Some(req.deck.clone()),
+            note_type: Some(req.note_type.clone()),
+            topic: topic.map(String::from),
+            note_id,
+        }
+    }
+
+    /// Compare the card against history and existing notes, by the value of the note
+    /// type's first field. Returns tags to add to the note.
+    async fn check_duplicate(
+        &self,
+        req: &CardRequest,
+        fields: &CardFields,
+        all_fields: &[String],
+        history: &[String],
+    ) -> Result<Vec<String>, AppError> {
+        // Existing notes live in Anki, which a dry run doesn't touch
+        let Some(dedup) = self.dedup.as_ref().filter(|_| !self.config.dry_run) else {
+            return Ok(Vec::new());
+        };
+        let Some(key_field) = all_fields.first() else {
+            return Ok(Vec::new());
+        };
+
+        let candidate = fields
+            .get(key_field)
+            .filter(|v| !v.trim().is_empty())
+            .map(|v| dedup::strip_html(v))
+            .unwrap_or_else(|| Self::key_value(req, fields));
+        let Some(found) = dedup
+            .find_duplicate(&self.model, &self.anki, req, key_field, &candidate, history)
+            .await?
+        else {
+            return Ok(Vec::new());
+        };
+
+        match dedup.action() {
+            DedupAction::Skip => Err(AppError::Duplicate(format!(
+                "'{}' is too similar to existing '{}' (similarity {:.2})",
+                candidate, found.existing, found.similarity
+            ))),
+            DedupAction::Flag => {
+                self.output.info(format_args!(
+                    "  Possible duplicate of '{}' (similarity {:.2}), tagging {}",
+                    found.existing, found.similarity, POSSIBLE_DUPLICATE_TAG
+                ));
+                Ok(vec![POSSIBLE_DUPLICATE_TAG.to_string()])
+            }
         }
     }
 
     /// Remap model output keys to match expected field names using fuzzy matching.
-    fn fix_field_names(fields: CardFields, expected: &[String]) -> CardFields {
+    /// Returns the fields and how many keys were renamed.
+    fn fix_field_names(fields: CardFields, expected: &[String]) -> (CardFields, u32) {
         let mut result = CardFields::new();
+        let mut fixes = 0;
 
         for (key, value) in fields {
             if expected.iter().any(|e| e == &key) {
@@ -45,6 +486,7 @@ impl Engine {
                             key, expected_name, dist
                         );
                         result.insert(expected_name.clone(), value);
+                        fixes += 1;
                     } else {
                         result.insert(key, value);
                     }
@@ -54,7 +496,7 @@ impl Engine {
             }
         }
 
-        result
+        (result, fixes)
     }
 
     fn validate_fields(
@@ -66,7 +508,7 @@ impl Engine {
             // In optional mode, just check that at least one field has content
             let has_content = fields.values().any(|v| !v.trim().is_empty());
             if !has_content {
-                return Err(AppError::Model(
+                return Err(AppError::SchemaViolation(
                     "Model returned no content in any field".into(),
                 ));
             }
@@ -78,7 +520,7 @@ impl Engine {
                 .collect();
 
             if !missing.is_empty() {
-                return Err(AppError::Model(format!(
+                return Err(AppError::SchemaViolation(format!(
                     "Model response missing fields: {}. Got: {}",
                     missing
                         .iter()
@@ -91,152 +533,1052 @@ impl Engine {
 
             let all_empty = expected
                 .iter()
-                .all(|f| fields.get(f.as_str()).map_or(true, |v| v.is_empty()));
+                .all(|f| fields.get(f.as_str()).is_none_or(|v| v.is_empty()));
             if all_empty {
-                return Err(AppError::Model("Model returned all empty fields".into()));
+                return Err(AppError::SchemaViolation(
+                    "Model returned all empty fields".into(),
+                ));
             }
         }
 
         Ok(())
     }
 
-    /// Validate Anki config. Returns all field names of the note type.
-    async fn preflight(&self, req: &CardRequest) -> Result<Vec<String>, AppError> {
-        println!("Checking Anki configuration...");
-        let all_fields = self
+    /// Ask the model for the card, then fix, validate and post-process its fields.
+    /// A card breaking validation rules is regenerated with the violations as feedback.
+    /// Requests, fixes and failures are counted in `meter`.
+    async fn generate_fields(
+        &self,
+        req: &CardRequest,
+        messages: &[ChatMessage],
+        meter: &mut CardMetrics,
+    ) -> Result<CardFields, AppError> {
+        let mut messages = messages.to_vec();
+        let map = &self.config.field_map;
+        let rules = field_map::by_model_key(map, &self.config.validation, &req.fields);
+        let processing = PostprocessConfig {
+            fields: field_map::by_model_key(map, &self.config.postprocess.fields, &req.fields),
+            ..self.config.postprocess.clone()
+        };
+        let max_attempts = self.config.validation_max_attempts.max(1);
+        let mut attempt = 1;
+        loop {
+            let (fields, usage) = self.model.generate(&messages, &req.fields).await?;
+            meter.add_request(&messages, &usage);
+            let (fields, fixes) = Self::fix_field_names(fields, &req.fields);
+            meter.fuzzy_fixes += fixes;
+            if let Err(e) = Self::validate_fields(&fields, &req.fields, req.optional_fields) {
+                meter.validation_failures += 1;
+                return Err(e);
+            }
+
+            let violations = validation::check(
+                &rules,
+                &fields,
+                req.fields.first().map(|f| f.as_str()),
+            );
+            if violations.is_empty() {
+                return Ok(postprocess::apply(&processing, fields));
+            }
+            meter.validation_failures += 1;
+
+            let summary = violations
+                .iter()
+                .map(|v| v.to_string())
+                .collect::<Vec<_>>()
+                .join("; ");
+            if attempt >= max_attempts {
+                return Err(AppError::Validation(format!(
+                    "{} (after {} attempts)",
+                    summary, attempt
+                )));
+            }
+            self.output.info(format_args!(
+                "  Validation failed ({}), retrying ({}/{})",
+                summary,
+                attempt + 1,
+                max_attempts
+            ));
+
+            Self::push_feedback(
+                &mut messages,
+                &fields,
+                format!(
+                    "That card breaks these rules: {}. Generate the card again, fixing them.",
+                    summary
+                ),
+            );
+            meter.retries += 1;
+            attempt += 1;
+        }
+    }
+
+    /// Append the rejected card and what was wrong with it to the transcript.
+    fn push_feedback(messages: &mut Vec<ChatMessage>, fields: &CardFields, feedback: String) {
+        // Sorted keys keep the transcript, and so cassette keys, stable
+        let previous: BTreeMap<&String, &String> = fields.iter().collect();
+        messages.push(ChatMessage::assistant(
+            serde_json::to_string(&previous).unwrap_or_default(),
+        ));
+        messages.push(ChatMessage::user(feedback));
+    }
+
+    /// Have the reviewer grade the card, if review is enabled. Below the threshold the
+    /// card is regenerated, held or tagged, per `review.action`. Returns the card to
+    /// add and any tags for it.
+    async fn review_card(
+        &self,
+        req: &CardRequest,
+        messages: &[ChatMessage],
+        fields: CardFields,
+        meter: &mut CardMetrics,
+    ) -> Result<(CardFields, Vec<String>), AppError> {
+        let Some(reviewer) = &self.reviewer else {
+            return Ok((fields, Vec::new()));
+        };
+
+        let mut messages = messages.to_vec();
+        let mut fields = fields;
+        let mut attempt = 1;
+        loop {
+            let review = reviewer.review(req, &fields).await?;
+            let item = Self::key_value(req, &fields);
+            self.output.event(&Event::Review {
+                item: &item,
+                score: review.score,
+                issues: &review.issues,
+            });
+            if reviewer.passes(&review) {
+                self.output
+                    .info(format_args!("  Review score {:.1}", review.score));
+                return Ok((fields, Vec::new()));
+            }
+
+            let issues = if review.issues.is_empty() {
+                "no issues listed".to_string()
+            } else {
+                review.issues.join("; ")
+            };
+            match reviewer.action() {
+                ReviewAction::Regenerate if attempt < reviewer.max_attempts() => {
+                    self.output.info(format_args!(
+                        "  Review score {:.1} below {:.1} ({}), regenerating ({}/{})",
+                        review.score,
+                        reviewer.threshold(),
+                        issues,
+                        attempt + 1,
+                        reviewer.max_attempts()
+                    ));
+                    Self::push_feedback(
+                        &mut messages,
+                        &fields,
+                        format!(
+                            "A reviewer scored that card {:.1}/10 and found these issues: {}. \
+                             Generate the card again, fixing them.",
+                            review.score, issues
+                        ),
+                    );
+                    meter.retries += 1;
+                    fields = self.generate_fields(req, &messages, meter).await?;
+                    attempt += 1;
+                }
+                ReviewAction::Hold => {
+                    if !self.config.dry_run {
+                        reviewer.hold(req, &item, &fields, &review)?;
+                    }
+                    return Err(AppError::HeldForReview(format!(
+                        "'{}' scored {:.1} ({}), written to {}",
+                        item,
+                        review.score,
+                        issues,
+                        reviewer.queue_path().display()
+                    )));
+                }
+                ReviewAction::Regenerate | ReviewAction::Tag => {
+                    self.output.info(format_args!(
+                        "  Review score {:.1} below {:.1} ({}), tagging {}",
+                        review.score,
+                        reviewer.threshold(),
+                        issues,
+                        NEEDS_REVIEW_TAG
+                    ));
+                    return Ok((fields, vec![NEEDS_REVIEW_TAG.to_string()]));
+                }
+            }
+        }
+    }
+
+    /// Validate Anki config. Returns the request with deck, note type and field names
+    /// as spelled in Anki, and all field names of the note type.
+    async fn preflight(&self, req: &CardRequest) -> Result<(CardRequest, Vec<String>), AppError> {
+        if self.config.dry_run {
+            self.output
+                .info("Dry run: skipping Anki checks, nothing will be added");
+            let generation = self.generation_request(req.clone());
+            self.check_field_config(&req.note_type, &req.fields, &generation.fields)?;
+            return Ok((generation, req.fields.clone()));
+        }
+
+        self.output.info("Checking Anki configuration...");
+        let found = self
             .anki
-            .preflight(&req.deck, &req.note_type, &req.fields)
+            .preflight(
+                &req.deck,
+                &req.note_type,
+                &req.fields,
+                self.config.create_missing_deck,
+                self.config.auto_resolve_names,
+            )
             .await?;
-        println!(
+        let req = CardRequest {
+            deck: found.deck,
+            note_type: found.note_type,
+            fields: found.fields,
+            ..req.clone()
+        };
+        let all_fields = found.all_fields;
+        if let Some(field) = &self.config.image_field
+            && !all_fields.contains(field)
+        {
+            return Err(AppError::FieldMismatch(format!(
+                "Image field '{}' is not a field of note type '{}'{}",
+                field,
+                req.note_type,
+                suggest::did_you_mean(field, &all_fields)
+            )));
+        }
+        let mapped = self.config.field_map.keys().map(|f| (f, "Mapped"));
+        let fixed = self.config.static_fields.keys().map(|f| (f, "Static"));
+        if let Some((field, kind)) = mapped.chain(fixed).find(|(f, _)| !all_fields.contains(f)) {
+            return Err(AppError::FieldMismatch(format!(
+                "{} field '{}' is not a field of note type '{}'{}",
+                kind,
+                field,
+                req.note_type,
+                suggest::did_you_mean(field, &all_fields)
+            )));
+        }
+        self.output.info(format_args!(
             "  Deck: '{}' OK\n  Note type: '{}' OK\n  Fields: {:?} OK\n  Note type has {} total fields: {}",
             req.deck,
             req.note_type,
             req.fields,
             all_fields.len(),
             all_fields.join(", "),
-        );
-        Ok(all_fields)
+        ));
+        self.output.event(&Event::Preflight {
+            deck: &req.deck,
+            note_type: &req.note_type,
+            fields: &req.fields,
+            note_type_fields: &all_fields,
+        });
+        let req = self.generation_request(req);
+        self.check_field_config(&req.note_type, &all_fields, &req.fields)?;
+        if !self.config.field_map.is_empty() {
+            self.output
+                .info(format_args!("  Model keys: {}", req.fields.join(", ")));
+        }
+        Ok((req, all_fields))
     }
 
-    pub async fn generate(&self, req: &CardRequest) -> Result<(), AppError> {
-        let all_fields = self.preflight(req).await?;
-        let prompt = PromptBuilder::build(req);
-        println!("Generating card for: {}", req.description);
+    /// Check that validation rules and post-processing chains name a model key or a
+    /// note field, and that a note field they name can be traced to one model key.
+    fn check_field_config(
+        &self,
+        note_type: &str,
+        note_fields: &[String],
+        keys: &[String],
+    ) -> Result<(), AppError> {
+        let sibling_keys = self
+            .config
+            .siblings
+            .iter()
+            .flat_map(|s| s.fields.values())
+            .flat_map(|source| source.keys());
+        let mut known: Vec<String> = keys.iter().chain(note_fields).cloned().collect();
+        known.extend(sibling_keys);
+        known.sort();
+        known.dedup();
 
-        let fields = self.model.generate(&prompt, &req.fields).await?;
-        let fields = Self::fix_field_names(fields, &req.fields);
-        Self::validate_fields(&fields, &req.fields, req.optional_fields)?;
-        println!("Generated fields: {:?}", fields);
+        let mut names: Vec<(&String, &str)> = self
+            .config
+            .validation
+            .keys()
+            .map(|name| (name, "Validation rules"))
+            .chain(
+                self.config
+                    .postprocess
+                    .fields
+                    .keys()
+                    .map(|name| (name, "Post-processing")),
+            )
+            .collect();
+        names.sort();
 
-        self.anki
-            .add_note(&fields, &req.note_type, &req.deck, &all_fields)
-            .await?;
-        println!("Card added to Anki!");
+        for (name, kind) in names {
+            if !known.contains(name) {
+                return Err(AppError::Config(format!(
+                    "{} for '{}' match no generated key or field of note type '{}'{}",
+                    kind,
+                    name,
+                    note_type,
+                    suggest::did_you_mean(name, &known)
+                )));
+            }
+            match self.config.field_map.get(name) {
+                Some(source) if !keys.contains(name) && source.single_key().is_none() => {
+                    let read = source.keys();
+                    let reason = if read.is_empty() {
+                        "field_map sets it to a constant".to_string()
+                    } else {
+                        format!(
+                            "field_map fills it from a template or concat; set them on the \
+                             keys it reads: {}",
+                            read.join(", ")
+                        )
+                    };
+                    return Err(AppError::Config(format!(
+                        "{} for '{}' can't apply: {}",
+                        kind, name, reason
+                    )));
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
 
-        let mut history = self.storage.load_history()?;
-        history.used_items.push(req.description.clone());
-        self.storage.save_history(&history)?;
+    /// The request with the keys to ask the model for in place of the note fields:
+    /// mapped fields are replaced by the keys they read, and the image field is left
+    /// out since the source images go there.
+    fn generation_request(&self, mut req: CardRequest) -> CardRequest {
+        if let Some(field) = &self.config.image_field {
+            req.fields.retain(|f| f != field);
+        }
+        req.fields
+            .retain(|f| !self.config.static_fields.contains_key(f));
+        req.fields = field_map::generation_keys(&req.fields, &self.config.field_map);
+        req
+    }
 
+    /// Check the sibling note mappings against Anki. Returns the request with the
+    /// generated keys the siblings need added to its fields, and the siblings.
+    async fn preflight_siblings(
+        &self,
+        mut req: CardRequest,
+    ) -> Result<(CardRequest, Vec<Sibling>), AppError> {
+        let mut siblings = Vec::new();
+        for mapping in &self.config.siblings {
+            let deck = mapping.deck.clone().unwrap_or_else(|| req.deck.clone());
+            let names: Vec<String> = mapping.fields.keys().cloned().collect();
+            let keys = mapping.fields.values().cloned();
+            let sibling = if self.config.dry_run {
+                Sibling {
+                    deck,
+                    note_type: mapping.note_type.clone(),
+                    fields: names.iter().cloned().zip(keys).collect(),
+                    all_fields: names,
+                }
+            } else {
+                let found = self
+                    .anki
+                    .preflight(
+                        &deck,
+                        &mapping.note_type,
+                        &names,
+                        self.config.create_missing_deck,
+                        self.config.auto_resolve_names,
+                    )
+                    .await?;
+                Sibling {
+                    deck: found.deck,
+                    note_type: found.note_type,
+                    fields: found.fields.into_iter().zip(keys).collect(),
+                    all_fields: found.all_fields,
+                }
+            };
+            self.output.info(format_args!(
+                "  Sibling: '{}' in deck '{}' (fields: {})",
+                sibling.note_type,
+                sibling.deck,
+                sibling
+                    .fields
+                    .iter()
+                    .map(|(field, _)| field.as_str())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ));
+            siblings.push(sibling);
+        }
+        siblings::extend_fields(&mut req.fields, &siblings);
+        Ok((req, siblings))
+    }
+
+    /// Add the sibling notes of a card whose main note was just added. A sibling that
+    /// can't be added is reported as failed without failing the card; siblings of a
+    /// skipped duplicate are skipped too.
+    async fn add_siblings(
+        &self,
+        siblings: &[Sibling],
+        main: NoteAction,
+        item: &str,
+        fields: &CardFields,
+        tags: &[String],
+    ) -> Vec<SiblingNote> {
+        if main == NoteAction::Skipped {
+            return Vec::new();
+        }
+
+        let mut notes = Vec::new();
+        for sibling in siblings {
+            let req = sibling.request(item);
+            let added = self
+                .add_note(&req, &sibling.project(fields), &sibling.all_fields, tags)
+                .await;
+            let (note_id, action, error) = match added {
+                Ok((note_id, action)) => (note_id, action, None),
+                Err(e) => (None, NoteAction::Failed, Some(e.to_string())),
+            };
+            if let Some(e) = &error {
+                self.output.info(format_args!(
+                    "  Sibling '{}' in '{}' failed: {}",
+                    sibling.note_type, sibling.deck, e
+                ));
+            } else if let (Some(note_id), NoteAction::Added) = (note_id, action) {
+                self.output.info(format_args!(
+                    "  Sibling '{}' added to '{}' (note id {})",
+                    sibling.note_type, sibling.deck, note_id
+                ));
+            }
+            notes.push(SiblingNote {
+                deck: req.deck,
+                note_type: req.note_type,
+                note_id,
+                action,
+                error,
+            });
+        }
+        notes
+    }
+
+    /// Set the static fields, which the model isn't asked for. Commands aren't run on a
+    /// dry run.
+    async fn merge_static_fields(
+        &self,
+        context: &FieldContext<'_>,
+        fields: &mut CardFields,
+    ) -> Result<(), AppError> {
+        if self.config.static_fields.is_empty() {
+            return Ok(());
+        }
+        let values = static_fields::compute(
+            &self.config.static_fields,
+            context,
+            fields,
+            !self.config.dry_run,
+        )
+        .await?;
+        fields.extend(values);
+        Ok(())
+    }
+
+    /// Send the images along with the last user message.
+    fn attach_images(mut messages: Vec<ChatMessage>, images: &[SourceImage]) -> Vec<ChatMessage> {
+        if let Some(message) = messages.iter_mut().rev().find(|m| m.role == Role::User) {
+            message.images = images.iter().map(SourceImage::base64).collect();
+        }
+        messages
+    }
+
+    /// Store the source images in Anki's media folder and reference them from the
+    /// image field, if one is configured.
+    async fn store_images(
+        &self,
+        fields: &mut CardFields,
+        images: &[SourceImage],
+    ) -> Result<(), AppError> {
+        let Some(field) = &self.config.image_field else {
+            return Ok(());
+        };
+        if images.is_empty() {
+            return Ok(());
+        }
+        let mut tags = Vec::new();
+        for image in images {
+            let mut filename = image.media_filename();
+            if !self.config.dry_run {
+                filename = self
+                    .anki
+                    .store_media_file(&filename, &image.base64())
+                    .await?;
+            }
+            tags.push(format!("<img src=\"{}\">", filename));
+        }
+        fields.insert(field.clone(), tags.concat());
         Ok(())
     }
 
-    pub async fn next(&self, req: &CardRequest) -> Result<(), AppError> {
-        let all_fields = self.preflight(req).await?;
+    pub async fn generate(&self, req: &CardRequest) -> Result<CardResult, AppError> {
+        let images = SourceImage::load_all(&req.images)?;
+        self.generate_with_images(req, &images).await
+    }
+
+    /// `generate` with images already in memory, in place of `req.images`.
+    pub async fn generate_with_images(
+        &self,
+        req: &CardRequest,
+        images: &[SourceImage],
+    ) -> Result<CardResult, AppError> {
+        let started_at = Utc::now();
+        let (req, all_fields) = self.preflight(req).await?;
+        let (req, siblings) = self.preflight_siblings(req).await?;
+        let req = &req;
+        let messages = Self::attach_images(PromptBuilder::build(req), images);
+        self.output
+            .info(format_args!("Generating card for: {}", req.description));
+        self.output.event(&Event::GenerationStarted {
+            item: &req.description,
+            index: None,
+            total: None,
+        });
+
+        let start = Instant::now();
+        let mut meter = CardMetrics::new(&req.description, &req.deck);
+        let result = async {
+            let meter = &mut meter;
+            let fields = self.generate_fields(req, &messages, meter).await?;
+            let (mut fields, mut tags) = self
+                .review_card(req, &messages, fields, meter)
+                .await?;
+            field_map::apply(&self.config.field_map, &mut fields);
+            let context = FieldContext {
+                command: "generate",
+                item: &req.description,
+                description: &req.description,
+                deck: &req.deck,
+                note_type: &req.note_type,
+                batch_file: None,
+                columns: &BTreeMap::new(),
+            };
+            self.merge_static_fields(&context, &mut fields).await?;
+            self.report_fields(&req.description, &all_fields, &fields);
+
+            let history = self.storage.load_history()?;
+            tags.extend(
+                self.check_duplicate(req, &fields, &all_fields, &history.used_items)
+                    .await?,
+            );
+            self.store_images(&mut fields, images).await?;
+
+            let (note_id, action) = self.add_note(req, &fields, &all_fields, &tags).await?;
+            if let (Some(note_id), NoteAction::Added) = (note_id, action) {
+                self.output
+                    .info(format_args!("Card added to Anki! (note id {})", note_id));
+            }
+            let entry = self.history_entry("generate", req, &req.description, note_id, None);
+            self.update_history(|history| history.record(entry))?;
+            let sibling_notes = self
+                .add_siblings(&siblings, action, &req.description, &fields, &tags)
+                .await;
+
+            Ok::<CardResult, AppError>(CardResult {
+                item: req.description.clone(),
+                note_id,
+                fields,
+                action,
+                siblings: sibling_notes,
+                metrics: CardMetrics::default(),
+            })
+        }
+        .await;
+        self.finish_card("generate", started_at, start, meter, result)
+    }
+
+    pub async fn next(&self, req: &CardRequest) -> Result<CardResult, AppError> {
+        let started_at = Utc::now();
+        let (req, all_fields) = self.preflight(req).await?;
+        let (req, siblings) = self.preflight_siblings(req).await?;
+        let req = &req;
         let history = self.storage.load_history()?;
-        let prompt = PromptBuilder::build_next(req, &history.used_items);
 
-        println!(
+        self.output.info(format_args!(
             "Generating next card (already have {} items)",
             history.used_items.len()
-        );
+        ));
+        self.output.event(&Event::GenerationStarted {
+            item: &req.description,
+            index: None,
+            total: None,
+        });
 
-        let fields = self.model.generate(&prompt, &req.fields).await?;
-        let fields = Self::fix_field_names(fields, &req.fields);
-        Self::validate_fields(&fields, &req.fields, req.optional_fields)?;
-        println!("Generated fields: {:?}", fields);
+        // The prompt may not list every history item, so repeats are caught here
+        // and regenerated with the rejected item listed as most recent.
+        let mut excluded = history.used_items.clone();
+        let groups = history.item_groups();
+        let max_attempts = self.config.next_max_attempts.max(1);
+        let mut attempt = 1;
+        let start = Instant::now();
+        let mut meter = CardMetrics::new(&req.description, &req.deck);
+        let result = async {
+            let meter = &mut meter;
+            let (fields, messages) = loop {
+                let budget = self.config.prompt_token_budget();
+                let messages = PromptBuilder::build_next(req, &excluded, &groups, budget);
+                let fields = self.generate_fields(req, &messages, meter).await?;
 
-        self.anki
-            .add_note(&fields, &req.note_type, &req.deck, &all_fields)
-            .await?;
-        println!("Card added to Anki!");
+                let item_name = Self::key_value(req, &fields);
+                let Some(repeat) = dedup::find_repeat(&item_name, &history.used_items) else {
+                    break (fields, messages);
+                };
 
-        let item_name = req
-            .fields
-            .first()
-            .and_then(|key| fields.get(key))
-            .cloned()
-            .unwrap_or_else(|| req.description.clone());
+                if attempt >= max_attempts {
+                    return Err(AppError::Duplicate(format!(
+                        "Model kept repeating history items (last: '{}') after {} attempts",
+                        repeat, attempt
+                    )));
+                }
+                self.output.info(format_args!(
+                    "  '{}' was already generated, retrying ({}/{})",
+                    repeat,
+                    attempt + 1,
+                    max_attempts
+                ));
+                excluded.retain(|e| e != repeat);
+                excluded.push(repeat.clone());
+                meter.retries += 1;
+                attempt += 1;
+            };
+            let (mut fields, mut tags) = self
+                .review_card(req, &messages, fields, meter)
+                .await?;
+            field_map::apply(&self.config.field_map, &mut fields);
+            let item = Self::key_value(req, &fields);
+            meter.item = item.clone();
+            let context = FieldContext {
+                command: "next",
+                item: &item,
+                description: &req.description,
+                deck: &req.deck,
+                note_type: &req.note_type,
+                batch_file: None,
+                columns: &BTreeMap::new(),
+            };
+            self.merge_static_fields(&context, &mut fields).await?;
+            self.report_fields(&item, &all_fields, &fields);
 
-        let mut history = history;
-        history.used_items.push(item_name);
-        self.storage.save_history(&history)?;
+            tags.extend(
+                self.check_duplicate(req, &fields, &all_fields, &history.used_items)
+                    .await?,
+            );
 
-        Ok(())
+            let (note_id, action) = self.add_note(req, &fields, &all_fields, &tags).await?;
+            if let (Some(note_id), NoteAction::Added) = (note_id, action) {
+                self.output
+                    .info(format_args!("Card added to Anki! (note id {})", note_id));
+            }
+            let entry = self.history_entry("next", req, &item, note_id, Some(&req.description));
+            self.update_history(|history| history.record(entry))?;
+            let sibling_notes = self
+                .add_siblings(&siblings, action, &req.description, &fields, &tags)
+                .await;
+
+            Ok::<CardResult, AppError>(CardResult {
+                item,
+                note_id,
+                fields,
+                action,
+                siblings: sibling_notes,
+                metrics: CardMetrics::default(),
+            })
+        }
+        .await;
+        self.finish_card("next", started_at, start, meter, result)
+    }
+
+    pub async fn batch(
+        &self,
+        req: &CardRequest,
+        items: &[String],
+    ) -> Result<BatchReport, AppError> {
+        let items: Vec<BatchItem> = items.iter().map(BatchItem::new).collect();
+        self.batch_items(req, &items, None).await
     }
 
-    pub async fn batch(&self, req: &CardRequest, items: &[String]) -> Result<(), AppError> {
-        let all_fields = self.preflight(req).await?;
+    /// `batch` for rows read from `batch_file`, whose other columns static fields can
+    /// refer to.
+    pub async fn batch_items(
+        &self,
+        req: &CardRequest,
+        items: &[BatchItem],
+        batch_file: Option<&Path>,
+    ) -> Result<BatchReport, AppError> {
+        let started_at = Utc::now();
+        let (req, all_fields) = self.preflight(req).await?;
+        let (req, siblings) = self.preflight_siblings(req).await?;
+        let req = &req;
         let mut history = self.storage.load_history()?;
+        let mut metrics = Vec::new();
         let total = items.len();
+        let mut report = BatchReport {
+            total,
+            ..Default::default()
+        };
 
-        let mut succeeded = 0;
-        let mut failed = 0;
-        let mut errors: Vec<(String, String)> = Vec::new();
-
-        for (i, item) in items.iter().enumerate() {
-            println!("[{}/{}] Generating: {}", i + 1, total, item);
+        for (i, row) in items.iter().enumerate() {
+            let item = &row.item;
+            if self.shutdown.requested() {
+                report.interrupted = true;
+                break;
+            }
+            self.output
+                .info(format_args!("[{}/{}] Generating: {}", i + 1, total, item));
+            self.output.event(&Event::GenerationStarted {
+                item,
+                index: Some(i + 1),
+                total: Some(total),
+            });
 
+            let (description, image) = image::parse_item(item);
             let item_req = CardRequest {
-                description: item.clone(),
+                description,
                 fields: req.fields.clone(),
                 note_type: req.note_type.clone(),
                 deck: req.deck.clone(),
                 optional_fields: req.optional_fields,
+                images: image.into_iter().collect(),
             };
 
+            self.journal(started_at, item, JournalStatus::Attempted, None, None)?;
+            let start = Instant::now();
+            let mut meter = CardMetrics::new(item, &item_req.deck);
             let result = async {
-                let prompt = PromptBuilder::build(&item_req);
-                let fields = self.model.generate(&prompt, &req.fields).await?;
-                let fields = Self::fix_field_names(fields, &req.fields);
-                Self::validate_fields(&fields, &req.fields, req.optional_fields)?;
+                let meter = &mut meter;
+                let images = SourceImage::load_all(&item_req.images)?;
+                let messages = Self::attach_images(PromptBuilder::build(&item_req), &images);
+                let fields = self.generate_fields(&item_req, &messages, meter).await?;
+                let (mut fields, mut tags) = self
+                    .review_card(&item_req, &messages, fields, meter)
+                    .await?;
+                field_map::apply(&self.config.field_map, &mut fields);
+                let context = FieldContext {
+                    command: "batch",
+                    item,
+                    description: &item_req.description,
+                    deck: &item_req.deck,
+                    note_type: &item_req.note_type,
+                    batch_file,
+                    columns: &row.columns,
+                };
+                self.merge_static_fields(&context, &mut fields).await?;
+                self.emit_fields(item, &all_fields, &fields);
+                tags.extend(
+                    self.check_duplicate(&item_req, &fields, &all_fields, &history.used_items)
+                        .await?,
+                );
+                self.store_images(&mut fields, &images).await?;
 
-                self.anki
-                    .add_note(&fields, &req.note_type, &req.deck, &all_fields)
+                let (note_id, action) = self
+                    .add_note(&item_req, &fields, &all_fields, &tags)
                     .await?;
+                // Saved right away, so an aborted run still remembers the notes
+                // already added
+                let entry = self.history_entry("batch", req, item, note_id, None);
+                self.update_history(|history| history.record(entry))?;
+                let status = if action == NoteAction::Skipped {
+                    JournalStatus::Skipped
+                } else {
+                    JournalStatus::Succeeded
+                };
+                self.journal(started_at, item, status, note_id, None)?;
+                let sibling_notes = self
+                    .add_siblings(&siblings, action, &item_req.description, &fields, &tags)
+                    .await;
 
-                Ok::<(), AppError>(())
+                Ok::<CardResult, AppError>(CardResult {
+                    item: item.clone(),
+                    note_id,
+                    fields,
+                    action,
+                    siblings: sibling_notes,
+                    metrics: CardMetrics::default(),
+                })
             }
             .await;
+            meter.wall_ms = start.elapsed().as_millis() as u64;
+            meter.failed = result.is_err();
+            metrics.push(meter.clone());
 
             match result {
-                Ok(_) => {
-                    println!("  ✓ Added to Anki");
+                Ok(mut card) => {
+                    card.metrics = meter;
+                    if card.action == NoteAction::Skipped {
+                        self.output.info("  - Skipped duplicate");
+                        report.skipped += 1;
+                    } else {
+                        if self.config.dry_run {
+                            self.output.info("  ✓ Generated (dry run)");
+                        } else if card.action == NoteAction::Updated {
+                            self.output.info("  ✓ Updated existing note");
+                        } else {
+                            self.output.info("  ✓ Added to Anki");
+                        }
+                        self.output.info(format_args!("    {}", card.metrics));
+                        report.succeeded += 1;
+                    }
                     history.used_items.push(item.clone());
-                    succeeded += 1;
+                    report.cards.push(card);
                 }
                 Err(e) => {
-                    println!("  ✗ Failed: {}", e);
-                    errors.push((item.clone(), e.to_string()));
-                    failed += 1;
+                    self.journal(
+                        started_at,
+                        item,
+                        JournalStatus::Failed,
+                        None,
+                        Some(e.to_string()),
+                    )?;
+                    self.output.info(format_args!("  ✗ Failed: {}", e));
+                    self.output.event(&Event::Error {
+                        item: Some(item),
+                        message: e.to_string(),
+                        code: e.exit_code(),
+                        hint: e.hint(),
+                    });
+                    report.failures.push(FailedItem {
+                        item: item.clone(),
+                        error: e.to_string(),
+                    });
+                    report.failed += 1;
                 }
             }
         }
 
-        self.storage.save_history(&history)?;
+        report.metrics = metrics.iter().collect();
+        let run = self.run_record("batch", started_at, report.succeeded, metrics);
+        self.update_history(|history| history.runs.push(run))?;
 
-        println!("\nBatch complete: {} succeeded, {} failed out of {}", succeeded, failed, total);
+        if report.interrupted {
+            let done = report.succeeded + report.skipped + report.failed;
+            self.output.info(format_args!(
+                "\nBatch interrupted: {} succeeded, {} skipped, {} failed, {} not started out of {}",
+                report.succeeded,
+                report.skipped,
+                report.failed,
+                total - done,
+                total
+            ));
+        } else {
+            self.output.info(format_args!(
+                "\nBatch complete: {} succeeded, {} skipped, {} failed out of {}",
+                report.succeeded, report.skipped, report.failed, total
+            ));
+        }
+        if report.metrics.cards > 0 {
+            self.output.info(format_args!("Metrics: {}", report.metrics));
+        }
 
-        if !errors.is_empty() {
-            println!("\nFailed items:");
-            for (item, error) in &errors {
-                println!("  - {}: {}", item, error);
+        if !report.failures.is_empty() {
+            self.output.info("\nFailed items:");
+            for failure in &report.failures {
+                self.output
+                    .info(format_args!("  - {}: {}", failure.item, failure.error));
             }
         }
 
-        if succeeded == 0 {
-            Err(AppError::Model("All batch items failed".into()))
+        self.output.event(&Event::BatchSummary {
+            succeeded: report.succeeded,
+            skipped: report.skipped,
+            failed: report.failed,
+            total,
+            interrupted: report.interrupted,
+            failures: &report.failures,
+            metrics: &report.metrics,
+        });
+
+        if report.succeeded == 0 && report.skipped == 0 && !report.interrupted {
+            Err(AppError::BatchFailed(Box::new(report)))
         } else {
-            Ok(())
+            Ok(report)
+        }
+    }
+
+    /// Regenerate `fields` of the notes matching `target` (a note id or an Anki search
+    /// query), with the other fields as fixed context. The previous values are kept
+    /// in the edit history so `revert` can restore them.
+    pub async fn regen(
+        &self,
+        target: &str,
+        fields: &[String],
+    ) -> Result<Vec<CardResult>, AppError> {
+        let started_at = Utc::now();
+        let ids = match target.trim().parse::<u64>() {
+            Ok(id) => vec![id],
+            Err(_) => self.anki.find_notes(target).await?,
+        };
+        if ids.is_empty() {
+            return Err(AppError::NotFound(format!("No notes match '{}'", target)));
         }
+
+        let notes = self.anki.notes_info(&ids).await?;
+        if let Some(missing) = ids
+            .iter()
+            .find(|id| !notes.iter().any(|n| n.note_id == **id))
+        {
+            return Err(AppError::NotFound(format!("Note {} not found", missing)));
+        }
+        // Check every note before changing any of them
+        for note in &notes {
+            let names = note.field_names();
+            if let Some(unknown) = fields.iter().find(|f| !names.contains(f)) {
+                return Err(AppError::FieldMismatch(format!(
+                    "Note {} ({}) has no field '{}'. Available: {}",
+                    note.note_id,
+                    note.model_name,
+                    unknown,
+                    names.join(", ")
+                )));
+            }
+        }
+
+        let mut edits = Vec::new();
+        let mut results = Vec::new();
+        let mut metrics = Vec::new();
+        let total = notes.len();
+        let mut result = Ok(());
+        for (i, note) in notes.iter().enumerate() {
+            if self.shutdown.requested() {
+                self.output.info(format_args!(
+                    "Interrupted: {} of {} notes regenerated",
+                    i, total
+                ));
+                break;
+            }
+            let names = note.field_names();
+            let value = |name: &String| note.fields[name].value.clone();
+            let req = CardRequest {
+                description: names
+                    .first()
+                    .map(|key| dedup::strip_html(&value(key)))
+                    .unwrap_or_default(),
+                fields: fields.to_vec(),
+                note_type: note.model_name.clone(),
+                deck: String::new(),
+                optional_fields: false,
+                images: Vec::new(),
+            };
+            let fixed: Vec<(String, String)> = names
+                .iter()
+                .filter(|name| !fields.contains(name))
+                .map(|name| (name.clone(), value(name)))
+                .filter(|(_, v)| !v.trim().is_empty())
+                .collect();
+            let previous: CardFields = fields.iter().map(|f| (f.clone(), value(f))).collect();
+
+            self.output.info(format_args!(
+                "[{}/{}] Regenerating {} of note {} ({})",
+                i + 1,
+                total,
+                fields.join(", "),
+                note.note_id,
+                req.description
+            ));
+            self.output.event(&Event::GenerationStarted {
+                item: &req.description,
+                index: Some(i + 1),
+                total: Some(total),
+            });
+
+            let messages = PromptBuilder::build_regen(&req, &fixed, &previous);
+            let start = Instant::now();
+            let mut meter = CardMetrics::new(&req.description, "");
+            let generated = self.generate_fields(&req, &messages, &mut meter).await;
+            meter.wall_ms = start.elapsed().as_millis() as u64;
+            meter.failed = generated.is_err();
+            metrics.push(meter.clone());
+            let new = match generated {
+                Ok(new) => new,
+                Err(e) => {
+                    result = Err(e);
+                    break;
+                }
+            };
+            self.report_fields(&req.description, &req.fields, &new);
+
+            if !self.config.dry_run {
+                if let Err(e) = self.anki.update_note_fields(note.note_id, &new).await {
+                    result = Err(e);
+                    break;
+                }
+                edits.push(FieldEdit {
+                    edited_at: Utc::now(),
+                    note_id: note.note_id,
+                    previous,
+                    new: new.clone(),
+                    reverted: false,
+                });
+                self.output
+                    .info(format_args!("Note {} updated", note.note_id));
+            }
+            self.output.event(&Event::NoteUpdated {
+                item: &req.description,
+                note_id: note.note_id,
+                fields: &new,
+                dry_run: self.config.dry_run,
+            });
+
+            results.push(CardResult {
+                item: req.description,
+                note_id: Some(note.note_id),
+                fields: new,
+                action: NoteAction::Updated,
+                siblings: Vec::new(),
+                metrics: meter,
+            });
+        }
+
+        // Save edits made so far even if a later note failed, so they can be reverted
+        let run = self.run_record("regen", started_at, 0, metrics);
+        self.update_history(|history| {
+            history.edits.extend(edits);
+            history.runs.push(run);
+        })?;
+        result.map(|_| results)
+    }
+
+    /// Restore the fields overwritten by the latest not yet reverted `regen` of a note.
+    pub async fn revert(&self, note_id: u64) -> Result<FieldEdit, AppError> {
+        let history = self.storage.load_history()?;
+        let Some(edit) = history
+            .edits
+            .iter()
+            .rev()
+            .find(|e| e.note_id == note_id && !e.reverted)
+        else {
+            return Err(AppError::NotFound(format!(
+                "No regen to revert for note {}",
+                note_id
+            )));
+        };
+
+        if !self.config.dry_run {
+            self.anki
+                .update_note_fields(note_id, &edit.previous)
+                .await?;
+        }
+        self.output.event(&Event::NoteUpdated {
+            item: &note_id.to_string(),
+            note_id,
+            fields: &edit.previous,
+            dry_run: self.config.dry_run,
+        });
+        self.update_history(|history| {
+            if let Some(stored) = history
+                .edits
+                .iter_mut()
+                .find(|e| e.note_id == note_id && e.edited_at == edit.edited_at)
+            {
+                stored.reverted = true;
+            }
+        })?;
+        Ok(edit.clone())
     }
 }
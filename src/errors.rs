@@ -1,19 +1,186 @@
 use thiserror::Error;
 
+use crate::types::BatchReport;
+
 #[derive(Error, Debug)]
 pub enum AppError {
     #[error("Network error: {0}")]
     Network(#[from] reqwest::Error),
 
+    #[error("Could not connect to {service} at {url} (connection refused)")]
+    ConnectionRefused { service: &'static str, url: String },
+
+    #[error("Request to {service} at {url} timed out")]
+    Timeout { service: &'static str, url: String },
+
     #[error("JSON parse error: {0}")]
     Parse(#[from] serde_json::Error),
 
+    /// The model's answer wasn't the JSON it was asked for.
+    #[error("Model output is not valid JSON: {error}")]
+    InvalidJson { error: String, raw: String },
+
+    #[error("Configuration error: {0}")]
+    Config(String),
+
     #[error("AnkiConnect error: {0}")]
     Anki(String),
 
+    #[error("{0}")]
+    DeckNotFound(String),
+
+    #[error("{0}")]
+    NoteTypeNotFound(String),
+
+    #[error("{0}")]
+    FieldMismatch(String),
+
+    #[error("Model '{0}' not found in Ollama")]
+    ModelNotFound(String),
+
+    #[error("Image error: {0}")]
+    Image(String),
+
+    /// No note, or no stored edit, matches what the user asked for.
+    #[error("{0}")]
+    NotFound(String),
+
+    #[error("Static field error: {0}")]
+    StaticField(String),
+
     #[error("Storage error: {0}")]
     Storage(#[from] std::io::Error),
 
     #[error("Model error: {0}")]
     Model(String),
+
+    /// No batch item succeeded. Carries the report, so callers can still say why.
+    #[error("All batch items failed")]
+    BatchFailed(Box<BatchReport>),
+
+    /// The model's JSON lacks required fields or content.
+    #[error("Schema violation: {0}")]
+    SchemaViolation(String),
+
+    #[error("Validation failed: {0}")]
+    Validation(String),
+
+    #[error("Held for review: {0}")]
+    HeldForReview(String),
+
+    #[error("Duplicate: {0}")]
+    Duplicate(String),
+}
+
+impl AppError {
+    /// Classify a failed HTTP request, so timeouts and refused connections say which
+    /// service was unreachable.
+    pub fn request(service: &'static str, url: &str, error: reqwest::Error) -> Self {
+        let url = url.to_string();
+        if error.is_timeout() {
+            AppError::Timeout { service, url }
+        } else if error.is_connect() {
+            AppError::ConnectionRefused { service, url }
+        } else {
+            AppError::Network(error)
+        }
+    }
+
+    /// Process exit code, distinct per kind of failure so wrapper scripts can react.
+    /// 2 is left to clap for usage errors.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            AppError::Network(_)
+            | AppError::Parse(_)
+            | AppError::Anki(_)
+            | AppError::Storage(_)
+            | AppError::StaticField(_)
+            | AppError::Model(_)
+            | AppError::BatchFailed(_) => 1,
+            AppError::Config(_) => 3,
+            AppError::ConnectionRefused { .. } => 4,
+            AppError::Timeout { .. } => 5,
+            AppError::DeckNotFound(_) => 10,
+            AppError::NoteTypeNotFound(_) => 11,
+            AppError::FieldMismatch(_) => 12,
+            AppError::ModelNotFound(_) => 13,
+            AppError::Image(_) => 14,
+            AppError::NotFound(_) => 15,
+            AppError::Duplicate(_) => 20,
+            AppError::SchemaViolation(_) => 21,
+            AppError::InvalidJson { .. } => 22,
+            AppError::Validation(_) => 23,
+            AppError::HeldForReview(_) => 24,
+        }
+    }
+
+    /// What the user can do about it, if there's something specific.
+    pub fn hint(&self) -> Option<String> {
+        let hint = match self {
+            AppError::ConnectionRefused { service: "AnkiConnect", .. } => {
+                "Start Anki and check that the AnkiConnect add-on is installed, or set --anki-url."
+                    .to_string()
+            }
+            AppError::ConnectionRefused { .. } => {
+                "Start Ollama with `ollama serve`, or set --ollama-url.".to_string()
+            }
+            AppError::Timeout { .. } => {
+                "The service is busy or the model is slow to load; try again or use a smaller model."
+                    .to_string()
+            }
+            AppError::InvalidJson { .. } => {
+                "Try a lower temperature, or a model that supports structured output.".to_string()
+            }
+            AppError::Config(_) => {
+                "Run `anki_gen config` to print an example configuration.".to_string()
+            }
+            AppError::DeckNotFound(_) => {
+                "Check the deck name, or pass --create-deck to create it.".to_string()
+            }
+            AppError::NoteTypeNotFound(_) => {
+                "Check the note type name, or create it with `anki_gen notetype create`."
+                    .to_string()
+            }
+            AppError::FieldMismatch(_) => {
+                "--fields must match the note type's field names; omit it to use them all."
+                    .to_string()
+            }
+            AppError::ModelNotFound(model) => format!(
+                "Pull it with `ollama pull {}`, or choose another with --model.",
+                model
+            ),
+            AppError::Image(_) => format!(
+                "Check the path; supported formats are {}.",
+                crate::image::EXTENSIONS.join(", ")
+            ),
+            AppError::NotFound(_) => {
+                "Check the note id or search query; `revert` only restores notes changed by `regen`."
+                    .to_string()
+            }
+            AppError::SchemaViolation(_) => {
+                "Try --optional-fields, or a larger model.".to_string()
+            }
+            AppError::Validation(_) => {
+                "Loosen the `validation` rules or raise `validation_max_attempts`.".to_string()
+            }
+            AppError::HeldForReview(_) => {
+                "Check the review queue (`review.queue_path`).".to_string()
+            }
+            AppError::StaticField(_) => {
+                "Commands in `static_fields` run with `sh -c`; try the command in a shell."
+                    .to_string()
+            }
+            AppError::Duplicate(_) => {
+                "Set `duplicates.policy` (or --duplicates) to skip, allow or update_empty."
+                    .to_string()
+            }
+            AppError::Network(_)
+            | AppError::Parse(_)
+            | AppError::Anki(_)
+            | AppError::Storage(_)
+            | AppError::Model(_)
+            | AppError::BatchFailed(_) => return None,
+        };
+        Some(hint)
+    }
 }
@@ -0,0 +1,182 @@
+//! Mapping from the keys the model generates to note fields. A field can take a key,
+//! a template of keys, a constant, or several keys joined, so the model can be asked
+//! for clear keys like `example_sentence_with_furigana` instead of `SentFurigana`.
+
+use std::collections::{BTreeMap, HashMap};
+
+use serde::{Deserialize, Serialize};
+
+use crate::types::CardFields;
+
+/// Where a note field's value comes from.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum FieldSource {
+    /// A generated key, or a template like `{reading} — {pos}` if it contains `{`.
+    Key(String),
+    /// Fixed text; the model isn't asked for anything.
+    Constant { constant: String },
+    /// The non-empty values of several keys, joined.
+    Concat {
+        concat: Vec<String>,
+        #[serde(default = "default_separator")]
+        separator: String,
+    },
+}
+
+fn default_separator() -> String {
+    "<br>".to_string()
+}
+
+/// A piece of a template.
+enum Segment<'a> {
+    Text(&'a str),
+    Key(&'a str),
+}
+
+/// Split a template into text and `{key}` placeholders. `{{` and `}}` are literal
+/// braces; an unclosed `{` is kept as text.
+fn segments(template: &str) -> Vec<Segment<'_>> {
+    let mut segments = Vec::new();
+    let mut rest = template;
+    while let Some(start) = rest.find(['{', '}']) {
+        if start > 0 {
+            segments.push(Segment::Text(&rest[..start]));
+        }
+        let after = &rest[start + 1..];
+        if rest[start..].starts_with("{{") || rest[start..].starts_with("}}") {
+            segments.push(Segment::Text(&rest[start..start + 1]));
+            rest = &after[1..];
+        } else if rest[start..].starts_with('{')
+            && let Some(end) = after.find('}')
+        {
+            segments.push(Segment::Key(after[..end].trim()));
+            rest = &after[end + 1..];
+        } else {
+            segments.push(Segment::Text(&rest[start..start + 1]));
+            rest = after;
+        }
+    }
+    if !rest.is_empty() {
+        segments.push(Segment::Text(rest));
+    }
+    segments
+}
+
+/// Replace the `{key}` placeholders of a template.
+pub fn fill(template: &str, mut value: impl FnMut(&str) -> String) -> String {
+    segments(template)
+        .into_iter()
+        .map(|s| match s {
+            Segment::Text(text) => text.to_string(),
+            Segment::Key(key) => value(key),
+        })
+        .collect()
+}
+
+impl FieldSource {
+    /// The key this source copies as is, if it is a plain key.
+    pub fn single_key(&self) -> Option<&str> {
+        match self {
+            FieldSource::Key(key) if !key.contains('{') => Some(key),
+            _ => None,
+        }
+    }
+
+    /// The generated keys this source reads.
+    pub fn keys(&self) -> Vec<String> {
+        match self {
+            FieldSource::Key(key) if !key.contains('{') => vec![key.clone()],
+            FieldSource::Key(template) => segments(template)
+                .into_iter()
+                .filter_map(|s| match s {
+                    Segment::Key(key) => Some(key.to_string()),
+                    Segment::Text(_) => None,
+                })
+                .collect(),
+            FieldSource::Constant { .. } => Vec::new(),
+            FieldSource::Concat { concat, .. } => concat.clone(),
+        }
+    }
+
+    /// The field value for a generated card. A template whose keys are all empty
+    /// renders as empty, rather than as its separators.
+    pub fn render(&self, card: &CardFields) -> String {
+        let value = |key: &str| card.get(key).map(|v| v.trim()).unwrap_or_default();
+        match self {
+            FieldSource::Key(key) if !key.contains('{') => value(key).to_string(),
+            FieldSource::Key(template) => {
+                if self.keys().iter().all(|key| value(key).is_empty()) {
+                    return String::new();
+                }
+                fill(template, |key| value(key).to_string())
+                    .trim()
+                    .to_string()
+            }
+            FieldSource::Constant { constant } => constant.clone(),
+            FieldSource::Concat { concat, separator } => concat
+                .iter()
+                .map(|key| value(key))
+                .filter(|v| !v.is_empty())
+                .collect::<Vec<_>>()
+                .join(separator),
+        }
+    }
+}
+
+/// The keys to ask the model for: unmapped fields as they are, and each mapped field
+/// replaced by the keys it reads, in field order. Mapped fields that aren't listed
+/// come last.
+pub fn generation_keys(fields: &[String], map: &BTreeMap<String, FieldSource>) -> Vec<String> {
+    let mut keys: Vec<String> = Vec::new();
+    let mut push = |key: String| {
+        if !keys.contains(&key) {
+            keys.push(key);
+        }
+    };
+    for field in fields {
+        match map.get(field) {
+            Some(source) => source.keys().into_iter().for_each(&mut push),
+            None => push(field.clone()),
+        }
+    }
+    for (field, source) in map {
+        if !fields.contains(field) {
+            source.keys().into_iter().for_each(&mut push);
+        }
+    }
+    keys
+}
+
+/// Fill the mapped fields from the generated keys.
+pub fn apply(map: &BTreeMap<String, FieldSource>, card: &mut CardFields) {
+    let mapped: Vec<(String, String)> = map
+        .iter()
+        .map(|(field, source)| (field.clone(), source.render(card)))
+        .collect();
+    card.extend(mapped);
+}
+
+/// Move per-field config (validation rules, post-processing) written against a note
+/// field that isn't one of `keys` to the key the field is mapped from, so it applies
+/// to the model's output. Fields mapped from a template, several keys or a constant
+/// keep their name. An entry for the key itself wins over one for its field.
+pub fn by_model_key<T: Clone>(
+    map: &BTreeMap<String, FieldSource>,
+    config: &HashMap<String, T>,
+    keys: &[String],
+) -> HashMap<String, T> {
+    let mut resolved = config.clone();
+    for (name, value) in config {
+        if keys.contains(name) {
+            continue;
+        }
+        if let Some(key) = map.get(name).and_then(FieldSource::single_key) {
+            resolved.remove(name);
+            resolved
+                .entry(key.to_string())
+                .or_insert_with(|| value.clone());
+        }
+    }
+    resolved
+}
@@ -0,0 +1,148 @@
+//! Listing, filtering and editing used items for the `history` command.
+
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::Path;
+
+use chrono::{Local, NaiveDate};
+
+use crate::errors::AppError;
+use crate::suggest::normalize;
+use crate::types::{HistoryEntry, StoredHistory};
+
+/// Which items a `history` command applies to. An empty filter matches everything.
+#[derive(Debug, Clone, Default)]
+pub struct HistoryFilter {
+    pub deck: Option<String>,
+    pub topic: Option<String>,
+    pub since: Option<NaiveDate>,
+    pub until: Option<NaiveDate>,
+}
+
+impl HistoryFilter {
+    /// Items recorded without metadata only match an empty filter.
+    pub fn matches(&self, entry: &HistoryEntry) -> bool {
+        if let Some(deck) = &self.deck {
+            let Some(entry_deck) = &entry.deck else {
+                return false;
+            };
+            let (wanted, actual) = (normalize(deck), normalize(entry_deck));
+            // A parent deck includes its subdecks
+            if actual != wanted && !actual.starts_with(&format!("{}::", wanted)) {
+                return false;
+            }
+        }
+        if let Some(topic) = &self.topic {
+            let topic = normalize(topic);
+            if !entry
+                .topic
+                .as_deref()
+                .is_some_and(|t| normalize(t).contains(&topic))
+            {
+                return false;
+            }
+        }
+        if self.since.is_some() || self.until.is_some() {
+            let Some(added_at) = entry.added_at else {
+                return false;
+            };
+            let date = added_at.with_timezone(&Local).date_naive();
+            if self.since.is_some_and(|since| date < since)
+                || self.until.is_some_and(|until| date > until)
+            {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+/// Every used item, oldest first, with the metadata recorded for it.
+pub fn items(history: &StoredHistory) -> Vec<HistoryEntry> {
+    // The latest entry wins for an item generated more than once
+    let latest: HashMap<&str, &HistoryEntry> = history
+        .entries
+        .iter()
+        .map(|e| (e.item.as_str(), e))
+        .collect();
+    history
+        .used_items
+        .iter()
+        .map(|item| match latest.get(item.as_str()) {
+            Some(entry) => (*entry).clone(),
+            None => HistoryEntry {
+                item: item.clone(),
+                ..Default::default()
+            },
+        })
+        .collect()
+}
+
+/// Items whose name contains `query`, ignoring case and character width.
+pub fn search(items: Vec<HistoryEntry>, query: &str) -> Vec<HistoryEntry> {
+    let query = normalize(query);
+    items
+        .into_iter()
+        .filter(|e| normalize(&e.item).contains(&query))
+        .collect()
+}
+
+/// Remove the items `pick` selects, with their metadata. Returns the removed names.
+pub fn remove(
+    history: &mut StoredHistory,
+    mut pick: impl FnMut(&HistoryEntry) -> bool,
+) -> Vec<String> {
+    let mut removed: Vec<String> = Vec::new();
+    for entry in items(history) {
+        if pick(&entry) && !removed.contains(&entry.item) {
+            removed.push(entry.item);
+        }
+    }
+    history.used_items.retain(|i| !removed.contains(i));
+    history.entries.retain(|e| !removed.contains(&e.item));
+    removed
+}
+
+/// Read items to import: a history file written by `history export`, or a text file
+/// with one item per line.
+pub fn read_import(path: &Path) -> Result<StoredHistory, AppError> {
+    let content = fs::read_to_string(path).map_err(|e| {
+        AppError::Config(format!("Failed to read {}: {}", path.display(), e))
+    })?;
+    if content.trim_start().starts_with('{') {
+        return serde_json::from_str(&content)
+            .map_err(|e| AppError::Config(format!("Failed to parse history file: {}", e)));
+    }
+    Ok(StoredHistory {
+        used_items: content
+            .lines()
+            .map(str::trim)
+            .filter(|l| !l.is_empty())
+            .map(String::from)
+            .collect(),
+        ..Default::default()
+    })
+}
+
+/// Add the imported items that aren't used yet, or replace all items. Runs and regen
+/// edits are left alone. Returns the number of items added.
+pub fn merge(history: &mut StoredHistory, imported: &StoredHistory, replace: bool) -> usize {
+    if replace {
+        history.used_items.clear();
+        history.entries.clear();
+    }
+    let mut known: HashSet<String> = history.used_items.iter().map(|i| normalize(i)).collect();
+    let mut added = 0;
+    for entry in items(imported) {
+        if !known.insert(normalize(&entry.item)) {
+            continue;
+        }
+        added += 1;
+        if entry.added_at.is_some() {
+            history.record(entry);
+        } else {
+            history.used_items.push(entry.item);
+        }
+    }
+    added
+}
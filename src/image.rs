@@ -0,0 +1,114 @@
+//! Images sent to multimodal models, and stored in Anki's media folder.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use base64::Engine as _;
+use base64::engine::general_purpose::STANDARD;
+use sha2::{Digest, Sha256};
+
+use crate::errors::AppError;
+
+/// Formats Ollama's vision models and Anki both handle.
+pub const EXTENSIONS: &[&str] = &["png", "jpg", "jpeg", "gif", "webp"];
+
+/// Separates an image path from its description in a batch item.
+const ITEM_SEPARATOR: char = '|';
+
+/// An image read from disk or sent in a request.
+pub struct SourceImage {
+    extension: String,
+    data: Vec<u8>,
+}
+
+impl SourceImage {
+    pub fn load(path: &Path) -> Result<Self, AppError> {
+        if !is_image_path(path) {
+            return Err(AppError::Image(format!(
+                "'{}' is not a supported image",
+                path.display()
+            )));
+        }
+        let data = fs::read(path).map_err(|e| {
+            AppError::Image(format!("Could not read '{}': {}", path.display(), e))
+        })?;
+        let extension = path
+            .extension()
+            .and_then(|e| e.to_str())
+            .unwrap_or("png")
+            .to_lowercase();
+        Ok(Self { extension, data })
+    }
+
+    pub fn load_all(paths: &[PathBuf]) -> Result<Vec<Self>, AppError> {
+        paths.iter().map(|p| Self::load(p)).collect()
+    }
+
+    /// An image sent base64-encoded. The format is taken from its content.
+    pub fn decode(base64: &str) -> Result<Self, AppError> {
+        let data = STANDARD
+            .decode(base64.trim())
+            .map_err(|e| AppError::Image(format!("Image is not valid base64: {}", e)))?;
+        let extension = sniff_extension(&data).ok_or_else(|| {
+            AppError::Image("Image data is not a supported image".to_string())
+        })?;
+        Ok(Self {
+            extension: extension.to_string(),
+            data,
+        })
+    }
+
+    pub fn base64(&self) -> String {
+        STANDARD.encode(&self.data)
+    }
+
+    /// Named after the content, so storing the same image twice keeps one file.
+    pub fn media_filename(&self) -> String {
+        let hash: String = Sha256::digest(&self.data)
+            .iter()
+            .take(8)
+            .map(|b| format!("{:02x}", b))
+            .collect();
+        format!("anki_gen_{}.{}", hash, self.extension)
+    }
+}
+
+/// The extension for image data, from the signature at its start.
+fn sniff_extension(data: &[u8]) -> Option<&'static str> {
+    if data.starts_with(b"\x89PNG\r\n\x1a\n") {
+        Some("png")
+    } else if data.starts_with(&[0xFF, 0xD8, 0xFF]) {
+        Some("jpg")
+    } else if data.starts_with(b"GIF87a") || data.starts_with(b"GIF89a") {
+        Some("gif")
+    } else if data.len() >= 12 && &data[..4] == b"RIFF" && &data[8..12] == b"WEBP" {
+        Some("webp")
+    } else {
+        None
+    }
+}
+
+pub fn is_image_path(path: &Path) -> bool {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|e| EXTENSIONS.contains(&e.to_lowercase().as_str()))
+}
+
+/// Split a batch item into its description and image: `page.png` or
+/// `page.png | grammar in this panel`. Other items have no image.
+pub fn parse_item(item: &str) -> (String, Option<PathBuf>) {
+    let (path, description) = match item.split_once(ITEM_SEPARATOR) {
+        Some((path, description)) => (path.trim(), description.trim()),
+        None => (item.trim(), ""),
+    };
+    let path = PathBuf::from(path);
+    if !is_image_path(&path) {
+        return (item.to_string(), None);
+    }
+    let description = if description.is_empty() {
+        "the attached image".to_string()
+    } else {
+        description.to_string()
+    };
+    (description, Some(path))
+}
@@ -1,58 +1,244 @@
 mod anki_client;
+mod cassette;
 mod cli;
 mod config;
+mod dedup;
 mod engine;
 mod errors;
+mod field_map;
+mod history;
+mod image;
+mod metrics;
 mod model_client;
+mod notetype;
+mod output;
+mod postprocess;
+mod prompt_budget;
 mod prompt_builder;
+mod retry;
+mod review;
+mod server;
+mod shutdown;
+mod siblings;
+mod static_fields;
 mod storage;
+mod suggest;
 mod types;
+mod validation;
 
-use std::path::PathBuf;
+#[cfg(test)]
+mod tests;
 
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use chrono::NaiveDate;
 use clap::Parser;
 
 use anki_client::AnkiConnectClient;
-use cli::{Cli, Commands};
+use cassette::{Cassette, CassetteMode};
+use cli::{Cli, Commands, HistoryCommand, NotetypeCommand};
 use config::Config;
+use dedup::SemanticDedup;
 use engine::Engine;
+use errors::AppError;
+use history::HistoryFilter;
+use metrics::GroupBy;
 use model_client::OllamaClient;
+use notetype::NoteTypeDefinition;
+use output::{Event, Output};
+use review::Reviewer;
+use shutdown::{INTERRUPTED_EXIT_CODE, Shutdown};
 use storage::FileStorage;
-use types::CardRequest;
+use types::{BatchItem, CardRequest, HistoryEntry};
 
 #[tokio::main]
 async fn main() {
     // Load config with priority: CLI args > config file > defaults
-    let mut config = Config::load_or_default();
     let cli = Cli::parse();
-    config.merge_cli_overrides(&cli);
+    let mut config = if matches!(cli.command, Commands::Check) {
+        Config::load_or_warn()
+    } else {
+        Config::load_or_default()
+            .unwrap_or_else(|e| fail(&Output::new(cli.output_format.unwrap_or_default()), e))
+    };
+    if let Err(e) = config.merge_cli_overrides(&cli) {
+        fail(&Output::new(cli.output_format.unwrap_or_default()), e);
+    }
+    let output = Output::new(config.output_format);
+
+    let mut model = OllamaClient::new(config.ollama_url.clone(), config.model.clone())
+        .with_api(config.ollama_api)
+        .with_http(&config.http)
+        .unwrap_or_else(|e| fail(&output, e))
+        .with_options(config.options.clone(), config.keep_alive.clone())
+        .with_output(output.clone());
 
-    let model = OllamaClient::new(config.ollama_url.clone(), config.model.clone());
-    let anki = AnkiConnectClient::new(config.anki_url.clone());
+    let cassette = match (&cli.record, &cli.replay) {
+        (Some(path), _) => Some(Cassette::record(path.clone())),
+        (_, Some(path)) => Some(Cassette::replay(path.clone())),
+        _ => None,
+    };
+    let cassette = cassette.map(|cassette| match cassette {
+        Ok(cassette) => Arc::new(cassette),
+        Err(e) => fail(
+            &output,
+            AppError::Config(format!("Could not open cassette: {}", e)),
+        ),
+    });
+    if let Some(cassette) = &cassette {
+        match cassette.mode() {
+            CassetteMode::Record => eprintln!("Recording model responses to cassette"),
+            CassetteMode::Replay => {
+                eprintln!("Replaying {} recorded model responses", cassette.len())
+            }
+        }
+        model = model.with_cassette(cassette.clone());
+    }
+    let anki = AnkiConnectClient::new(config.anki_url.clone())
+        .with_http(&config.http)
+        .unwrap_or_else(|e| fail(&output, e));
+    let storage = FileStorage::new(PathBuf::from(&config.storage_path));
+    let mut engine = Engine::new(model, anki, storage, config.clone());
 
     // Handle commands that don't need full config
     match &cli.command {
         Commands::Check => {
-            run_check(&model, &anki).await;
+            run_check(&engine, &output).await;
             return;
         }
         Commands::Config { format } => {
-            generate_config_file(format);
+            generate_config_file(format, &output);
+            return;
+        }
+        Commands::History { action } => {
+            if let Err(e) = run_history(&engine, action, &output, config.dry_run) {
+                fail(&output, e);
+            }
+            return;
+        }
+        Commands::Stats { by, since, until } => {
+            if let Err(e) = run_stats(&engine, by, *since, *until, &output) {
+                fail(&output, e);
+            }
+            return;
+        }
+        _ => {}
+    }
+
+    if config.dedup.enabled {
+        match SemanticDedup::new(config.dedup.clone()) {
+            Ok(dedup) => {
+                eprintln!(
+                    "Semantic dedup enabled ({}, threshold {:.2})",
+                    config.dedup.embedding_model, config.dedup.threshold
+                );
+                engine = engine.with_dedup(dedup);
+            }
+            Err(e) => fail(
+                &output,
+                AppError::Config(format!("Could not load embedding cache: {}", e)),
+            ),
+        }
+    }
+
+    if config.review.enabled {
+        let review_model = config
+            .review
+            .model
+            .clone()
+            .unwrap_or_else(|| config.model.clone());
+        eprintln!(
+            "Review enabled ({}, threshold {:.1}, action {:?})",
+            review_model, config.review.threshold, config.review.action
+        );
+        let mut judge = OllamaClient::new(config.ollama_url.clone(), review_model)
+            .with_api(config.ollama_api)
+            .with_http(&config.http)
+            .unwrap_or_else(|e| fail(&output, e))
+            .with_options(config.options.clone(), config.keep_alive.clone())
+            .with_output(output.clone());
+        if let Some(cassette) = &cassette {
+            judge = judge.with_cassette(cassette.clone());
+        }
+        engine = engine.with_reviewer(Reviewer::new(judge, config.review.clone()));
+    }
+
+    // The server keeps the default handling: Ctrl-C stops it right away
+    if !matches!(cli.command, Commands::Serve { .. }) {
+        let shutdown = Shutdown::new();
+        shutdown.listen();
+        engine = engine.with_shutdown(shutdown);
+    }
+
+    // These work on existing notes or note types, so they need no deck
+    match &cli.command {
+        Commands::Notetype {
+            action: NotetypeCommand::Create { file },
+        } => {
+            let definition =
+                NoteTypeDefinition::load_from_file(file).unwrap_or_else(|e| fail(&output, e));
+            if let Err(e) = engine.create_note_type(&definition).await {
+                fail(&output, e);
+            }
+            output.info(format_args!(
+                "Created note type '{}' with fields: {}",
+                definition.name,
+                definition.fields.join(", ")
+            ));
+            return;
+        }
+        Commands::Regen { target, fields } => {
+            if let Err(e) = engine.regen(target, fields).await {
+                fail(&output, e);
+            }
+            return;
+        }
+        Commands::Revert { note_id } => {
+            match engine.revert(*note_id).await {
+                Ok(edit) => {
+                    let mut restored: Vec<&String> = edit.previous.keys().collect();
+                    restored.sort();
+                    output.info(format_args!(
+                        "Restored {} of note {} (regenerated {})",
+                        restored
+                            .iter()
+                            .map(|f| f.as_str())
+                            .collect::<Vec<_>>()
+                            .join(", "),
+                        note_id,
+                        edit.edited_at.format("%Y-%m-%d %H:%M")
+                    ));
+                }
+                Err(e) => fail(&output, e),
+            }
             return;
         }
         _ => {}
     }
 
+    // The server takes deck and fields per request, falling back to config
+    if let Commands::Serve { host, port } = &cli.command {
+        if let Err(e) = server::serve(engine, config.clone(), host, *port).await {
+            fail(&output, e.into());
+        }
+        return;
+    }
+
     let deck = config.deck.clone().unwrap_or_else(|| {
-        eprintln!("Error: --deck is required for this command (set via CLI or config file)");
-        std::process::exit(1);
+        fail(
+            &output,
+            AppError::Config(
+                "--deck is required for this command (set via CLI or config file)".into(),
+            ),
+        )
     });
     let note_type = config.note_type.clone();
 
     // If fields not specified, auto-detect from note type
     let fields = if config.fields.is_empty() {
         eprintln!("No fields specified, auto-detecting from note type '{}'...", note_type);
-        match anki.get_model_field_names(&note_type).await {
+        match engine.note_type_fields(&note_type).await {
             Ok(all_fields) => {
                 eprintln!("Auto-detected {} fields: {}", all_fields.len(), all_fields.join(", "));
                 if config.optional_fields {
@@ -63,29 +249,33 @@ async fn main() {
                 all_fields
             }
             Err(e) => {
-                eprintln!("Error: Could not get fields for note type '{}': {}", note_type, e);
                 eprintln!("Either specify --fields explicitly or ensure the note type exists in Anki");
-                std::process::exit(1);
+                fail(&output, e);
             }
         }
     } else {
         config.fields.clone()
     };
 
-    let storage = FileStorage::new(PathBuf::from(&config.storage_path));
-    let engine = Engine::new(model, anki, storage);
-
     let result = match cli.command {
-        Commands::Check | Commands::Config { .. } => unreachable!(),
-        Commands::Generate { description } => {
+        Commands::Check
+        | Commands::Config { .. }
+        | Commands::History { .. }
+        | Commands::Stats { .. }
+        | Commands::Serve { .. }
+        | Commands::Notetype { .. }
+        | Commands::Regen { .. }
+        | Commands::Revert { .. } => unreachable!(),
+        Commands::Generate { description, image } => {
             let req = CardRequest {
                 description,
                 fields: fields.clone(),
                 note_type,
                 deck,
                 optional_fields: config.optional_fields,
+                images: image,
             };
-            engine.generate(&req).await
+            engine.generate(&req).await.map(|_| ())
         }
         Commands::Next { description } => {
             let req = CardRequest {
@@ -94,89 +284,308 @@ async fn main() {
                 note_type,
                 deck,
                 optional_fields: config.optional_fields,
+                images: Vec::new(),
             };
-            engine.next(&req).await
+            engine.next(&req).await.map(|_| ())
         }
         Commands::Batch { items } => {
-            let item_list = parse_items(&items);
+            let item_list = parse_items(&items).unwrap_or_else(|e| fail(&output, e));
             let req = CardRequest {
                 description: String::new(),
                 fields: fields.clone(),
                 note_type,
                 deck,
                 optional_fields: config.optional_fields,
+                images: Vec::new(),
             };
-            engine.batch(&req, &item_list).await
+            let batch_file = items.strip_prefix('@').map(Path::new);
+            match engine.batch_items(&req, &item_list, batch_file).await {
+                Ok(report) if report.interrupted => std::process::exit(INTERRUPTED_EXIT_CODE),
+                result => result.map(|_| ()),
+            }
         }
     };
 
     if let Err(e) = result {
-        eprintln!("Error: {}", e);
+        fail(&output, e);
+    }
+}
+
+/// Report a fatal error (as an event in JSON mode) with a remediation hint, and exit
+/// with the error's exit code.
+fn fail(output: &Output, error: AppError) -> ! {
+    let message = error.to_string();
+    eprintln!("Error: {}", message);
+    if let AppError::InvalidJson { raw, .. } = &error {
+        eprintln!("Model output was:\n{}", raw);
+    }
+    let hint = error.hint();
+    if let Some(hint) = &hint {
+        eprintln!("Hint: {}", hint);
+    }
+    output.event(&Event::Error {
+        item: None,
+        message,
+        code: error.exit_code(),
+        hint,
+    });
+    std::process::exit(error.exit_code());
+}
+
+async fn run_check(engine: &Engine, output: &Output) {
+    let report = engine.check().await;
+
+    match &report.ollama_error {
+        None if report.model_found => output.info(format_args!(
+            "Ollama ({})... OK (model found)",
+            report.model
+        )),
+        None => output.info(format_args!(
+            "Ollama ({})... WARNING: connected but model '{}' not found. Available: {}",
+            report.model,
+            report.model,
+            report.models.join(", ")
+        )),
+        Some(e) => output.info(format_args!("Ollama ({})... FAIL ({})", report.model, e)),
+    }
+
+    match (&report.anki_version, &report.anki_error) {
+        (Some(version), _) => output.info(format_args!("AnkiConnect... OK (version {})", version)),
+        (None, e) => output.info(format_args!(
+            "AnkiConnect... FAIL ({})",
+            e.as_deref().unwrap_or_default()
+        )),
+    }
+
+    output.event(&Event::Check {
+        ollama_ok: report.ollama_ok,
+        model_found: report.model_found,
+        models: &report.models,
+        anki_ok: report.anki_ok,
+        anki_version: report.anki_version,
+    });
+
+    if report.ok() {
+        output.info("\nAll checks passed.");
+    } else {
+        output.info("\nSome checks failed.");
         std::process::exit(1);
     }
 }
 
-async fn run_check(model: &OllamaClient, anki: &AnkiConnectClient) {
-    let mut ok = true;
-
-    print!("Ollama ({})... ", model.model_name());
-    match model.ping().await {
-        Ok(models) => {
-            if models.iter().any(|m| m.starts_with(model.model_name())) {
-                println!("OK (model found)");
-            } else {
-                println!(
-                    "WARNING: connected but model '{}' not found. Available: {}",
-                    model.model_name(),
-                    models.join(", ")
-                );
+fn run_history(
+    engine: &Engine,
+    action: &HistoryCommand,
+    output: &Output,
+    dry_run: bool,
+) -> Result<(), AppError> {
+    match action {
+        HistoryCommand::List { filter } => {
+            let filter = HistoryFilter::from(filter);
+            let mut items = history::items(&engine.history()?);
+            items.retain(|e| filter.matches(e));
+            print_history_items(&items, output);
+        }
+        HistoryCommand::Search { query, filter } => {
+            let filter = HistoryFilter::from(filter);
+            let mut items = history::search(history::items(&engine.history()?), query);
+            items.retain(|e| filter.matches(e));
+            print_history_items(&items, output);
+        }
+        HistoryCommand::Remove { items } => {
+            let removed = engine.remove_history(items)?;
+            let used = engine.history()?.used_items;
+            for item in items {
+                if !removed.iter().any(|r| suggest::normalize(r) == suggest::normalize(item)) {
+                    output.info(format_args!(
+                        "'{}' is not in history{}",
+                        item,
+                        suggest::did_you_mean(item, &used)
+                    ));
+                }
             }
+            report_removed(&removed, output, dry_run);
         }
-        Err(e) => {
-            println!("FAIL ({})", e);
-            ok = false;
+        HistoryCommand::Clear { filter, yes } => {
+            let filter = HistoryFilter::from(filter);
+            if !yes {
+                let mut matching = history::items(&engine.history()?);
+                matching.retain(|e| filter.matches(e));
+                let names: Vec<String> = matching.into_iter().map(|e| e.item).collect();
+                output.info(format_args!(
+                    "Would remove {} items; pass --yes to clear them",
+                    names.len()
+                ));
+                output.event(&Event::HistoryRemoved {
+                    items: &names,
+                    dry_run: true,
+                });
+                return Ok(());
+            }
+            let removed = engine.clear_history(&filter)?;
+            report_removed(&removed, output, dry_run);
+        }
+        HistoryCommand::Export { file } => {
+            let history = engine.history()?;
+            match file {
+                Some(path) => {
+                    std::fs::write(path, serde_json::to_string_pretty(&history)?)?;
+                    eprintln!("Exported history to {}", path.display());
+                }
+                None if output.is_json() => {
+                    output.event(&Event::HistoryExport { history: &history })
+                }
+                None => println!("{}", serde_json::to_string_pretty(&history)?),
+            }
+        }
+        HistoryCommand::Import { file, replace } => {
+            let imported = history::read_import(file)?;
+            let added = engine.import_history(&imported, *replace)?;
+            let verb = if dry_run { "Would add" } else { "Added" };
+            output.info(format_args!(
+                "{} {} of {} items from {}",
+                verb,
+                added,
+                imported.used_items.len(),
+                file.display()
+            ));
+            output.event(&Event::HistoryImported { added, dry_run });
         }
     }
+    Ok(())
+}
 
-    print!("AnkiConnect... ");
-    match anki.ping().await {
-        Ok(version) => println!("OK (version {})", version),
-        Err(e) => {
-            println!("FAIL ({})", e);
-            ok = false;
+fn print_history_items(items: &[HistoryEntry], output: &Output) {
+    for entry in items {
+        let added = entry
+            .added_at
+            .map(|t| t.with_timezone(&chrono::Local).format("%Y-%m-%d %H:%M").to_string())
+            .unwrap_or_default();
+        let details: Vec<&str> = [entry.deck.as_deref(), entry.topic.as_deref()]
+            .into_iter()
+            .flatten()
+            .collect();
+        if details.is_empty() {
+            output.info(format_args!("{:<16}  {}", added, entry.item));
+        } else {
+            output.info(format_args!(
+                "{:<16}  {}  [{}]",
+                added,
+                entry.item,
+                details.join(" · ")
+            ));
         }
     }
+    output.info(format_args!("{} items", items.len()));
+    output.event(&Event::HistoryItems { items });
+}
 
-    if ok {
-        println!("\nAll checks passed.");
+fn run_stats(
+    engine: &Engine,
+    by: &[GroupBy],
+    since: Option<NaiveDate>,
+    until: Option<NaiveDate>,
+    output: &Output,
+) -> Result<(), AppError> {
+    let groups = metrics::aggregate(&engine.history()?.runs, by, since, until);
+    if groups.is_empty() {
+        output.info("No metrics recorded yet");
     } else {
-        println!("\nSome checks failed.");
-        std::process::exit(1);
+        let title = by
+            .iter()
+            .map(|g| format!("{:?}", g))
+            .collect::<Vec<_>>()
+            .join(" / ");
+        let width = groups
+            .keys()
+            .map(|k| k.chars().count())
+            .chain([title.len()])
+            .max()
+            .unwrap_or_default();
+        let pad = |text: &str| {
+            let fill = width.saturating_sub(text.chars().count());
+            format!("{}{}", text, " ".repeat(fill))
+        };
+        output.info(format_args!(
+            "{}  {:>6}  {:>6}  {:>7}  {:>8}  {:>9}  {:>9}  {:>7}  {:>5}  {:>7}",
+            pad(&title),
+            "Cards",
+            "Failed",
+            "s/card",
+            "tokens/s",
+            "prompt tk",
+            "output tk",
+            "retries",
+            "fuzzy",
+            "invalid"
+        ));
+        for (key, summary) in &groups {
+            let rate = summary
+                .tokens_per_second()
+                .map(|r| format!("{:.1}", r))
+                .unwrap_or_else(|| "-".into());
+            output.info(format_args!(
+                "{}  {:>6}  {:>6}  {:>7.1}  {:>8}  {:>9}  {:>9}  {:>7}  {:>5}  {:>7}",
+                pad(key),
+                summary.cards,
+                summary.failed,
+                summary.seconds_per_card(),
+                rate,
+                summary.prompt_tokens,
+                summary.eval_tokens,
+                summary.retries,
+                summary.fuzzy_fixes,
+                summary.validation_failures
+            ));
+        }
     }
+    output.event(&Event::Stats {
+        by,
+        groups: &groups,
+    });
+    Ok(())
 }
 
-fn parse_items(input: &str) -> Vec<String> {
+fn report_removed(removed: &[String], output: &Output, dry_run: bool) {
+    let verb = if dry_run { "Would remove" } else { "Removed" };
+    output.info(format_args!("{} {} items from history", verb, removed.len()));
+    output.event(&Event::HistoryRemoved {
+        items: removed,
+        dry_run,
+    });
+}
+
+fn parse_items(input: &str) -> Result<Vec<BatchItem>, AppError> {
     let items = if let Some(path) = input.strip_prefix('@') {
         match std::fs::read_to_string(path) {
+            Ok(content) if path.ends_with(".tsv") => {
+                let items = parse_rows(&content);
+                eprintln!("Loaded {} rows from file '{}'", items.len(), path);
+                items
+            }
             Ok(content) => {
-                let items: Vec<String> = content
+                let items: Vec<BatchItem> = content
                     .lines()
-                    .map(|l| l.trim().to_string())
+                    .map(|l| l.trim())
                     .filter(|l| !l.is_empty())
+                    .map(BatchItem::new)
                     .collect();
                 eprintln!("Loaded {} items from file '{}'", items.len(), path);
                 items
             }
             Err(e) => {
-                eprintln!("Error reading file '{}': {}", path, e);
-                std::process::exit(1);
+                return Err(AppError::Config(format!(
+                    "Failed to read batch file '{}': {}",
+                    path, e
+                )));
             }
         }
     } else {
-        let items: Vec<String> = input
+        let items: Vec<BatchItem> = input
             .split(',')
-            .map(|s| s.trim().to_string())
+            .map(|s| s.trim())
             .filter(|s| !s.is_empty())
+            .map(BatchItem::new)
             .collect();
         eprintln!("Parsed {} items from input", items.len());
         items
@@ -186,19 +595,51 @@ fn parse_items(input: &str) -> Vec<String> {
         eprintln!("Warning: No items to process!");
     }
 
-    items
+    Ok(items)
 }
 
-fn generate_config_file(format: &str) {
+/// Rows of a tab-separated file: a header line naming the columns, then one item per
+/// row in the first column, with the other columns kept for static fields.
+fn parse_rows(content: &str) -> Vec<BatchItem> {
+    let mut lines = content.lines().filter(|l| !l.trim().is_empty());
+    let header: Vec<&str> = lines
+        .next()
+        .map(|l| l.split('\t').map(str::trim).collect())
+        .unwrap_or_default();
+    lines
+        .filter_map(|line| {
+            let mut values = line.split('\t').map(str::trim);
+            let item = values.next().filter(|v| !v.is_empty())?;
+            let mut row = BatchItem::new(item);
+            for (name, value) in header.iter().skip(1).zip(values) {
+                row.columns.insert(name.to_string(), value.to_string());
+            }
+            Some(row)
+        })
+        .collect()
+}
+
+fn generate_config_file(format: &str, output: &Output) {
     let (content, filename) = match format.to_lowercase().as_str() {
         "json" => (Config::generate_example_json(), "config.json"),
         "yaml" | "yml" => (Config::generate_example_yaml(), "config.yaml"),
-        _ => {
-            eprintln!("Error: Unknown format '{}'. Use 'yaml' or 'json'.", format);
-            std::process::exit(1);
-        }
+        _ => fail(
+            output,
+            AppError::Config(format!(
+                "Unknown format '{}'. Use 'yaml' or 'json'.",
+                format
+            )),
+        ),
     };
 
+    if output.is_json() {
+        output.event(&Event::Config {
+            format,
+            content: &content,
+        });
+        return;
+    }
+
     println!("{}", content);
     println!("\n# To use this config, save it to '{}'", filename);
 }
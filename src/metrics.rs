@@ -0,0 +1,199 @@
+//! Per-card generation metrics, and their aggregation across runs for `stats`.
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+use chrono::{Local, NaiveDate};
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+use crate::model_client::Usage;
+use crate::types::{ChatMessage, RunRecord};
+
+/// What generating one card took. Counts cover every request made for the card,
+/// including regenerations.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct CardMetrics {
+    pub item: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub deck: String,
+    /// Characters of prompt sent to the model.
+    pub prompt_chars: usize,
+    pub prompt_tokens: u64,
+    pub eval_tokens: u64,
+    pub eval_ms: u64,
+    pub wall_ms: u64,
+    /// Regenerations (validation, review, repeated `next` items) and requests repeated
+    /// after transient failures.
+    pub retries: u32,
+    /// Model keys renamed to the nearest expected field.
+    pub fuzzy_fixes: u32,
+    /// Responses rejected by the schema check or validation rules.
+    pub validation_failures: u32,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub failed: bool,
+}
+
+impl CardMetrics {
+    pub fn new(item: &str, deck: &str) -> Self {
+        Self {
+            item: item.to_string(),
+            deck: deck.to_string(),
+            ..Self::default()
+        }
+    }
+
+    /// Count one request to the model and what Ollama reported for it.
+    pub fn add_request(&mut self, messages: &[ChatMessage], usage: &Usage) {
+        self.prompt_chars += messages
+            .iter()
+            .map(|m| m.content.chars().count())
+            .sum::<usize>();
+        self.prompt_tokens += usage.prompt_tokens;
+        self.eval_tokens += usage.eval_tokens;
+        self.eval_ms += usage.eval_ms;
+        self.retries += usage.retries;
+    }
+
+    pub fn tokens_per_second(&self) -> Option<f64> {
+        tokens_per_second(self.eval_tokens, self.eval_ms)
+    }
+}
+
+impl fmt::Display for CardMetrics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:.1}s", self.wall_ms as f64 / 1000.0)?;
+        if self.eval_tokens > 0 {
+            write!(f, ", {} tokens", self.eval_tokens)?;
+        }
+        if let Some(rate) = self.tokens_per_second() {
+            write!(f, " at {:.1} tokens/s", rate)?;
+        }
+        for (count, what) in [
+            (self.retries, "retries"),
+            (self.fuzzy_fixes, "fuzzy fixes"),
+            (self.validation_failures, "validation failures"),
+        ] {
+            if count > 0 {
+                write!(f, ", {} {}", count, what)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn tokens_per_second(tokens: u64, ms: u64) -> Option<f64> {
+    (ms > 0).then(|| tokens as f64 * 1000.0 / ms as f64)
+}
+
+/// Totals over a set of cards.
+#[derive(Serialize, Clone, Debug, Default, PartialEq)]
+pub struct MetricsSummary {
+    pub cards: usize,
+    pub failed: usize,
+    pub prompt_chars: usize,
+    pub prompt_tokens: u64,
+    pub eval_tokens: u64,
+    pub eval_ms: u64,
+    pub wall_ms: u64,
+    pub retries: u32,
+    pub fuzzy_fixes: u32,
+    pub validation_failures: u32,
+}
+
+impl MetricsSummary {
+    pub fn add(&mut self, card: &CardMetrics) {
+        self.cards += 1;
+        self.failed += usize::from(card.failed);
+        self.prompt_chars += card.prompt_chars;
+        self.prompt_tokens += card.prompt_tokens;
+        self.eval_tokens += card.eval_tokens;
+        self.eval_ms += card.eval_ms;
+        self.wall_ms += card.wall_ms;
+        self.retries += card.retries;
+        self.fuzzy_fixes += card.fuzzy_fixes;
+        self.validation_failures += card.validation_failures;
+    }
+
+    pub fn tokens_per_second(&self) -> Option<f64> {
+        tokens_per_second(self.eval_tokens, self.eval_ms)
+    }
+
+    /// Average wall time per card, in seconds.
+    pub fn seconds_per_card(&self) -> f64 {
+        if self.cards == 0 {
+            return 0.0;
+        }
+        self.wall_ms as f64 / 1000.0 / self.cards as f64
+    }
+}
+
+impl<'a> FromIterator<&'a CardMetrics> for MetricsSummary {
+    fn from_iter<I: IntoIterator<Item = &'a CardMetrics>>(cards: I) -> Self {
+        let mut summary = Self::default();
+        for card in cards {
+            summary.add(card);
+        }
+        summary
+    }
+}
+
+impl fmt::Display for MetricsSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:.1}s per card, {} prompt tokens, {} generated tokens",
+            self.seconds_per_card(),
+            self.prompt_tokens,
+            self.eval_tokens
+        )?;
+        if let Some(rate) = self.tokens_per_second() {
+            write!(f, " ({:.1} tokens/s)", rate)?;
+        }
+        write!(
+            f,
+            ", {} retries, {} fuzzy fixes, {} validation failures",
+            self.retries, self.fuzzy_fixes, self.validation_failures
+        )
+    }
+}
+
+/// What `stats` groups cards by.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum GroupBy {
+    Model,
+    Deck,
+    Command,
+}
+
+/// Metrics of the cards from runs started between `since` and `until` (local dates,
+/// inclusive), grouped by the values of `by` joined with " / ".
+pub fn aggregate(
+    runs: &[RunRecord],
+    by: &[GroupBy],
+    since: Option<NaiveDate>,
+    until: Option<NaiveDate>,
+) -> BTreeMap<String, MetricsSummary> {
+    let mut groups: BTreeMap<String, MetricsSummary> = BTreeMap::new();
+    for run in runs {
+        let date = run.started_at.with_timezone(&Local).date_naive();
+        if since.is_some_and(|since| date < since) || until.is_some_and(|until| date > until) {
+            continue;
+        }
+        for card in &run.cards {
+            let key = by
+                .iter()
+                .map(|group| match group {
+                    GroupBy::Model => run.model.as_str(),
+                    GroupBy::Deck if card.deck.is_empty() => "-",
+                    GroupBy::Deck => card.deck.as_str(),
+                    GroupBy::Command => run.command.as_str(),
+                })
+                .collect::<Vec<_>>()
+                .join(" / ");
+            groups.entry(key).or_default().add(card);
+        }
+    }
+    groups
+}
@@ -1,23 +1,106 @@
-use std::io::{self, Write};
+use std::sync::Arc;
 
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 use serde_json::json;
 
+use crate::cassette::{self, Cassette, CassetteRequest};
+use crate::config::{HttpConfig, ModelOptions, OllamaApi};
 use crate::errors::AppError;
-use crate::types::CardFields;
+use crate::output::Output;
+use crate::retry::{self, RetryPolicy};
+use crate::types::{CardFields, ChatMessage, Role};
 
 #[derive(Serialize)]
-struct OllamaRequest {
-    model: String,
+struct ChatRequest<'a> {
+    model: &'a str,
+    messages: &'a [ChatMessage],
+    stream: bool,
+    format: serde_json::Value,
+    #[serde(skip_serializing_if = "ModelOptions::is_empty")]
+    options: &'a ModelOptions,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    keep_alive: Option<&'a str>,
+}
+
+#[derive(Serialize)]
+struct GenerateRequest<'a> {
+    model: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    system: Option<String>,
     prompt: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    images: Vec<&'a str>,
     stream: bool,
     format: serde_json::Value,
+    #[serde(skip_serializing_if = "ModelOptions::is_empty")]
+    options: &'a ModelOptions,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    keep_alive: Option<&'a str>,
 }
 
+/// A streamed NDJSON line. `/api/generate` fills `response`, `/api/chat` fills `message`.
+/// The final line also carries token counts and timings.
 #[derive(Deserialize)]
 struct StreamChunk {
+    #[serde(default)]
     response: String,
+    #[serde(default)]
+    message: Option<ChunkMessage>,
     done: bool,
+    #[serde(default)]
+    prompt_eval_count: u64,
+    #[serde(default)]
+    eval_count: u64,
+    /// Nanoseconds.
+    #[serde(default)]
+    eval_duration: u64,
+}
+
+#[derive(Deserialize)]
+struct ChunkMessage {
+    #[serde(default)]
+    content: String,
+}
+
+impl StreamChunk {
+    fn text(&self) -> &str {
+        match &self.message {
+            Some(message) => &message.content,
+            None => &self.response,
+        }
+    }
+
+    /// Record the counts of the final chunk.
+    fn add_usage(&self, usage: &mut Usage) {
+        if self.done {
+            usage.prompt_tokens += self.prompt_eval_count;
+            usage.eval_tokens += self.eval_count;
+            usage.eval_ms += self.eval_duration / 1_000_000;
+        }
+    }
+}
+
+/// What one completion took, as reported by Ollama. The counts are zero for responses
+/// replayed from a cassette.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Usage {
+    pub prompt_tokens: u64,
+    pub eval_tokens: u64,
+    pub eval_ms: u64,
+    /// Requests repeated after a transient failure.
+    pub retries: u32,
+}
+
+#[derive(Serialize)]
+struct EmbeddingRequest<'a> {
+    model: &'a str,
+    prompt: &'a str,
+}
+
+#[derive(Deserialize)]
+struct EmbeddingResponse {
+    embedding: Vec<f32>,
 }
 
 #[derive(Deserialize)]
@@ -33,7 +116,13 @@ struct ModelEntry {
 pub struct OllamaClient {
     base_url: String,
     model: String,
+    api: OllamaApi,
+    options: ModelOptions,
+    keep_alive: Option<String>,
+    cassette: Option<Arc<Cassette>>,
+    output: Output,
     client: reqwest::Client,
+    retry: RetryPolicy,
 }
 
 impl OllamaClient {
@@ -41,13 +130,58 @@ impl OllamaClient {
         Self {
             base_url,
             model,
+            api: OllamaApi::Chat,
+            options: ModelOptions::default(),
+            keep_alive: None,
+            cassette: None,
+            output: Output::default(),
             client: reqwest::Client::new(),
+            retry: RetryPolicy::none(),
         }
     }
 
+    /// Choose between `/api/chat` (default) and the legacy `/api/generate` endpoint.
+    pub fn with_api(mut self, api: OllamaApi) -> Self {
+        self.api = api;
+        self
+    }
+
+    /// Apply timeouts and retry transient failures. Fails if the HTTP client can't be
+    /// built, rather than falling back to one without timeouts.
+    pub fn with_http(mut self, config: &HttpConfig) -> Result<Self, AppError> {
+        self.client = retry::client(config)?;
+        self.retry = RetryPolicy::from_config(config);
+        Ok(self)
+    }
+
+    /// Set the Ollama `options` and `keep_alive` sent with generation requests.
+    pub fn with_options(mut self, options: ModelOptions, keep_alive: Option<String>) -> Self {
+        self.options = options;
+        self.keep_alive = keep_alive;
+        self
+    }
+
+    /// Where streamed tokens are echoed.
+    pub fn with_output(mut self, output: Output) -> Self {
+        self.output = output;
+        self
+    }
+
+    /// Record responses to, or replay them from, a cassette file. The cassette may be
+    /// shared with other clients, since the model name is part of each key.
+    pub fn with_cassette(mut self, cassette: Arc<Cassette>) -> Self {
+        self.cassette = Some(cassette);
+        self
+    }
+
     pub async fn ping(&self) -> Result<Vec<String>, AppError> {
         let url = format!("{}/api/tags", self.base_url);
-        let resp = self.client.get(&url).send().await?;
+        let resp = self
+            .client
+            .get(&url)
+            .send()
+            .await
+            .map_err(|e| AppError::request("Ollama", &url, e))?;
 
         if !resp.status().is_success() {
             return Err(AppError::Model(format!(
@@ -64,6 +198,58 @@ impl OllamaClient {
         &self.model
     }
 
+    /// Embed a piece of text with the given embedding model, from the cassette when
+    /// replaying.
+    pub async fn embed(&self, model: &str, text: &str) -> Result<Vec<f32>, AppError> {
+        let Some(cassette) = &self.cassette else {
+            return self.fetch_embedding(model, text).await;
+        };
+
+        let request = cassette::EmbeddingRequest {
+            model: model.to_string(),
+            prompt: text.to_string(),
+        };
+        match cassette.lookup_embedding(&request)? {
+            Some(recorded) => Ok(recorded),
+            None => {
+                let embedding = self.fetch_embedding(model, text).await?;
+                cassette.store_embedding(&request, &embedding)?;
+                Ok(embedding)
+            }
+        }
+    }
+
+    /// Embed a piece of text via `/api/embeddings`.
+    async fn fetch_embedding(&self, model: &str, text: &str) -> Result<Vec<f32>, AppError> {
+        let req = EmbeddingRequest {
+            model,
+            prompt: text,
+        };
+
+        let url = format!("{}/api/embeddings", self.base_url);
+        let (resp, _) = self.post(&url, &req).await?;
+
+        if resp.status() == reqwest::StatusCode::NOT_FOUND {
+            return Err(AppError::ModelNotFound(model.to_string()));
+        }
+        if !resp.status().is_success() {
+            return Err(AppError::Model(format!(
+                "Ollama returned status {} for embedding model '{}'",
+                resp.status(),
+                model
+            )));
+        }
+
+        let parsed: EmbeddingResponse = resp.json().await?;
+        if parsed.embedding.is_empty() {
+            return Err(AppError::Model(format!(
+                "Embedding model '{}' returned an empty vector",
+                model
+            )));
+        }
+        Ok(parsed.embedding)
+    }
+
     /// Build a JSON schema that enforces all fields are present and non-empty strings.
     fn build_schema(fields: &[String]) -> serde_json::Value {
         let mut properties = serde_json::Map::new();
@@ -85,19 +271,152 @@ impl OllamaClient {
         })
     }
 
-    pub async fn generate(&self, prompt: &str, fields: &[String]) -> Result<CardFields, AppError> {
-        let schema = Self::build_schema(fields);
+    /// POST to Ollama, retrying refused connections and 5xx responses. Requests have no
+    /// side effects, so repeating them is safe, but a timed-out one isn't repeated: a
+    /// hung generation would otherwise hold up a batch for several times the timeout.
+    /// Returns the response and how many times the request was repeated.
+    async fn post(
+        &self,
+        url: &str,
+        body: &impl Serialize,
+    ) -> Result<(reqwest::Response, u32), AppError> {
+        let mut failures = 0;
+        loop {
+            let reason = match self.client.post(url).json(body).send().await {
+                Ok(resp) if resp.status().is_server_error() => {
+                    AppError::Model(format!("Ollama returned status {}", resp.status()))
+                }
+                Ok(resp) => return Ok((resp, failures)),
+                Err(e) if e.is_connect() => AppError::request("Ollama", url, e),
+                Err(e) => return Err(AppError::request("Ollama", url, e)),
+            };
+            failures += 1;
+            if !self.retry.wait(failures, "Ollama", &reason).await {
+                return Err(reason);
+            }
+        }
+    }
+
+    /// Send the request to the configured endpoint. `/api/generate` gets the system
+    /// messages as `system` and the rest of the transcript as `prompt`.
+    async fn send(
+        &self,
+        messages: &[ChatMessage],
+        schema: serde_json::Value,
+    ) -> Result<(reqwest::Response, u32), AppError> {
+        let options = &self.options;
+        let keep_alive = self.keep_alive.as_deref();
 
-        let req = OllamaRequest {
+        let resp = match self.api {
+            OllamaApi::Chat => {
+                let req = ChatRequest {
+                    model: &self.model,
+                    messages,
+                    stream: true,
+                    format: schema,
+                    options,
+                    keep_alive,
+                };
+                let url = format!("{}/api/chat", self.base_url);
+                self.post(&url, &req).await?
+            }
+            OllamaApi::Generate => {
+                let join = |system: bool| {
+                    messages
+                        .iter()
+                        .filter(|m| (m.role == Role::System) == system)
+                        .map(|m| m.content.as_str())
+                        .collect::<Vec<_>>()
+                        .join("\n\n")
+                };
+                let system = join(true);
+                let images = messages
+                    .iter()
+                    .flat_map(|m| m.images.iter().map(String::as_str))
+                    .collect();
+                let req = GenerateRequest {
+                    model: &self.model,
+                    system: (!system.is_empty()).then_some(system),
+                    prompt: join(false),
+                    images,
+                    stream: true,
+                    format: schema,
+                    options,
+                    keep_alive,
+                };
+                let url = format!("{}/api/generate", self.base_url);
+                self.post(&url, &req).await?
+            }
+        };
+
+        Ok(resp)
+    }
+
+    /// Generate the card fields, and report what the completion took.
+    pub async fn generate(
+        &self,
+        messages: &[ChatMessage],
+        fields: &[String],
+    ) -> Result<(CardFields, Usage), AppError> {
+        let (full_response, usage) = self.complete(messages, Self::build_schema(fields)).await?;
+        Ok((Self::parse_fields(&full_response)?, usage))
+    }
+
+    /// Generate a response constrained to `schema` and deserialize it.
+    pub async fn generate_json<T: DeserializeOwned>(
+        &self,
+        messages: &[ChatMessage],
+        schema: serde_json::Value,
+    ) -> Result<T, AppError> {
+        let (full_response, _) = self.complete(messages, schema).await?;
+        serde_json::from_str(&full_response).map_err(|e| AppError::InvalidJson {
+            error: e.to_string(),
+            raw: full_response,
+        })
+    }
+
+    /// The full response text, from the cassette when replaying.
+    async fn complete(
+        &self,
+        messages: &[ChatMessage],
+        schema: serde_json::Value,
+    ) -> Result<(String, Usage), AppError> {
+        let Some(cassette) = &self.cassette else {
+            return self.stream_response(messages, schema).await;
+        };
+
+        let request = CassetteRequest {
             model: self.model.clone(),
-            prompt: prompt.to_string(),
-            stream: true,
-            format: schema,
+            messages: messages.to_vec(),
+            schema: schema.clone(),
+            options: self.options.clone(),
         };
+        match cassette.lookup(&request)? {
+            Some(recorded) => {
+                self.output.tokens(&recorded);
+                self.output.tokens("\n");
+                Ok((recorded, Usage::default()))
+            }
+            None => {
+                let (response, usage) = self.stream_response(messages, schema).await?;
+                cassette.store(&request, &response)?;
+                Ok((response, usage))
+            }
+        }
+    }
 
-        let url = format!("{}/api/generate", self.base_url);
-        let mut resp = self.client.post(&url).json(&req).send().await?;
+    /// Stream a completion from Ollama, echoing tokens as they arrive. Returns the full
+    /// text and the usage from the final chunk.
+    async fn stream_response(
+        &self,
+        messages: &[ChatMessage],
+        schema: serde_json::Value,
+    ) -> Result<(String, Usage), AppError> {
+        let (mut resp, retries) = self.send(messages, schema).await?;
 
+        if resp.status() == reqwest::StatusCode::NOT_FOUND {
+            return Err(AppError::ModelNotFound(self.model.clone()));
+        }
         if !resp.status().is_success() {
             return Err(AppError::Model(format!(
                 "Ollama returned status {}",
@@ -105,40 +424,60 @@ impl OllamaClient {
             )));
         }
 
-        let mut buffer = String::new();
+        // Buffer raw bytes: a chunk boundary may fall inside a multi-byte character
+        let mut buffer: Vec<u8> = Vec::new();
         let mut full_response = String::new();
+        let mut usage = Usage {
+            retries,
+            ..Usage::default()
+        };
 
-        while let Some(chunk) = resp.chunk().await? {
-            buffer.push_str(&String::from_utf8_lossy(&chunk));
+        let url = resp.url().to_string();
+        while let Some(chunk) = resp
+            .chunk()
+            .await
+            .map_err(|e| AppError::request("Ollama", &url, e))?
+        {
+            buffer.extend_from_slice(&chunk);
 
-            while let Some(pos) = buffer.find('\n') {
-                let line = buffer[..pos].to_string();
-                buffer = buffer[pos + 1..].to_string();
+            while let Some(pos) = buffer.iter().position(|b| *b == b'\n') {
+                let line: Vec<u8> = buffer.drain(..=pos).collect();
+                let line = String::from_utf8_lossy(&line);
 
                 if line.trim().is_empty() {
                     continue;
                 }
 
                 if let Ok(parsed) = serde_json::from_str::<StreamChunk>(&line) {
-                    print!("{}", parsed.response);
-                    io::stdout().flush().ok();
-                    full_response.push_str(&parsed.response);
+                    self.output.tokens(parsed.text());
+                    full_response.push_str(parsed.text());
+                    parsed.add_usage(&mut usage);
 
                     if parsed.done {
-                        println!();
+                        self.output.tokens("\n");
                     }
                 }
             }
         }
 
         // Handle any remaining data in buffer
-        if !buffer.trim().is_empty() {
-            if let Ok(parsed) = serde_json::from_str::<StreamChunk>(&buffer) {
-                full_response.push_str(&parsed.response);
-            }
+        let rest = String::from_utf8_lossy(&buffer);
+        if !rest.trim().is_empty()
+            && let Ok(parsed) = serde_json::from_str::<StreamChunk>(&rest)
+        {
+            full_response.push_str(parsed.text());
+            parsed.add_usage(&mut usage);
         }
 
-        let raw: CardFields = serde_json::from_str(&full_response)?;
+        Ok((full_response, usage))
+    }
+
+    fn parse_fields(full_response: &str) -> Result<CardFields, AppError> {
+        let raw: CardFields =
+            serde_json::from_str(full_response).map_err(|e| AppError::InvalidJson {
+                error: e.to_string(),
+                raw: full_response.to_string(),
+            })?;
 
         // Trim whitespace from keys and values
         let fields: CardFields = raw
@@ -0,0 +1,71 @@
+use std::fs;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::errors::AppError;
+
+/// One card template of a note type.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CardTemplate {
+    pub name: String,
+    pub front: String,
+    pub back: String,
+}
+
+/// A note type definition file, for bootstrapping Anki from the repo with
+/// `notetype create`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NoteTypeDefinition {
+    pub name: String,
+    pub fields: Vec<String>,
+    pub templates: Vec<CardTemplate>,
+    #[serde(default)]
+    pub css: String,
+    #[serde(default)]
+    pub is_cloze: bool,
+}
+
+impl NoteTypeDefinition {
+    /// Load a YAML (`.yaml`/`.yml`) or JSON definition file.
+    pub fn load_from_file<P: AsRef<Path>>(path: P) -> Result<Self, AppError> {
+        let path = path.as_ref();
+        let content = fs::read_to_string(path)
+            .map_err(|e| AppError::Config(format!("Failed to read note type file: {}", e)))?;
+
+        let definition: Self = match path.extension().and_then(|s| s.to_str()) {
+            Some("yaml" | "yml") => serde_yaml::from_str(&content)
+                .map_err(|e| AppError::Config(format!("Failed to parse YAML note type: {}", e)))?,
+            _ => serde_json::from_str(&content)
+                .map_err(|e| AppError::Config(format!("Failed to parse JSON note type: {}", e)))?,
+        };
+        definition.validate().map_err(AppError::Config)?;
+        Ok(definition)
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if self.name.trim().is_empty() {
+            return Err("Note type needs a name".into());
+        }
+        if self.fields.is_empty() {
+            return Err(format!(
+                "Note type '{}' needs at least one field",
+                self.name
+            ));
+        }
+        if self.templates.is_empty() {
+            return Err(format!(
+                "Note type '{}' needs at least one card template",
+                self.name
+            ));
+        }
+        let mut seen = Vec::new();
+        for field in &self.fields {
+            if seen.contains(&field) {
+                return Err(format!("Field '{}' is listed twice", field));
+            }
+            seen.push(field);
+        }
+        Ok(())
+    }
+}
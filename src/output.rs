@@ -0,0 +1,172 @@
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex};
+
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+use crate::metrics::{GroupBy, MetricsSummary};
+use crate::types::{CardFields, FailedItem, HistoryEntry, StoredHistory};
+
+/// How results are written to stdout.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum OutputFormat {
+    /// Human-readable progress on stdout.
+    #[default]
+    Text,
+    /// One JSON event per line on stdout; progress and model tokens go to stderr.
+    Json,
+}
+
+/// Structured events written in JSON mode.
+#[derive(Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event<'a> {
+    Check {
+        ollama_ok: bool,
+        model_found: bool,
+        models: &'a [String],
+        anki_ok: bool,
+        anki_version: Option<u64>,
+    },
+    Preflight {
+        deck: &'a str,
+        note_type: &'a str,
+        fields: &'a [String],
+        note_type_fields: &'a [String],
+    },
+    GenerationStarted {
+        item: &'a str,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        index: Option<usize>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        total: Option<usize>,
+    },
+    Fields {
+        item: &'a str,
+        fields: &'a CardFields,
+    },
+    Review {
+        item: &'a str,
+        score: f32,
+        issues: &'a [String],
+    },
+    NoteAdded {
+        item: &'a str,
+        note_id: Option<u64>,
+        dry_run: bool,
+    },
+    NoteUpdated {
+        item: &'a str,
+        note_id: u64,
+        fields: &'a CardFields,
+        dry_run: bool,
+    },
+    Skipped {
+        item: &'a str,
+        reason: &'a str,
+    },
+    Error {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        item: Option<&'a str>,
+        message: String,
+        /// The exit code this error maps to.
+        code: i32,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        hint: Option<String>,
+    },
+    BatchSummary {
+        succeeded: usize,
+        skipped: usize,
+        failed: usize,
+        total: usize,
+        interrupted: bool,
+        failures: &'a [FailedItem],
+        metrics: &'a MetricsSummary,
+    },
+    HistoryItems {
+        items: &'a [HistoryEntry],
+    },
+    /// The whole stored history, for `history export` without a file.
+    HistoryExport {
+        history: &'a StoredHistory,
+    },
+    HistoryRemoved {
+        items: &'a [String],
+        dry_run: bool,
+    },
+    HistoryImported {
+        added: usize,
+        dry_run: bool,
+    },
+    Stats {
+        by: &'a [GroupBy],
+        groups: &'a BTreeMap<String, MetricsSummary>,
+    },
+    Config {
+        format: &'a str,
+        content: &'a str,
+    },
+}
+
+/// Routes human progress text, structured events and model tokens to the right stream.
+#[derive(Clone, Default)]
+pub struct Output {
+    format: OutputFormat,
+    log: Option<Arc<Mutex<Vec<serde_json::Value>>>>,
+}
+
+impl Output {
+    pub fn new(format: OutputFormat) -> Self {
+        Self { format, log: None }
+    }
+
+    /// JSON mode that keeps events in memory instead of writing them to stdout.
+    #[cfg(test)]
+    pub fn collecting() -> (Self, Arc<Mutex<Vec<serde_json::Value>>>) {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let output = Self {
+            format: OutputFormat::Json,
+            log: Some(log.clone()),
+        };
+        (output, log)
+    }
+
+    pub fn is_json(&self) -> bool {
+        self.format == OutputFormat::Json
+    }
+
+    /// Progress text for humans: stdout in text mode, stderr in JSON mode.
+    pub fn info(&self, message: impl Display) {
+        if self.is_json() {
+            eprintln!("{}", message);
+        } else {
+            println!("{}", message);
+        }
+    }
+
+    /// A structured event. Only written in JSON mode.
+    pub fn event(&self, event: &Event) {
+        if !self.is_json() {
+            return;
+        }
+        let value = serde_json::to_value(event).unwrap_or_default();
+        match &self.log {
+            Some(log) => log.lock().unwrap().push(value),
+            None => println!("{}", value),
+        }
+    }
+
+    /// Streamed model tokens, echoed as they arrive.
+    pub fn tokens(&self, text: &str) {
+        if self.is_json() {
+            eprint!("{}", text);
+            io::stderr().flush().ok();
+        } else {
+            print!("{}", text);
+            io::stdout().flush().ok();
+        }
+    }
+}
@@ -0,0 +1,209 @@
+//! Field post-processing: turns raw model output into HTML that renders well on a card.
+
+use std::collections::{HashMap, HashSet};
+
+use pulldown_cmark::{Event, Options, Parser};
+
+use crate::config::{PostprocessConfig, Processor};
+use crate::types::CardFields;
+
+/// Run each field through its configured chain.
+pub fn apply(config: &PostprocessConfig, fields: CardFields) -> CardFields {
+    fields
+        .into_iter()
+        .map(|(name, value)| {
+            let value = config
+                .chain(&name)
+                .iter()
+                .fold(value, |value, processor| run(*processor, &value, config));
+            (name, value)
+        })
+        .collect()
+}
+
+pub fn run(processor: Processor, text: &str, config: &PostprocessConfig) -> String {
+    match processor {
+        Processor::Markdown => markdown_to_html(text),
+        Processor::NewlinesToBr => newlines_to_br(text),
+        Processor::Sanitize => sanitize(text, &config.allowed_tags),
+        Processor::StripQuotes => strip_quotes(text),
+        Processor::StripCodeFences => strip_code_fences(text),
+        Processor::FuriganaToRuby => furigana_to_ruby(text),
+        Processor::RubyToFurigana => ruby_to_furigana(text),
+    }
+}
+
+/// Render Markdown. Single line breaks become `<br>`, and a lone paragraph is
+/// unwrapped so short values don't gain a margin.
+fn markdown_to_html(text: &str) -> String {
+    let parser = Parser::new_ext(text, Options::ENABLE_STRIKETHROUGH).map(|event| match event {
+        Event::SoftBreak => Event::HardBreak,
+        other => other,
+    });
+    let mut html = String::new();
+    pulldown_cmark::html::push_html(&mut html, parser);
+
+    let html = html.trim();
+    match html
+        .strip_prefix("<p>")
+        .and_then(|inner| inner.strip_suffix("</p>"))
+    {
+        Some(inner) if !inner.contains("<p>") => inner.replace("<br />\n", "<br>"),
+        _ => html.replace("<br />\n", "<br>"),
+    }
+}
+
+fn newlines_to_br(text: &str) -> String {
+    text.trim_end_matches(['\r', '\n'])
+        .replace("\r\n", "\n")
+        .replace('\n', "<br>")
+}
+
+/// Keep only allowlisted tags, without attributes. Script and style contents are dropped.
+fn sanitize(text: &str, allowed_tags: &[String]) -> String {
+    let allowed: HashSet<&str> = allowed_tags.iter().map(|t| t.as_str()).collect();
+    let dropped: HashSet<&str> = ["script", "style"]
+        .into_iter()
+        .filter(|t| !allowed.contains(t))
+        .collect();
+
+    ammonia::Builder::empty()
+        .add_tags(allowed_tags)
+        .generic_attributes(HashSet::new())
+        .tag_attributes(HashMap::new())
+        .link_rel(None)
+        .clean_content_tags(dropped)
+        .clean(text)
+        .to_string()
+}
+
+/// Remove one pair of quotes wrapped around the whole value.
+fn strip_quotes(text: &str) -> String {
+    const PAIRS: &[(char, char)] = &[('"', '"'), ('\'', '\''), ('“', '”'), ('‘', '’'), ('`', '`')];
+
+    let trimmed = text.trim();
+    for (open, close) in PAIRS {
+        if let Some(inner) = trimmed
+            .strip_prefix(*open)
+            .and_then(|rest| rest.strip_suffix(*close))
+            && !inner.contains(*close)
+        {
+            return inner.trim().to_string();
+        }
+    }
+    trimmed.to_string()
+}
+
+/// Remove a ```` ``` ```` fence (with optional language) wrapped around the whole value.
+fn strip_code_fences(text: &str) -> String {
+    let trimmed = text.trim();
+    let Some(rest) = trimmed.strip_prefix("```") else {
+        return trimmed.to_string();
+    };
+    let Some(body) = rest.strip_suffix("```") else {
+        return trimmed.to_string();
+    };
+    // Drop the info string (e.g. "json") on the opening line
+    let body = match body.split_once('\n') {
+        Some((info, body)) if !info.trim().contains(' ') => body,
+        _ => body,
+    };
+    body.trim().to_string()
+}
+
+fn is_kanji(c: char) -> bool {
+    matches!(c, '\u{4E00}'..='\u{9FFF}' | '\u{3400}'..='\u{4DBF}' | '\u{F900}'..='\u{FAFF}' | '々')
+}
+
+fn is_kana(c: char) -> bool {
+    matches!(c, '\u{3040}'..='\u{309F}' | '\u{30A0}'..='\u{30FF}')
+}
+
+/// `漢字[かんじ]` to ruby. The base is the run of kanji right before the brackets,
+/// so `私は日本[にほん]` only annotates 日本. A space before the base (Anki's
+/// separator) is dropped. Brackets holding anything but kana are left alone.
+fn furigana_to_ruby(text: &str) -> String {
+    let mut out = String::new();
+    let mut rest = text;
+
+    while let Some(open) = rest.find('[') {
+        let Some(close) = rest[open..].find(']').map(|i| open + i) else {
+            break;
+        };
+        let before = &rest[..open];
+        let reading = &rest[open + 1..close];
+        let base_start = before
+            .char_indices()
+            .rev()
+            .take_while(|(_, c)| is_kanji(*c))
+            .last()
+            .map(|(i, _)| i);
+
+        match base_start {
+            Some(start) if !reading.is_empty() && reading.chars().all(is_kana) => {
+                let prefix = &before[..start];
+                out.push_str(prefix.strip_suffix(' ').unwrap_or(prefix));
+                out.push_str(&format!(
+                    "<ruby>{}<rt>{}</rt></ruby>",
+                    &before[start..],
+                    reading
+                ));
+            }
+            _ => out.push_str(&rest[..=close]),
+        }
+        rest = &rest[close + 1..];
+    }
+
+    out.push_str(rest);
+    out
+}
+
+/// Ruby markup to Anki's `漢字[かんじ]` notation, adding a separating space where needed.
+fn ruby_to_furigana(text: &str) -> String {
+    let mut out = String::new();
+    let mut rest = text;
+
+    while let Some(start) = rest.find("<ruby>") {
+        let Some(end) = rest[start..].find("</ruby>").map(|i| start + i) else {
+            break;
+        };
+        out.push_str(&rest[..start]);
+
+        let mut inner = strip_rp(&rest[start + "<ruby>".len()..end]);
+        while let Some(rt) = inner.find("<rt>") {
+            let base = &inner[..rt];
+            let after = &inner[rt + "<rt>".len()..];
+            let (reading, remainder) = after.split_once("</rt>").unwrap_or((after, ""));
+
+            if out
+                .chars()
+                .last()
+                .is_some_and(|c| !c.is_whitespace() && c != ']' && c != '>')
+            {
+                out.push(' ');
+            }
+            out.push_str(&format!("{}[{}]", base, reading));
+            inner = remainder.to_string();
+        }
+        out.push_str(&inner);
+        rest = &rest[end + "</ruby>".len()..];
+    }
+
+    out.push_str(rest);
+    out
+}
+
+/// Remove `<rp>…</rp>` fallback parentheses.
+fn strip_rp(text: &str) -> String {
+    let mut out = String::new();
+    let mut rest = text;
+    while let Some(start) = rest.find("<rp>") {
+        out.push_str(&rest[..start]);
+        rest = match rest[start..].find("</rp>") {
+            Some(end) => &rest[start + end + "</rp>".len()..],
+            None => "",
+        };
+    }
+    out.push_str(rest);
+    out
+}
@@ -0,0 +1,146 @@
+use std::collections::HashMap;
+
+use strsim::sorensen_dice;
+
+/// Share of the exclusion budget reserved for the most recent items.
+const RECENT_SHARE: f32 = 0.5;
+/// How many groups to name when summarising omitted items.
+const MAX_SUMMARY_GROUPS: usize = 12;
+
+/// Rough token estimate: ~4 characters per token for ASCII text, one token per
+/// character for everything else (kana and kanji are usually 1+ tokens each).
+pub fn estimate_tokens(text: &str) -> usize {
+    let (ascii, other): (usize, usize) =
+        text.chars().fold(
+            (0, 0),
+            |(a, o), c| if c.is_ascii() { (a + 1, o) } else { (a, o + 1) },
+        );
+    ascii.div_ceil(4) + other
+}
+
+/// The part of the history that fits in the prompt, plus a summary of the rest.
+pub struct ExclusionList {
+    pub listed: Vec<String>,
+    pub omitted: usize,
+    pub summary: Option<String>,
+}
+
+impl ExclusionList {
+    /// Pick exclusions that fit in `budget` tokens: the most recent items first,
+    /// then the ones most similar to the topic. Everything else is summarised by the
+    /// group `groups` puts it in.
+    pub fn select(
+        used: &[String],
+        groups: &HashMap<String, String>,
+        topic: &str,
+        budget: usize,
+    ) -> Self {
+        let cost = |item: &String| estimate_tokens(item) + 1;
+
+        if used.iter().map(cost).sum::<usize>() <= budget {
+            return Self {
+                listed: used.to_vec(),
+                omitted: 0,
+                summary: None,
+            };
+        }
+
+        let mut keep = vec![false; used.len()];
+        let mut spent = 0;
+
+        // Most recent first
+        let recent_budget = (budget as f32 * RECENT_SHARE) as usize;
+        for (i, item) in used.iter().enumerate().rev() {
+            if spent + cost(item) > recent_budget {
+                break;
+            }
+            keep[i] = true;
+            spent += cost(item);
+        }
+
+        // Then the most relevant of the remaining ones
+        let topic = topic.to_lowercase();
+        let mut ranked: Vec<(usize, f64)> = used
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| !keep[*i])
+            .map(|(i, item)| (i, sorensen_dice(&topic, &item.to_lowercase())))
+            .collect();
+        ranked.sort_by(|a, b| b.1.total_cmp(&a.1).then(b.0.cmp(&a.0)));
+
+        for (i, _) in ranked {
+            if spent + cost(&used[i]) > budget {
+                continue;
+            }
+            keep[i] = true;
+            spent += cost(&used[i]);
+        }
+
+        let listed: Vec<String> = used
+            .iter()
+            .zip(&keep)
+            .filter(|(_, k)| **k)
+            .map(|(item, _)| item.clone())
+            .collect();
+        let omitted: Vec<&String> = used
+            .iter()
+            .zip(&keep)
+            .filter(|(_, k)| !**k)
+            .map(|(item, _)| item)
+            .collect();
+
+        Self {
+            listed,
+            omitted: omitted.len(),
+            summary: Some(summarize(&omitted, groups)),
+        }
+    }
+
+    pub fn render(&self) -> String {
+        let mut out = if self.listed.is_empty() {
+            "none yet".to_string()
+        } else {
+            self.listed.join(", ")
+        };
+
+        if let Some(summary) = &self.summary {
+            out.push_str(&format!(
+                "\n...plus {} earlier items not listed here, by what they were generated for: {}",
+                self.omitted, summary
+            ));
+        }
+
+        out
+    }
+}
+
+/// Count items per group, largest first, e.g. `"JLPT N3 grammar" (12), deck "Japanese"
+/// (5), other (2)`. Items without a group, and groups past the first few, are "other".
+fn summarize(items: &[&String], groups: &HashMap<String, String>) -> String {
+    let mut counts: HashMap<&str, usize> = HashMap::new();
+    let mut other = 0;
+    for item in items {
+        match groups.get(item.as_str()) {
+            Some(group) => *counts.entry(group).or_default() += 1,
+            None => other += 1,
+        }
+    }
+
+    let mut counts: Vec<(&str, usize)> = counts.into_iter().collect();
+    counts.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+    other += counts
+        .iter()
+        .skip(MAX_SUMMARY_GROUPS)
+        .map(|(_, n)| n)
+        .sum::<usize>();
+
+    let mut parts: Vec<String> = counts
+        .iter()
+        .take(MAX_SUMMARY_GROUPS)
+        .map(|(group, n)| format!("{} ({})", group, n))
+        .collect();
+    if other > 0 {
+        parts.push(format!("other ({})", other));
+    }
+    parts.join(", ")
+}
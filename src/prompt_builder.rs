@@ -1,4 +1,7 @@
-use crate::types::CardRequest;
+use std::collections::HashMap;
+
+use crate::prompt_budget::{ExclusionList, estimate_tokens};
+use crate::types::{CardFields, CardRequest, ChatMessage};
 
 const SYSTEM_PREAMBLE_STRICT: &str = "\
 You are an expert language learning flashcard generator for Anki. \
@@ -26,6 +29,14 @@ Rules you MUST follow:
 6. For vocabulary: include the word, reading, meaning, part of speech, and a contextual example sentence when relevant.
 7. Keep content concise but complete — only include fields that serve the learner for this specific topic.";
 
+const REVIEW_PREAMBLE: &str = "\
+You are a strict reviewer of language learning flashcards for Anki. \
+You check generated cards for factual errors and unnatural language before a learner studies them.
+
+Grade the card against the rubric on a scale from 0 (unusable) to 10 (perfect). \
+List every concrete problem you find as a short sentence; list none if the card is correct. \
+Output ONLY a JSON object with the keys \"score\" and \"issues\".";
+
 pub struct PromptBuilder;
 
 impl PromptBuilder {
@@ -37,7 +48,7 @@ impl PromptBuilder {
             .join(", ")
     }
 
-    pub fn build(req: &CardRequest) -> String {
+    fn preamble_and_instruction(req: &CardRequest) -> (&'static str, String) {
         let fields_list = Self::format_fields(&req.fields);
         let (preamble, field_instruction) = if req.optional_fields {
             (
@@ -52,59 +63,141 @@ impl PromptBuilder {
                  Respond with a single JSON object using exactly those keys. Every value must be a non-empty string with real content.",
             )
         };
+        let instruction = field_instruction.replace("{fields}", &fields_list);
+        (preamble, instruction)
+    }
+
+    pub fn build(req: &CardRequest) -> Vec<ChatMessage> {
+        let (preamble, instruction) = Self::preamble_and_instruction(req);
 
-        format!(
-            "{preamble}\n\n\
-             ---\n\
-             Task: Generate a flashcard.\n\
+        let source = match req.images.len() {
+            0 => "",
+            1 => "Source: the attached image. Base the card on what it shows.\n",
+            _ => "Source: the attached images. Base the card on what they show.\n",
+        };
+        let task = format!(
+            "Task: Generate a flashcard.\n\
              Topic: {description}\n\
+             {source}\
              Note type: {note_type}\n\
              {instruction}\n\
              Now generate the JSON:",
-            preamble = preamble,
             description = req.description,
+            source = source,
             note_type = req.note_type,
-            instruction = field_instruction.replace("{fields}", &fields_list),
-        )
-    }
+            instruction = instruction,
+        );
 
-    pub fn build_next(req: &CardRequest, used: &[String]) -> String {
-        let fields_list = Self::format_fields(&req.fields);
+        vec![ChatMessage::system(preamble), ChatMessage::user(task)]
+    }
 
-        let used_list = if used.is_empty() {
-            "none yet".to_string()
-        } else {
-            used.join(", ")
-        };
+    /// Build a prompt for the next item in a series. The "already generated" list is
+    /// trimmed so the whole prompt stays within `max_prompt_tokens`; the items left out
+    /// are summarised by their `groups`.
+    pub fn build_next(
+        req: &CardRequest,
+        used: &[String],
+        groups: &HashMap<String, String>,
+        max_prompt_tokens: usize,
+    ) -> Vec<ChatMessage> {
+        let (preamble, instruction) = Self::preamble_and_instruction(req);
 
-        let (preamble, field_instruction) = if req.optional_fields {
-            (
-                SYSTEM_PREAMBLE_OPTIONAL,
-                "Available JSON keys (include only relevant ones): [{fields}]\n\n\
-                 Respond with a single JSON object. Include only the fields that are relevant and have meaningful content for this topic.",
-            )
-        } else {
-            (
-                SYSTEM_PREAMBLE_STRICT,
-                "Required JSON keys: [{fields}]\n\n\
-                 Respond with a single JSON object using exactly those keys. Every value must be a non-empty string with real content.",
+        let render = |used: &str| {
+            format!(
+                "Task: Generate the NEXT item in a series. Pick one that has NOT been generated yet.\n\
+                 Topic: {description}\n\
+                 Note type: {note_type}\n\
+                 {instruction}\n\n\
+                 Already generated (DO NOT repeat any of these):\n{used}\n\n\
+                 Now generate the JSON for the next item:",
+                description = req.description,
+                note_type = req.note_type,
+                instruction = instruction,
+                used = used,
             )
         };
 
-        format!(
-            "{preamble}\n\n\
-             ---\n\
-             Task: Generate the NEXT item in a series. Pick one that has NOT been generated yet.\n\
-             Topic: {description}\n\
+        let fixed_tokens = estimate_tokens(preamble) + estimate_tokens(&render(""));
+        let budget = max_prompt_tokens.saturating_sub(fixed_tokens);
+        let exclusions = ExclusionList::select(used, groups, &req.description, budget);
+        if exclusions.omitted > 0 {
+            eprintln!(
+                "  Prompt budget: listing {} of {} history items ({} summarised)",
+                exclusions.listed.len(),
+                used.len(),
+                exclusions.omitted
+            );
+        }
+
+        vec![
+            ChatMessage::system(preamble),
+            ChatMessage::user(render(&exclusions.render())),
+        ]
+    }
+
+    /// Ask a reviewer to grade a generated card against the rubric.
+    pub fn build_review(req: &CardRequest, fields: &CardFields, rubric: &str) -> Vec<ChatMessage> {
+        let card = req
+            .fields
+            .iter()
+            .filter_map(|name| fields.get(name).map(|value| format!("{}: {}", name, value)))
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        let task = format!(
+            "Rubric:\n{rubric}\n\n\
+             Card topic: {description}\n\
              Note type: {note_type}\n\
-             {instruction}\n\n\
-             Already generated (DO NOT repeat any of these):\n{used}\n\n\
-             Now generate the JSON for the next item:",
-            preamble = preamble,
+             Card fields:\n{card}\n\n\
+             Now grade the card as JSON:",
+            rubric = rubric,
             description = req.description,
             note_type = req.note_type,
-            instruction = field_instruction.replace("{fields}", &fields_list),
-            used = used_list,
-        )
+            card = card,
+        );
+
+        vec![
+            ChatMessage::system(REVIEW_PREAMBLE),
+            ChatMessage::user(task),
+        ]
+    }
+
+    /// Regenerate some fields of an existing note. The other fields are given as
+    /// fixed context; `current` holds the values being replaced.
+    pub fn build_regen(
+        req: &CardRequest,
+        fixed: &[(String, String)],
+        current: &CardFields,
+    ) -> Vec<ChatMessage> {
+        let (preamble, instruction) = Self::preamble_and_instruction(req);
+        let render = |pairs: Vec<(&String, &String)>| {
+            pairs
+                .into_iter()
+                .map(|(name, value)| format!("{}: {}", name, value))
+                .collect::<Vec<_>>()
+                .join("\n")
+        };
+        let fixed = render(fixed.iter().map(|(k, v)| (k, v)).collect());
+        let current = render(
+            req.fields
+                .iter()
+                .filter_map(|f| current.get(f).map(|v| (f, v)))
+                .collect(),
+        );
+
+        let task = format!(
+            "Task: Rewrite some fields of an existing flashcard.\n\
+             Note type: {note_type}\n\
+             Fixed fields (context only, keep the new content consistent with them):\n{fixed}\n\n\
+             Current values to replace (write new, better content):\n{current}\n\n\
+             {instruction}\n\
+             Now generate the JSON:",
+            note_type = req.note_type,
+            fixed = fixed,
+            current = current,
+            instruction = instruction,
+        );
+
+        vec![ChatMessage::system(preamble), ChatMessage::user(task)]
     }
 }
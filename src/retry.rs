@@ -0,0 +1,77 @@
+//! Timeouts and exponential-backoff retries shared by the HTTP clients.
+
+use std::fmt::Display;
+use std::time::Duration;
+
+use crate::config::HttpConfig;
+use crate::errors::AppError;
+
+/// A client with the configured timeouts. A timeout of 0 means none.
+pub fn client(config: &HttpConfig) -> Result<reqwest::Client, AppError> {
+    let secs = |s: f64| (s > 0.0).then(|| Duration::from_secs_f64(s));
+    let mut builder = reqwest::Client::builder();
+    if let Some(timeout) = secs(config.connect_timeout_secs) {
+        builder = builder.connect_timeout(timeout);
+    }
+    if let Some(timeout) = secs(config.read_timeout_secs) {
+        builder = builder.read_timeout(timeout);
+    }
+    if let Some(timeout) = secs(config.total_timeout_secs) {
+        builder = builder.timeout(timeout);
+    }
+    Ok(builder.build()?)
+}
+
+/// How often, and how patiently, to repeat a request that failed transiently.
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    max_retries: u32,
+    initial_backoff: Duration,
+    max_backoff: Duration,
+}
+
+impl RetryPolicy {
+    pub fn none() -> Self {
+        Self {
+            max_retries: 0,
+            initial_backoff: Duration::ZERO,
+            max_backoff: Duration::ZERO,
+        }
+    }
+
+    pub fn from_config(config: &HttpConfig) -> Self {
+        Self {
+            max_retries: config.max_retries,
+            initial_backoff: Duration::from_millis(config.retry_backoff_ms),
+            max_backoff: Duration::from_millis(config.max_backoff_ms),
+        }
+    }
+
+    /// Delay before retry number `retry` (1-based): doubling from the initial backoff,
+    /// capped at the maximum.
+    pub fn backoff(&self, retry: u32) -> Duration {
+        let factor = 2u32.saturating_pow(retry.saturating_sub(1));
+        self.initial_backoff
+            .saturating_mul(factor)
+            .min(self.max_backoff)
+    }
+
+    /// After `failures` failed attempts, wait before the next one. Returns false once
+    /// the retries are used up.
+    pub async fn wait(&self, failures: u32, service: &str, reason: &impl Display) -> bool {
+        if failures > self.max_retries {
+            return false;
+        }
+        let delay = self.backoff(failures);
+        eprintln!(
+            "  {} request failed ({}), retrying in {:.1}s ({}/{})",
+            service,
+            reason,
+            delay.as_secs_f64(),
+            failures,
+            self.max_retries
+        );
+        tokio::time::sleep(delay).await;
+        true
+    }
+}
@@ -0,0 +1,128 @@
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::config::{ReviewAction, ReviewConfig};
+use crate::errors::AppError;
+use crate::model_client::OllamaClient;
+use crate::prompt_builder::PromptBuilder;
+use crate::types::{CardFields, CardRequest};
+
+/// A reviewer's verdict on one card.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Review {
+    pub score: f32,
+    #[serde(default)]
+    pub issues: Vec<String>,
+}
+
+/// A card held back for manual review, as written to the review queue.
+#[derive(Serialize, Deserialize)]
+struct HeldCard {
+    held_at: DateTime<Utc>,
+    deck: String,
+    note_type: String,
+    item: String,
+    fields: CardFields,
+    score: f32,
+    issues: Vec<String>,
+}
+
+/// Grades generated cards with a second model against a rubric.
+pub struct Reviewer {
+    model: OllamaClient,
+    config: ReviewConfig,
+    queue_path: PathBuf,
+    queue: Mutex<()>,
+}
+
+impl Reviewer {
+    pub fn new(model: OllamaClient, config: ReviewConfig) -> Self {
+        Self {
+            model,
+            queue_path: PathBuf::from(&config.queue_path),
+            config,
+            queue: Mutex::new(()),
+        }
+    }
+
+    pub fn action(&self) -> ReviewAction {
+        self.config.action
+    }
+
+    pub fn max_attempts(&self) -> u32 {
+        self.config.max_attempts.max(1)
+    }
+
+    pub fn passes(&self, review: &Review) -> bool {
+        review.score >= self.config.threshold
+    }
+
+    pub fn threshold(&self) -> f32 {
+        self.config.threshold
+    }
+
+    fn schema() -> serde_json::Value {
+        json!({
+            "type": "object",
+            "properties": {
+                "score": { "type": "number", "minimum": 0, "maximum": 10 },
+                "issues": { "type": "array", "items": { "type": "string" } }
+            },
+            "required": ["score", "issues"]
+        })
+    }
+
+    pub async fn review(&self, req: &CardRequest, fields: &CardFields) -> Result<Review, AppError> {
+        let messages = PromptBuilder::build_review(req, fields, &self.config.rubric);
+        let review: Review = self.model.generate_json(&messages, Self::schema()).await?;
+        if !review.score.is_finite() {
+            return Err(AppError::Model(format!(
+                "Reviewer returned an invalid score: {}",
+                review.score
+            )));
+        }
+        Ok(review)
+    }
+
+    /// Append a card to the review queue file.
+    pub fn hold(
+        &self,
+        req: &CardRequest,
+        item: &str,
+        fields: &CardFields,
+        review: &Review,
+    ) -> Result<(), AppError> {
+        let _guard = self.queue.lock().unwrap();
+
+        let mut held: Vec<HeldCard> = match fs::read_to_string(&self.queue_path) {
+            Ok(data) if !data.trim().is_empty() => serde_json::from_str(&data)?,
+            Ok(_) => Vec::new(),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e.into()),
+        };
+        held.push(HeldCard {
+            held_at: Utc::now(),
+            deck: req.deck.clone(),
+            note_type: req.note_type.clone(),
+            item: item.to_string(),
+            fields: fields.clone(),
+            score: review.score,
+            issues: review.issues.clone(),
+        });
+
+        if let Some(parent) = self.queue_path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(&self.queue_path, serde_json::to_string_pretty(&held)?)?;
+        Ok(())
+    }
+
+    pub fn queue_path(&self) -> &PathBuf {
+        &self.queue_path
+    }
+}
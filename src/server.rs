@@ -0,0 +1,341 @@
+//! Local JSON HTTP API over the engine, for scripts and dashboards that
+//! shouldn't shell out to the CLI.
+
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tokio::net::TcpListener;
+
+use crate::config::Config;
+use crate::engine::Engine;
+use crate::errors::AppError;
+use crate::image::{self, SourceImage};
+use crate::types::{BatchReport, CardRequest};
+
+/// Deck, note type and fields for a request. Anything omitted comes from config.
+#[derive(Deserialize, Default)]
+struct CardParams {
+    deck: Option<String>,
+    note_type: Option<String>,
+    #[serde(default)]
+    fields: Vec<String>,
+    optional_fields: Option<bool>,
+}
+
+#[derive(Deserialize)]
+struct GenerateBody {
+    description: String,
+    /// Base64-encoded PNG, JPEG, GIF or WebP images (`/generate` only).
+    #[serde(default)]
+    images: Vec<String>,
+    #[serde(flatten)]
+    card: CardParams,
+}
+
+#[derive(Deserialize)]
+struct BatchBody {
+    items: Vec<String>,
+    #[serde(flatten)]
+    card: CardParams,
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum JobStatus {
+    Queued,
+    Running,
+    Completed,
+    Failed,
+}
+
+/// A batch running in the background. Polled via `GET /jobs/{id}`.
+#[derive(Serialize, Debug, Clone)]
+pub struct Job {
+    pub id: u64,
+    pub status: JobStatus,
+    pub items: usize,
+    pub created_at: DateTime<Utc>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub finished_at: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub report: Option<BatchReport>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+pub struct AppState {
+    engine: Engine,
+    config: Config,
+    jobs: Mutex<BTreeMap<u64, Job>>,
+    /// Generation runs one request at a time, since history is read-modify-write.
+    work: tokio::sync::Mutex<()>,
+}
+
+impl AppState {
+    pub fn new(engine: Engine, config: Config) -> Self {
+        Self {
+            engine,
+            config,
+            jobs: Mutex::new(BTreeMap::new()),
+            work: tokio::sync::Mutex::new(()),
+        }
+    }
+
+    /// Fill in a card request from request parameters and config.
+    async fn card_request(
+        &self,
+        description: String,
+        params: CardParams,
+    ) -> Result<CardRequest, ApiError> {
+        let deck = params
+            .deck
+            .or_else(|| self.config.deck.clone())
+            .ok_or_else(|| {
+                ApiError::bad_request("deck is required (in the request or config file)")
+            })?;
+        let note_type = params
+            .note_type
+            .unwrap_or_else(|| self.config.note_type.clone());
+        let fields = if !params.fields.is_empty() {
+            params.fields
+        } else if !self.config.fields.is_empty() {
+            self.config.fields.clone()
+        } else {
+            self.engine.note_type_fields(&note_type).await?
+        };
+
+        Ok(CardRequest {
+            description,
+            fields,
+            note_type,
+            deck,
+            optional_fields: params
+                .optional_fields
+                .unwrap_or(self.config.optional_fields),
+            images: Vec::new(),
+        })
+    }
+
+    fn update_job(&self, id: u64, update: impl FnOnce(&mut Job)) {
+        if let Some(job) = self.jobs.lock().unwrap().get_mut(&id) {
+            update(job);
+        }
+    }
+}
+
+/// An error response: `{"error": "..."}` with a status code.
+struct ApiError {
+    status: StatusCode,
+    message: String,
+    hint: Option<String>,
+}
+
+impl ApiError {
+    fn bad_request(message: impl Into<String>) -> Self {
+        Self {
+            status: StatusCode::BAD_REQUEST,
+            message: message.into(),
+            hint: None,
+        }
+    }
+
+    fn not_found(message: impl Into<String>) -> Self {
+        Self {
+            status: StatusCode::NOT_FOUND,
+            message: message.into(),
+            hint: None,
+        }
+    }
+}
+
+impl From<AppError> for ApiError {
+    fn from(e: AppError) -> Self {
+        let status = match e {
+            AppError::Duplicate(_) => StatusCode::CONFLICT,
+            AppError::Image(_) => StatusCode::BAD_REQUEST,
+            AppError::Validation(_) | AppError::HeldForReview(_) => {
+                StatusCode::UNPROCESSABLE_ENTITY
+            }
+            AppError::DeckNotFound(_)
+            | AppError::NoteTypeNotFound(_)
+            | AppError::FieldMismatch(_)
+            | AppError::ModelNotFound(_)
+            | AppError::NotFound(_) => StatusCode::NOT_FOUND,
+            AppError::Network(_)
+            | AppError::ConnectionRefused { .. }
+            | AppError::InvalidJson { .. }
+            | AppError::SchemaViolation(_) => StatusCode::BAD_GATEWAY,
+            AppError::Timeout { .. } => StatusCode::GATEWAY_TIMEOUT,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+        Self {
+            status,
+            message: e.to_string(),
+            hint: e.hint(),
+        }
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let mut body = json!({ "error": self.message });
+        if let Some(hint) = self.hint {
+            body["hint"] = json!(hint);
+        }
+        (self.status, Json(body)).into_response()
+    }
+}
+
+pub fn router(state: Arc<AppState>) -> Router {
+    Router::new()
+        .route("/check", get(check))
+        .route("/generate", post(generate))
+        .route("/next", post(next))
+        .route("/batch", post(batch))
+        .route("/jobs", get(list_jobs))
+        .route("/jobs/{id}", get(get_job))
+        .route("/history", get(history))
+        .with_state(state)
+}
+
+/// Serve the API until the process is stopped.
+pub async fn serve(engine: Engine, config: Config, host: &str, port: u16) -> std::io::Result<()> {
+    let listener = TcpListener::bind((host, port)).await?;
+    eprintln!(
+        "Serving the anki_gen API on http://{}",
+        listener.local_addr()?
+    );
+    let state = Arc::new(AppState::new(engine, config));
+    axum::serve(listener, router(state)).await
+}
+
+async fn check(State(state): State<Arc<AppState>>) -> Response {
+    let report = state.engine.check().await;
+    let status = if report.ok() {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    (status, Json(report)).into_response()
+}
+
+async fn generate(
+    State(state): State<Arc<AppState>>,
+    Json(body): Json<GenerateBody>,
+) -> Result<Response, ApiError> {
+    let images = body
+        .images
+        .iter()
+        .map(|data| SourceImage::decode(data))
+        .collect::<Result<Vec<_>, _>>()?;
+    let req = state.card_request(body.description, body.card).await?;
+    let _work = state.work.lock().await;
+    let card = state.engine.generate_with_images(&req, &images).await?;
+    Ok(Json(card).into_response())
+}
+
+async fn next(
+    State(state): State<Arc<AppState>>,
+    Json(body): Json<GenerateBody>,
+) -> Result<Response, ApiError> {
+    if !body.images.is_empty() {
+        return Err(ApiError::bad_request("images are only supported by /generate"));
+    }
+    let req = state.card_request(body.description, body.card).await?;
+    let _work = state.work.lock().await;
+    let card = state.engine.next(&req).await?;
+    Ok(Json(card).into_response())
+}
+
+/// Queue a batch and return its job immediately.
+async fn batch(
+    State(state): State<Arc<AppState>>,
+    Json(body): Json<BatchBody>,
+) -> Result<Response, ApiError> {
+    if body.items.is_empty() {
+        return Err(ApiError::bad_request("items must not be empty"));
+    }
+    // Image items would be read from the server's disk
+    if let Some(item) = body.items.iter().find(|i| image::parse_item(i).1.is_some()) {
+        return Err(ApiError::bad_request(format!(
+            "'{}' is an image path; send images base64-encoded to /generate",
+            item
+        )));
+    }
+    let req = state.card_request(String::new(), body.card).await?;
+
+    let job = {
+        let mut jobs = state.jobs.lock().unwrap();
+        let id = jobs.keys().next_back().map_or(1, |last| last + 1);
+        let job = Job {
+            id,
+            status: JobStatus::Queued,
+            items: body.items.len(),
+            created_at: Utc::now(),
+            finished_at: None,
+            report: None,
+            error: None,
+        };
+        jobs.insert(id, job.clone());
+        job
+    };
+
+    let id = job.id;
+    let items = body.items;
+    let worker = state.clone();
+    tokio::spawn(async move {
+        let _work = worker.work.lock().await;
+        worker.update_job(id, |job| job.status = JobStatus::Running);
+
+        let result = worker.engine.batch(&req, &items).await;
+        worker.update_job(id, |job| {
+            job.finished_at = Some(Utc::now());
+            match result {
+                Ok(report) => {
+                    job.status = JobStatus::Completed;
+                    job.report = Some(report);
+                }
+                Err(e) => {
+                    job.status = JobStatus::Failed;
+                    job.error = Some(e.to_string());
+                    if let AppError::BatchFailed(report) = e {
+                        job.report = Some(*report);
+                    }
+                }
+            }
+        });
+    });
+
+    Ok((StatusCode::ACCEPTED, Json(job)).into_response())
+}
+
+async fn list_jobs(State(state): State<Arc<AppState>>) -> Json<Vec<Job>> {
+    Json(state.jobs.lock().unwrap().values().cloned().collect())
+}
+
+async fn get_job(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<u64>,
+) -> Result<Json<Job>, ApiError> {
+    state
+        .jobs
+        .lock()
+        .unwrap()
+        .get(&id)
+        .cloned()
+        .map(Json)
+        .ok_or_else(|| ApiError::not_found(format!("No job with id {}", id)))
+}
+
+async fn history(State(state): State<Arc<AppState>>) -> Result<Response, ApiError> {
+    let history = state.engine.history()?;
+    Ok(Json(history).into_response())
+}
@@ -0,0 +1,66 @@
+//! Graceful interruption: the first SIGINT/SIGTERM asks running commands to stop
+//! after the current item, the second exits immediately.
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Exit code after an interrupted run, as shells report for SIGINT.
+pub const INTERRUPTED_EXIT_CODE: i32 = 130;
+
+/// Shared flag set once the user asked to stop.
+#[derive(Clone, Default)]
+pub struct Shutdown {
+    requested: Arc<AtomicBool>,
+}
+
+impl Shutdown {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn requested(&self) -> bool {
+        self.requested.load(Ordering::SeqCst)
+    }
+
+    /// Ask running commands to stop after the current item.
+    pub fn request(&self) {
+        self.requested.store(true, Ordering::SeqCst);
+    }
+
+    /// Handle SIGINT and SIGTERM in the background from now on.
+    pub fn listen(&self) {
+        let shutdown = self.clone();
+        tokio::spawn(async move {
+            loop {
+                if wait_for_signal().await.is_err() {
+                    return;
+                }
+                if shutdown.requested() {
+                    eprintln!("\nAborting.");
+                    std::process::exit(INTERRUPTED_EXIT_CODE);
+                }
+                shutdown.request();
+                eprintln!(
+                    "\nInterrupted: finishing the current item and saving history. \
+                     Press Ctrl-C again to abort immediately."
+                );
+            }
+        });
+    }
+}
+
+#[cfg(unix)]
+async fn wait_for_signal() -> std::io::Result<()> {
+    use tokio::signal::unix::{SignalKind, signal};
+
+    let mut terminate = signal(SignalKind::terminate())?;
+    tokio::select! {
+        result = tokio::signal::ctrl_c() => result,
+        _ = terminate.recv() => Ok(()),
+    }
+}
+
+#[cfg(not(unix))]
+async fn wait_for_signal() -> std::io::Result<()> {
+    tokio::signal::ctrl_c().await
+}
@@ -0,0 +1,48 @@
+//! Sibling notes: several notes projected from one generated card, so e.g. the
+//! JP→EN and EN→JP notes of a word share exactly the same meaning and example.
+
+use crate::field_map::FieldSource;
+use crate::types::{CardFields, CardRequest};
+
+/// A note mapping checked against Anki.
+pub struct Sibling {
+    pub deck: String,
+    pub note_type: String,
+    /// Note field (as spelled in Anki) to where its value comes from.
+    pub fields: Vec<(String, FieldSource)>,
+    /// All fields of the note type.
+    pub all_fields: Vec<String>,
+}
+
+impl Sibling {
+    /// The sibling's fields, filled from the generated card.
+    pub fn project(&self, card: &CardFields) -> CardFields {
+        self.fields
+            .iter()
+            .map(|(field, source)| (field.clone(), source.render(card)))
+            .collect()
+    }
+
+    /// A request naming this sibling's deck and note type, for adding its note.
+    pub fn request(&self, item: &str) -> CardRequest {
+        CardRequest {
+            description: item.to_string(),
+            fields: self.fields.iter().map(|(field, _)| field.clone()).collect(),
+            note_type: self.note_type.clone(),
+            deck: self.deck.clone(),
+            optional_fields: true,
+            images: Vec::new(),
+        }
+    }
+}
+
+/// Add the generated keys the siblings need to the fields the model is asked for.
+pub fn extend_fields(fields: &mut Vec<String>, siblings: &[Sibling]) {
+    for (_, source) in siblings.iter().flat_map(|s| &s.fields) {
+        for key in source.keys() {
+            if !fields.contains(&key) {
+                fields.push(key);
+            }
+        }
+    }
+}
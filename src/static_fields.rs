@@ -0,0 +1,146 @@
+//! Field values that don't come from the model: fixed text with placeholders such as
+//! `{date}` or `{column:JLPT}`, and the output of shell commands (e.g. TTS).
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::path::Path;
+
+use chrono::Local;
+use serde::{Deserialize, Serialize};
+
+use crate::errors::AppError;
+use crate::field_map;
+use crate::types::CardFields;
+
+/// A value for a static field.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum StaticValue {
+    /// Text; `{date}`, `{date:%Y-%m}`, `{item}`, `{description}`, `{deck}`,
+    /// `{note_type}`, `{command}`, `{batch_file}` and `{column:NAME}` are filled in.
+    Text(String),
+    /// The trimmed stdout of a command run with `sh -c`. It gets the card through
+    /// `ANKI_GEN_*` environment variables rather than placeholders, so generated text
+    /// can't change the command.
+    Command { command: String },
+}
+
+/// What a static value can refer to.
+pub struct FieldContext<'a> {
+    /// `generate`, `next` or `batch`.
+    pub command: &'a str,
+    /// The item's name: the description, the `next` key value or the batch item.
+    pub item: &'a str,
+    pub description: &'a str,
+    pub deck: &'a str,
+    pub note_type: &'a str,
+    pub batch_file: Option<&'a Path>,
+    /// Other columns of the batch row.
+    pub columns: &'a BTreeMap<String, String>,
+}
+
+impl FieldContext<'_> {
+    fn placeholder(&self, key: &str) -> Result<String, AppError> {
+        let value = match key.split_once(':') {
+            Some(("date", format)) => {
+                let mut date = String::new();
+                write!(date, "{}", Local::now().format(format)).map_err(|_| {
+                    AppError::Config(format!("Invalid date format '{}' in static field", format))
+                })?;
+                date
+            }
+            Some(("column", name)) => self.columns.get(name).cloned().unwrap_or_default(),
+            _ => match key {
+                "date" => Local::now().format("%Y-%m-%d").to_string(),
+                "item" => self.item.to_string(),
+                "description" => self.description.to_string(),
+                "deck" => self.deck.to_string(),
+                "note_type" => self.note_type.to_string(),
+                "command" => self.command.to_string(),
+                "batch_file" => self.batch_file_name(),
+                _ => {
+                    return Err(AppError::Config(format!(
+                        "Unknown placeholder '{{{}}}' in static field",
+                        key
+                    )));
+                }
+            },
+        };
+        Ok(value)
+    }
+
+    fn batch_file_name(&self) -> String {
+        self.batch_file
+            .and_then(|p| p.file_name())
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or_default()
+    }
+}
+
+/// The values of the static fields for one card. `card` holds the generated fields,
+/// which commands get as JSON in `ANKI_GEN_FIELDS`. Without `run_commands`, command
+/// fields are left empty.
+pub async fn compute(
+    fields: &BTreeMap<String, StaticValue>,
+    context: &FieldContext<'_>,
+    card: &CardFields,
+    run_commands: bool,
+) -> Result<CardFields, AppError> {
+    let mut values = CardFields::new();
+    for (field, value) in fields {
+        let value = match value {
+            StaticValue::Text(template) => {
+                let mut error = None;
+                let text = field_map::fill(template, |key| {
+                    context.placeholder(key).unwrap_or_else(|e| {
+                        error.get_or_insert(e);
+                        String::new()
+                    })
+                });
+                if let Some(e) = error {
+                    return Err(e);
+                }
+                text
+            }
+            StaticValue::Command { command } if run_commands => {
+                run(field, command, context, card).await?
+            }
+            StaticValue::Command { .. } => String::new(),
+        };
+        values.insert(field.clone(), value);
+    }
+    Ok(values)
+}
+
+async fn run(
+    field: &str,
+    command: &str,
+    context: &FieldContext<'_>,
+    card: &CardFields,
+) -> Result<String, AppError> {
+    let output = tokio::process::Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .env("ANKI_GEN_FIELD", field)
+        .env("ANKI_GEN_ITEM", context.item)
+        .env("ANKI_GEN_DESCRIPTION", context.description)
+        .env("ANKI_GEN_DECK", context.deck)
+        .env("ANKI_GEN_NOTE_TYPE", context.note_type)
+        .env("ANKI_GEN_COMMAND", context.command)
+        .env("ANKI_GEN_BATCH_FILE", context.batch_file_name())
+        .env("ANKI_GEN_COLUMNS", serde_json::to_string(context.columns)?)
+        .env("ANKI_GEN_FIELDS", serde_json::to_string(card)?)
+        .output()
+        .await
+        .map_err(|e| AppError::StaticField(format!("could not run '{}': {}", command, e)))?;
+    if !output.status.success() {
+        return Err(AppError::StaticField(format!(
+            "'{}' for field '{}' failed ({}): {}",
+            command,
+            field,
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
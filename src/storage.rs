@@ -1,9 +1,13 @@
-use std::fs;
-use std::path::PathBuf;
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
 
 use crate::errors::AppError;
-use crate::types::StoredHistory;
+use crate::types::{JournalEntry, StoredHistory};
 
+/// History on disk, shared safely between concurrent `anki_gen` processes: writes go
+/// to a temp file that replaces the history atomically, read-modify-write cycles hold
+/// an advisory lock, and the previous version is kept as `.bak` to recover from.
 pub struct FileStorage {
     path: PathBuf,
 }
@@ -13,11 +17,64 @@ impl FileStorage {
         Self { path }
     }
 
+    fn sibling(&self, suffix: &str) -> PathBuf {
+        let mut name = self.path.file_name().unwrap_or_default().to_os_string();
+        name.push(suffix);
+        self.path.with_file_name(name)
+    }
+
+    fn backup_path(&self) -> PathBuf {
+        self.sibling(".bak")
+    }
+
+    /// The batch journal, one JSON line per item started or finished.
+    pub fn journal_path(&self) -> PathBuf {
+        self.sibling(".journal.jsonl")
+    }
+
+    /// Append a line to the batch journal. Appends don't rewrite earlier lines, so no
+    /// lock is needed and an interrupted run keeps everything written so far.
+    pub fn append_journal(&self, entry: &JournalEntry) -> Result<(), AppError> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut line = serde_json::to_string(entry)?;
+        line.push('\n');
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.journal_path())?;
+        file.write_all(line.as_bytes())?;
+        file.sync_data()?;
+        Ok(())
+    }
+
+    /// Falls back to the `.bak` copy if the history file is corrupt.
     pub fn load_history(&self) -> Result<StoredHistory, AppError> {
-        if !self.path.exists() {
+        match Self::read(&self.path) {
+            Ok(history) => Ok(history),
+            Err(e) => {
+                let backup = self.backup_path();
+                if !backup.exists() {
+                    return Err(e);
+                }
+                let history = Self::read(&backup)?;
+                eprintln!(
+                    "Warning: {} is unreadable ({}), using backup {}",
+                    self.path.display(),
+                    e,
+                    backup.display()
+                );
+                Ok(history)
+            }
+        }
+    }
+
+    fn read(path: &Path) -> Result<StoredHistory, AppError> {
+        if !path.exists() {
             return Ok(StoredHistory::default());
         }
-        let data = fs::read_to_string(&self.path)?;
+        let data = fs::read_to_string(path)?;
         if data.trim().is_empty() {
             return Ok(StoredHistory::default());
         }
@@ -25,12 +82,51 @@ impl FileStorage {
         Ok(history)
     }
 
-    pub fn save_history(&self, history: &StoredHistory) -> Result<(), AppError> {
+    /// Apply `change` to the latest history on disk and save it, holding the lock so
+    /// other processes' changes in between aren't lost.
+    pub fn update<T>(&self, change: impl FnOnce(&mut StoredHistory) -> T) -> Result<T, AppError> {
+        let _lock = self.lock()?;
+        let mut history = self.load_history()?;
+        let result = change(&mut history);
+        self.write(&history)?;
+        Ok(result)
+    }
+
+    /// Exclusive advisory lock on a `.lock` file next to the history. The history
+    /// itself is replaced on every write, so it can't carry the lock. Released on drop.
+    fn lock(&self) -> Result<File, AppError> {
         if let Some(parent) = self.path.parent() {
             fs::create_dir_all(parent)?;
         }
+        let file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(self.sibling(".lock"))?;
+        file.lock()?;
+        Ok(file)
+    }
+
+    /// Write to a temp file, keep the current version as `.bak`, then rename the temp
+    /// file over the history, so readers never see a partial file.
+    fn write(&self, history: &StoredHistory) -> Result<(), AppError> {
         let data = serde_json::to_string_pretty(history)?;
-        fs::write(&self.path, data)?;
+        let tmp = self.sibling(&format!(".tmp.{}", std::process::id()));
+        {
+            let mut file = File::create(&tmp)?;
+            file.write_all(data.as_bytes())?;
+            file.sync_all()?;
+        }
+
+        if self.path.exists() && Self::read(&self.path).is_ok() {
+            let backup = self.backup_path();
+            let _ = fs::remove_file(&backup);
+            // A hard link keeps the old version without copying; not every filesystem has them
+            if fs::hard_link(&self.path, &backup).is_err() {
+                fs::copy(&self.path, &backup)?;
+            }
+        }
+        fs::rename(&tmp, &self.path)?;
         Ok(())
     }
 }
@@ -0,0 +1,64 @@
+//! "Did you mean" suggestions for deck, note type and field names.
+
+use strsim::normalized_damerau_levenshtein;
+use unicode_normalization::UnicodeNormalization;
+
+/// Similarity below which a name isn't worth suggesting.
+const MIN_SIMILARITY: f64 = 0.6;
+const MAX_SUGGESTIONS: usize = 3;
+
+/// NFKC-normalised, lowercased and trimmed, so full-width letters, composed
+/// characters and case don't matter.
+pub fn normalize(name: &str) -> String {
+    name.trim().nfkc().flat_map(char::to_lowercase).collect()
+}
+
+/// The single candidate equal to `name` after normalisation, if there is exactly one.
+pub fn resolve<'a>(name: &str, candidates: &'a [String]) -> Option<&'a String> {
+    let key = normalize(name);
+    let mut matches = candidates.iter().filter(|c| normalize(c) == key);
+    match (matches.next(), matches.next()) {
+        (Some(only), None) => Some(only),
+        _ => None,
+    }
+}
+
+/// Up to three candidates closest to `name`, best first.
+pub fn closest<'a>(name: &str, candidates: &'a [String]) -> Vec<&'a String> {
+    let key = normalize(name);
+    let mut scored: Vec<(f64, &String)> = candidates
+        .iter()
+        .map(|c| {
+            let candidate = normalize(c);
+            let mut score = normalized_damerau_levenshtein(&key, &candidate);
+            // "Grammar" for "Japanese::Grammar" is a good guess despite the distance
+            if !key.is_empty() && (candidate.contains(&key) || key.contains(&candidate)) {
+                score = score.max(0.8);
+            }
+            (score, c)
+        })
+        .filter(|(score, _)| *score >= MIN_SIMILARITY)
+        .collect();
+    scored.sort_by(|a, b| b.0.total_cmp(&a.0));
+    scored
+        .into_iter()
+        .take(MAX_SUGGESTIONS)
+        .map(|(_, c)| c)
+        .collect()
+}
+
+/// A sentence pointing at the closest candidates, e.g. " — did you mean 'Japanese'?".
+pub fn did_you_mean(name: &str, candidates: &[String]) -> String {
+    let closest = closest(name, candidates);
+    match closest.as_slice() {
+        [] => String::new(),
+        [only] => format!(" — did you mean '{}'?", only),
+        many => format!(
+            " — did you mean one of {}?",
+            many.iter()
+                .map(|c| format!("'{}'", c))
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+    }
+}
@@ -0,0 +1,107 @@
+use std::sync::Arc;
+
+use serde_json::json;
+
+use super::{DECK, Harness, NOTE_TYPE};
+use crate::cassette::Cassette;
+use crate::config::DedupAction;
+use crate::dedup::SemanticDedup;
+use crate::errors::AppError;
+
+#[tokio::test]
+async fn replay_serves_recorded_responses_offline() {
+    let mut h = Harness::new().await;
+    let path = h.dir.path().join("cassette.json");
+    h.ollama.reply_json(json!({
+        "Grammar": "ておく",
+        "Meaning": "do in advance",
+        "Example": "予約しておく。",
+    }));
+
+    let recorder = h
+        .model()
+        .with_cassette(Arc::new(Cassette::record(path.clone()).unwrap()));
+    h.engine_with(recorder)
+        .generate(&h.request("ておく"))
+        .await
+        .unwrap();
+    assert_eq!(h.anki.notes().len(), 1);
+
+    // Nothing listens here: every response must come from the cassette
+    h.config.ollama_url = "http://127.0.0.1:9".to_string();
+    h.config.dry_run = true;
+    let replayer = h
+        .model()
+        .with_cassette(Arc::new(Cassette::replay(path.clone()).unwrap()));
+    h.engine_with(replayer)
+        .generate(&h.request("ておく"))
+        .await
+        .unwrap();
+
+    assert_eq!(h.anki.notes().len(), 1);
+    assert_eq!(h.history().used_items, vec!["ておく"]);
+}
+
+#[tokio::test]
+async fn replay_miss_is_an_error() {
+    let mut h = Harness::new().await;
+    let path = h.dir.path().join("cassette.json");
+    std::fs::write(&path, r#"{"entries":{}}"#).unwrap();
+    h.config.dry_run = true;
+
+    let replayer = h
+        .model()
+        .with_cassette(Arc::new(Cassette::replay(path).unwrap()));
+    let err = h
+        .engine_with(replayer)
+        .generate(&h.request("ておく"))
+        .await
+        .unwrap_err();
+
+    assert!(matches!(err, AppError::Model(ref m) if m.contains("No cassette entry")));
+    assert!(h.ollama.generation_requests().is_empty());
+}
+
+#[tokio::test]
+async fn replay_serves_recorded_embeddings() {
+    let mut h = Harness::new().await;
+    let path = h.dir.path().join("cassette.json");
+    h.config.dedup.enabled = true;
+    h.config.dedup.action = DedupAction::Skip;
+    h.anki
+        .add_existing_note(DECK, NOTE_TYPE, &[("Grammar", "てしまう")]);
+    h.ollama.set_embedding("てしまう", &[1.0, 0.0, 0.1]);
+    h.ollama.set_embedding("ちゃう", &[0.98, 0.05, 0.12]);
+    h.ollama.reply_json(json!({
+        "Grammar": "ちゃう",
+        "Meaning": "end up doing",
+        "Example": "食べちゃう。",
+    }));
+
+    let recorder = h
+        .model()
+        .with_cassette(Arc::new(Cassette::record(path.clone()).unwrap()));
+    let err = h
+        .engine_with(recorder)
+        .with_dedup(SemanticDedup::new(h.config.dedup.clone()).unwrap())
+        .generate(&h.request("ちゃう"))
+        .await
+        .unwrap_err();
+    assert!(matches!(err, AppError::Duplicate(_)));
+
+    // Neither Ollama nor the embedding cache may answer: only the cassette can
+    h.config.ollama_url = "http://127.0.0.1:9".to_string();
+    h.config.dedup.cache_path = h.dir.path().join("fresh.json").display().to_string();
+    let replayer = h
+        .model()
+        .with_cassette(Arc::new(Cassette::replay(path).unwrap()));
+    let err = h
+        .engine_with(replayer)
+        .with_dedup(SemanticDedup::new(h.config.dedup.clone()).unwrap())
+        .generate(&h.request("ちゃう"))
+        .await
+        .unwrap_err();
+
+    assert!(matches!(err, AppError::Duplicate(ref m) if m.contains("てしまう")));
+    assert_eq!(h.anki.notes().len(), 1);
+}
@@ -0,0 +1,113 @@
+use serde_json::json;
+
+use super::{DECK, Harness, NOTE_TYPE, card};
+use crate::config::DuplicatePolicy;
+use crate::errors::AppError;
+use crate::types::NoteAction;
+
+fn existing(h: &Harness, deck: &str) -> u64 {
+    h.anki.add_existing_note(
+        deck,
+        NOTE_TYPE,
+        &[("Grammar", "ておく"), ("Meaning", "do in advance")],
+    )
+}
+
+fn add_note_options(h: &Harness) -> serde_json::Value {
+    let requests = h.anki.state().requests.clone();
+    requests
+        .iter()
+        .rfind(|r| r["action"] == "addNote")
+        .map(|r| r["params"]["note"]["options"].clone())
+        .unwrap()
+}
+
+#[tokio::test]
+async fn fail_is_the_default() {
+    let h = Harness::new().await;
+    existing(&h, DECK);
+    h.ollama.reply_json(card("ておく"));
+
+    let err = h.engine().generate(&h.request("ておく")).await.unwrap_err();
+
+    assert!(matches!(err, AppError::Duplicate(ref m) if m.contains("'ておく' already exists")));
+    assert_eq!(add_note_options(&h), json!({ "allowDuplicate": false }));
+}
+
+#[tokio::test]
+async fn skip_counts_separately_in_batch() {
+    let mut h = Harness::new().await;
+    h.config.duplicates.policy = DuplicatePolicy::Skip;
+    existing(&h, DECK);
+    h.ollama.reply_json(card("ておく"));
+    h.ollama.reply_json(card("ばかり"));
+    h.ollama.reply_content("not json");
+
+    let items = ["ておく", "ばかり", "ながら"].map(String::from);
+    let report = h.engine().batch(&h.request(""), &items).await.unwrap();
+
+    assert_eq!((report.succeeded, report.skipped, report.failed), (1, 1, 1));
+    assert_eq!(report.cards[0].action, NoteAction::Skipped);
+    assert_eq!(report.cards[0].note_id, None);
+    assert_eq!(h.anki.notes().len(), 2);
+}
+
+#[tokio::test]
+async fn allow_adds_the_duplicate() {
+    let mut h = Harness::new().await;
+    h.config.duplicates.policy = DuplicatePolicy::Allow;
+    existing(&h, DECK);
+    h.ollama.reply_json(card("ておく"));
+
+    h.engine().generate(&h.request("ておく")).await.unwrap();
+
+    assert_eq!(h.anki.notes().len(), 2);
+    assert_eq!(add_note_options(&h), json!({ "allowDuplicate": true }));
+}
+
+#[tokio::test]
+async fn allow_other_deck_scopes_the_check_to_the_deck() {
+    let mut h = Harness::new().await;
+    h.config.duplicates.policy = DuplicatePolicy::AllowOtherDeck;
+    h.config.duplicates.check_children = true;
+    h.anki.state().decks.push("Other".to_string());
+    existing(&h, "Other");
+    h.ollama.reply_json(card("ておく"));
+    h.ollama.reply_json(card("ておく"));
+
+    let engine = h.engine();
+    engine.generate(&h.request("ておく")).await.unwrap();
+    let err = engine.generate(&h.request("ておく")).await.unwrap_err();
+
+    assert!(matches!(err, AppError::Duplicate(_)));
+    assert_eq!(h.anki.notes().len(), 2);
+    assert_eq!(
+        add_note_options(&h),
+        json!({
+            "allowDuplicate": false,
+            "duplicateScope": "deck",
+            "duplicateScopeOptions": {
+                "deckName": DECK,
+                "checkChildren": true,
+                "checkAllModels": false
+            }
+        })
+    );
+}
+
+#[tokio::test]
+async fn update_empty_fills_only_empty_fields() {
+    let mut h = Harness::new().await;
+    h.config.duplicates.policy = DuplicatePolicy::UpdateEmpty;
+    let id = existing(&h, DECK);
+    h.ollama.reply_json(card("ておく"));
+
+    let card = h.engine().generate(&h.request("ておく")).await.unwrap();
+
+    assert_eq!(card.action, NoteAction::Updated);
+    assert_eq!(card.note_id, Some(id));
+    let notes = h.anki.notes();
+    assert_eq!(notes.len(), 1);
+    assert_eq!(notes[0].fields["Meaning"], "do in advance");
+    assert_eq!(notes[0].fields["Example"], "例文：ておく。");
+}
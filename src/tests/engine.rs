@@ -0,0 +1,411 @@
+use serde_json::json;
+
+use super::{DECK, Harness, NOTE_TYPE, card};
+use crate::config::{DedupAction, OllamaApi};
+use crate::dedup::SemanticDedup;
+use crate::errors::AppError;
+use crate::output::Output;
+
+#[tokio::test]
+async fn generate_adds_note_and_records_history() {
+    let h = Harness::new().await;
+    h.ollama.reply_json(card("ておく"));
+
+    h.engine().generate(&h.request("ておく")).await.unwrap();
+
+    let notes = h.anki.notes();
+    assert_eq!(notes.len(), 1);
+    assert_eq!(notes[0].deck, DECK);
+    assert_eq!(notes[0].model, NOTE_TYPE);
+    assert_eq!(notes[0].fields["Grammar"], "ておく");
+    assert_eq!(notes[0].fields["Example"], "例文：ておく。");
+
+    let history = h.history();
+    assert_eq!(history.used_items, vec!["ておく"]);
+    assert_eq!(history.runs.len(), 1);
+    assert_eq!(history.runs[0].command, "generate");
+}
+
+#[tokio::test]
+async fn generate_sends_system_message_and_schema_over_chat() {
+    let h = Harness::new().await;
+    h.ollama.reply_json(card("ておく"));
+
+    h.engine().generate(&h.request("ておく")).await.unwrap();
+
+    let requests = h.ollama.generation_requests();
+    assert_eq!(requests.len(), 1);
+    let messages = requests[0]["messages"].as_array().unwrap();
+    assert_eq!(messages[0]["role"], "system");
+    assert_eq!(messages[1]["role"], "user");
+    assert!(messages[1]["content"].as_str().unwrap().contains("ておく"));
+    assert_eq!(
+        requests[0]["format"]["required"],
+        json!(["Grammar", "Meaning", "Example"])
+    );
+}
+
+#[tokio::test]
+async fn generate_endpoint_fallback() {
+    let mut h = Harness::new().await;
+    h.config.ollama_api = OllamaApi::Generate;
+    h.ollama.reply_json(card("ておく"));
+
+    h.engine().generate(&h.request("ておく")).await.unwrap();
+
+    let request = &h.ollama.state().requests[0];
+    assert_eq!(request.0, "/api/generate");
+    assert!(request.1["system"].as_str().unwrap().contains("flashcard"));
+    assert!(request.1["prompt"].as_str().unwrap().contains("ておく"));
+    assert_eq!(h.anki.notes().len(), 1);
+}
+
+#[tokio::test]
+async fn fuzzy_field_names_are_fixed() {
+    let h = Harness::new().await;
+    h.ollama.reply_json(json!({
+        "Gramar": "ばかり",
+        "Meanin": "just did",
+        "Example": "食べたばかりです。",
+    }));
+
+    h.engine().generate(&h.request("ばかり")).await.unwrap();
+
+    let notes = h.anki.notes();
+    assert_eq!(notes[0].fields["Grammar"], "ばかり");
+    assert_eq!(notes[0].fields["Meaning"], "just did");
+}
+
+#[tokio::test]
+async fn strict_mode_rejects_missing_fields() {
+    let h = Harness::new().await;
+    h.ollama
+        .reply_json(json!({ "Grammar": "ながら", "Something": "else" }));
+
+    let err = h.engine().generate(&h.request("ながら")).await.unwrap_err();
+
+    assert!(matches!(err, AppError::SchemaViolation(ref m) if m.contains("missing fields")));
+    assert!(h.anki.notes().is_empty());
+    assert!(h.history().used_items.is_empty());
+}
+
+#[tokio::test]
+async fn preflight_rejects_unknown_deck() {
+    let h = Harness::new().await;
+    let mut req = h.request("ておく");
+    req.deck = "Japanes".to_string();
+
+    let err = h.engine().generate(&req).await.unwrap_err();
+
+    assert!(matches!(err, AppError::DeckNotFound(ref m) if m.contains("Deck 'Japanes' not found")));
+    assert!(h.ollama.generation_requests().is_empty());
+}
+
+#[tokio::test]
+async fn preflight_rejects_unknown_field() {
+    let h = Harness::new().await;
+    let mut req = h.request("ておく");
+    req.fields.push("Reading".to_string());
+
+    let err = h.engine().generate(&req).await.unwrap_err();
+
+    assert!(matches!(err, AppError::FieldMismatch(ref m) if m.contains("Reading")));
+    assert!(h.ollama.generation_requests().is_empty());
+}
+
+#[tokio::test]
+async fn preflight_suggests_close_names() {
+    let h = Harness::new().await;
+    let mut req = h.request("ておく");
+    req.deck = "Japanes".to_string();
+
+    let err = h.engine().generate(&req).await.unwrap_err();
+
+    assert!(
+        matches!(err, AppError::DeckNotFound(ref m) if m.contains(&format!("did you mean '{}'", DECK)))
+    );
+}
+
+#[tokio::test]
+async fn preflight_hints_auto_resolve_for_case_mismatch() {
+    let h = Harness::new().await;
+    let mut req = h.request("ておく");
+    req.note_type = NOTE_TYPE.to_uppercase();
+
+    let err = h.engine().generate(&req).await.unwrap_err();
+
+    assert!(matches!(err, AppError::NoteTypeNotFound(ref m) if m.contains("--auto-resolve")));
+    assert!(h.ollama.generation_requests().is_empty());
+}
+
+#[tokio::test]
+async fn preflight_auto_resolves_unambiguous_names() {
+    let mut h = Harness::new().await;
+    h.config.auto_resolve_names = true;
+    h.ollama.reply_json(card("ておく"));
+    let mut req = h.request("ておく");
+    req.deck = DECK.to_lowercase();
+    req.note_type = NOTE_TYPE.to_uppercase();
+    req.fields = vec![
+        "ｇｒａｍｍａｒ".to_string(),
+        "meaning".to_string(),
+        "Example".to_string(),
+    ];
+
+    h.engine().generate(&req).await.unwrap();
+
+    let notes = h.anki.notes();
+    assert_eq!(notes[0].deck, DECK);
+    assert_eq!(notes[0].model, NOTE_TYPE);
+    assert_eq!(notes[0].fields["Grammar"], "ておく");
+}
+
+#[tokio::test]
+async fn anki_errors_are_reported() {
+    let h = Harness::new().await;
+    h.anki.fail_action("addNote", "collection is not available");
+    h.ollama.reply_json(card("ておく"));
+
+    let err = h.engine().generate(&h.request("ておく")).await.unwrap_err();
+
+    assert!(matches!(err, AppError::Anki(ref m) if m.contains("collection is not available")));
+    assert!(h.anki.notes().is_empty());
+}
+
+#[tokio::test]
+async fn anki_action_failures_stop_before_generation() {
+    let h = Harness::new().await;
+    h.anki
+        .fail_action("deckNames", "collection is not available");
+
+    let err = h.engine().generate(&h.request("ておく")).await.unwrap_err();
+
+    assert!(matches!(err, AppError::Anki(ref m) if m.contains("collection is not available")));
+    assert_eq!(h.anki.actions(), vec!["deckNames"]);
+    assert!(h.ollama.generation_requests().is_empty());
+}
+
+#[tokio::test]
+async fn next_lists_history_and_stores_key_field() {
+    let h = Harness::new().await;
+    h.ollama.reply_json(card("ておく"));
+    h.ollama.reply_json(card("てしまう"));
+
+    let engine = h.engine();
+    let req = h.request("JLPT N3 grammar");
+    engine.next(&req).await.unwrap();
+    engine.next(&req).await.unwrap();
+
+    let requests = h.ollama.generation_requests();
+    let second_prompt = requests[1]["messages"][1]["content"].as_str().unwrap();
+    assert!(second_prompt.contains("DO NOT repeat"));
+    assert!(second_prompt.contains("ておく"));
+
+    assert_eq!(h.history().used_items, vec!["ておく", "てしまう"]);
+    assert_eq!(h.anki.notes().len(), 2);
+}
+
+#[tokio::test]
+async fn next_regenerates_when_model_repeats_history() {
+    let h = Harness::new().await;
+    h.ollama.reply_json(card("ておく"));
+    h.ollama.reply_json(card("～ておく"));
+    h.ollama.reply_json(card("ながら"));
+
+    let engine = h.engine();
+    let req = h.request("JLPT N3 grammar");
+    engine.next(&req).await.unwrap();
+    engine.next(&req).await.unwrap();
+
+    assert_eq!(h.ollama.generation_requests().len(), 3);
+    assert_eq!(h.history().used_items, vec!["ておく", "ながら"]);
+}
+
+#[tokio::test]
+async fn next_gives_up_after_max_attempts() {
+    let mut h = Harness::new().await;
+    h.config.next_max_attempts = 2;
+    h.ollama.reply_json(card("ておく"));
+    h.ollama.reply_json(card("ておく"));
+    h.ollama.reply_json(card("ておく"));
+
+    let engine = h.engine();
+    let req = h.request("JLPT N3 grammar");
+    engine.next(&req).await.unwrap();
+    let err = engine.next(&req).await.unwrap_err();
+
+    assert!(matches!(err, AppError::Duplicate(_)));
+    assert_eq!(h.anki.notes().len(), 1);
+}
+
+#[tokio::test]
+async fn batch_continues_after_malformed_stream() {
+    let h = Harness::new().await;
+    h.ollama.reply_json(card("ておく"));
+    h.ollama.reply_lines(&[
+        r#"{"message":{"role":"assistant","content":"{\"Gram"},"done":false}"#,
+        "this is not json",
+        r#"{"message":{"role":"assistant","content":"mar\": "},"done":true}"#,
+    ]);
+    h.ollama.reply_json(card("ながら"));
+
+    let items = vec!["ておく".to_string(), "てしまう".into(), "ながら".into()];
+    h.engine().batch(&h.request(""), &items).await.unwrap();
+
+    let added: Vec<String> = h
+        .anki
+        .notes()
+        .iter()
+        .map(|n| n.fields["Grammar"].clone())
+        .collect();
+    assert_eq!(added, vec!["ておく", "ながら"]);
+    assert_eq!(h.ollama.pending_replies(), 0);
+    assert_eq!(h.history().used_items, vec!["ておく", "ながら"]);
+    assert_eq!(h.history().runs[0].cards_added, 2);
+}
+
+#[tokio::test]
+async fn batch_fails_when_every_item_fails() {
+    let h = Harness::new().await;
+    h.ollama.reply_status(500);
+    h.ollama.reply_status(500);
+
+    let items = vec!["ておく".to_string(), "ながら".into()];
+    let err = h.engine().batch(&h.request(""), &items).await.unwrap_err();
+
+    let AppError::BatchFailed(report) = err else {
+        panic!("expected BatchFailed, got {:?}", err);
+    };
+    assert_eq!((report.failed, report.total), (2, 2));
+    assert!(h.anki.notes().is_empty());
+}
+
+#[tokio::test]
+async fn history_persists_across_runs() {
+    let h = Harness::new().await;
+    h.ollama.reply_json(card("ておく"));
+    h.ollama.reply_json(card("ながら"));
+
+    h.engine().generate(&h.request("ておく")).await.unwrap();
+    h.engine()
+        .next(&h.request("JLPT N3 grammar"))
+        .await
+        .unwrap();
+
+    let history = h.history();
+    assert_eq!(history.used_items, vec!["ておく", "ながら"]);
+    assert_eq!(history.runs.len(), 2);
+    let prompt = h.ollama.generation_requests()[1]["messages"][1]["content"]
+        .as_str()
+        .unwrap()
+        .to_string();
+    assert!(prompt.contains("ておく"));
+}
+
+#[tokio::test]
+async fn semantic_dedup_skips_similar_cards() {
+    let mut h = Harness::new().await;
+    h.config.dedup.enabled = true;
+    h.config.dedup.action = DedupAction::Skip;
+    h.anki
+        .add_existing_note(DECK, NOTE_TYPE, &[("Grammar", "てしまう")]);
+    h.ollama.set_embedding("てしまう", &[1.0, 0.0, 0.1]);
+    h.ollama.set_embedding("ちゃう", &[0.98, 0.05, 0.12]);
+    h.ollama.reply_json(card("ちゃう"));
+
+    let engine = h
+        .engine()
+        .with_dedup(SemanticDedup::new(h.config.dedup.clone()).unwrap());
+    let err = engine.generate(&h.request("ちゃう")).await.unwrap_err();
+
+    assert!(matches!(err, AppError::Duplicate(ref m) if m.contains("てしまう")));
+    assert_eq!(h.anki.notes().len(), 1);
+}
+
+#[tokio::test]
+async fn semantic_dedup_fetches_existing_notes_per_deck() {
+    let mut h = Harness::new().await;
+    h.config.dedup.enabled = true;
+    h.config.dedup.action = DedupAction::Skip;
+    h.anki.state().decks.push("Other".into());
+    h.anki
+        .add_existing_note("Other", NOTE_TYPE, &[("Grammar", "てしまう")]);
+    h.ollama.set_embedding("てしまう", &[1.0, 0.0, 0.1]);
+    h.ollama.set_embedding("ちゃう", &[0.98, 0.05, 0.12]);
+    h.ollama.reply_json(card("ておく"));
+    h.ollama.reply_json(card("ちゃう"));
+
+    let engine = h
+        .engine()
+        .with_dedup(SemanticDedup::new(h.config.dedup.clone()).unwrap());
+    engine.generate(&h.request("ておく")).await.unwrap();
+    let mut other = h.request("ちゃう");
+    other.deck = "Other".into();
+    let err = engine.generate(&other).await.unwrap_err();
+
+    assert!(matches!(err, AppError::Duplicate(ref m) if m.contains("てしまう")));
+    assert_eq!(h.anki.notes().len(), 2);
+}
+
+#[tokio::test]
+async fn semantic_dedup_rebuilds_an_unreadable_cache() {
+    let mut h = Harness::new().await;
+    h.config.dedup.enabled = true;
+    std::fs::write(&h.config.dedup.cache_path, "{ not json").unwrap();
+    h.ollama.reply_json(card("ておく"));
+    h.ollama.reply_json(card("ておく"));
+
+    let engine = h
+        .engine()
+        .with_dedup(SemanticDedup::new(h.config.dedup.clone()).unwrap());
+    engine.generate(&h.request("ておく")).await.unwrap();
+
+    let cache: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(&h.config.dedup.cache_path).unwrap())
+            .unwrap();
+    assert!(cache["vectors"].get("ておく").is_some());
+
+    // Nothing new to embed, so the cache isn't rewritten
+    std::fs::remove_file(&h.config.dedup.cache_path).unwrap();
+    let _ = engine.generate(&h.request("ておく")).await;
+    assert_eq!(h.ollama.generation_requests().len(), 2);
+    assert!(!std::path::Path::new(&h.config.dedup.cache_path).exists());
+}
+
+#[tokio::test]
+async fn json_output_reports_structured_events() {
+    let h = Harness::new().await;
+    h.ollama.reply_json(card("ておく"));
+    h.ollama.reply_content("not json at all");
+
+    let (output, events) = Output::collecting();
+    let items = vec!["ておく".to_string(), "ながら".into()];
+    h.engine()
+        .with_output(output)
+        .batch(&h.request(""), &items)
+        .await
+        .unwrap();
+
+    let events = events.lock().unwrap();
+    let kinds: Vec<&str> = events
+        .iter()
+        .map(|e| e["event"].as_str().unwrap())
+        .collect();
+    assert_eq!(
+        kinds,
+        vec![
+            "preflight",
+            "generation_started",
+            "fields",
+            "note_added",
+            "generation_started",
+            "error",
+            "batch_summary",
+        ]
+    );
+    let note_id = h.anki.notes()[0].id;
+    assert_eq!(events[3]["note_id"], json!(note_id));
+    assert_eq!(events[2]["fields"]["Grammar"], "ておく");
+    assert_eq!(events[6]["succeeded"], 1);
+    assert_eq!(events[6]["failures"][0]["item"], "ながら");
+}
@@ -0,0 +1,101 @@
+use std::collections::HashSet;
+
+use super::Harness;
+use crate::config::Config;
+use crate::errors::AppError;
+
+#[tokio::test]
+async fn invalid_json_keeps_the_raw_output() {
+    let h = Harness::new().await;
+    h.ollama.reply_content("Sure! Here is your card: {");
+
+    let err = h.engine().generate(&h.request("ておく")).await.unwrap_err();
+
+    assert!(
+        matches!(err, AppError::InvalidJson { ref raw, .. } if raw == "Sure! Here is your card: {")
+    );
+    assert_eq!(err.exit_code(), 22);
+}
+
+#[tokio::test]
+async fn missing_model_is_reported_with_pull_hint() {
+    let h = Harness::new().await;
+    h.ollama.reply_status(404);
+
+    let err = h.engine().generate(&h.request("ておく")).await.unwrap_err();
+
+    assert!(matches!(err, AppError::ModelNotFound(ref m) if *m == h.config.model));
+    assert!(err.hint().unwrap().contains("ollama pull"));
+}
+
+#[tokio::test]
+async fn refused_connection_names_the_url() {
+    let mut h = Harness::new().await;
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    drop(listener);
+    h.config.anki_url = url.clone();
+
+    let err = h.engine().generate(&h.request("ておく")).await.unwrap_err();
+
+    assert!(
+        matches!(err, AppError::ConnectionRefused { service: "AnkiConnect", url: ref u } if *u == url)
+    );
+    assert_eq!(err.exit_code(), 4);
+    assert!(err.hint().unwrap().contains("AnkiConnect"));
+}
+
+#[test]
+fn exit_codes_are_distinct_per_kind() {
+    let errors = [
+        AppError::Config(String::new()),
+        AppError::ConnectionRefused {
+            service: "Ollama",
+            url: String::new(),
+        },
+        AppError::Timeout {
+            service: "Ollama",
+            url: String::new(),
+        },
+        AppError::DeckNotFound(String::new()),
+        AppError::NoteTypeNotFound(String::new()),
+        AppError::FieldMismatch(String::new()),
+        AppError::ModelNotFound(String::new()),
+        AppError::NotFound(String::new()),
+        AppError::Duplicate(String::new()),
+        AppError::SchemaViolation(String::new()),
+        AppError::InvalidJson {
+            error: String::new(),
+            raw: String::new(),
+        },
+        AppError::Validation(String::new()),
+        AppError::HeldForReview(String::new()),
+        AppError::Model(String::new()),
+    ];
+
+    let codes: HashSet<i32> = errors.iter().map(|e| e.exit_code()).collect();
+    assert_eq!(codes.len(), errors.len());
+    assert!(!codes.contains(&0) && !codes.contains(&2));
+    assert!(errors.iter().take(13).all(|e| e.hint().is_some()));
+}
+
+#[test]
+fn unloadable_config_file_is_fatal_unless_lenient() {
+    let dir = tempfile::tempdir().unwrap();
+    let broken = dir.path().join("config.yaml");
+    let fallback = dir.path().join(".anki_gen.json");
+    std::fs::write(&broken, "model: [unclosed\n").unwrap();
+    std::fs::write(&fallback, r#"{"model": "qwen2.5:7b"}"#).unwrap();
+    let paths = [&broken, &fallback];
+
+    let err = Config::load_first(&paths, false).unwrap_err();
+    assert!(matches!(err, AppError::Config(ref m) if m.contains("Failed to parse YAML")));
+
+    // `check` skips the broken file and uses the next one
+    assert_eq!(Config::load_first(&paths, true).unwrap().model, "qwen2.5:7b");
+    let missing = [dir.path().join("missing.yaml")];
+    assert_eq!(
+        Config::load_first(&missing, false).unwrap().model,
+        Config::default().model
+    );
+}
@@ -0,0 +1,236 @@
+use std::collections::BTreeMap;
+
+use serde_json::json;
+
+use super::{DECK, Harness, NOTE_TYPE};
+use crate::config::{DedupAction, DuplicatePolicy, Processor, Rule};
+use crate::dedup::SemanticDedup;
+use crate::errors::AppError;
+use crate::field_map::{self, FieldSource};
+use crate::output::Output;
+use crate::types::CardFields;
+
+fn card(pairs: &[(&str, &str)]) -> CardFields {
+    pairs
+        .iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect()
+}
+
+#[test]
+fn sources_render_keys_templates_constants_and_concatenation() {
+    let card = card(&[("reading", "よむ"), ("pos", "verb"), ("nuance", " ")]);
+    let template = FieldSource::Key("{reading} — {pos}".into());
+    assert_eq!(template.keys(), vec!["reading", "pos"]);
+    assert_eq!(template.render(&card), "よむ — verb");
+    assert_eq!(FieldSource::Key("pos".into()).render(&card), "verb");
+    assert_eq!(
+        FieldSource::Key("{nuance} — {missing}".into()).render(&card),
+        ""
+    );
+    assert_eq!(
+        FieldSource::Key("{{{pos}}} {unclosed".into()).render(&card),
+        "{verb} {unclosed"
+    );
+    let constant = FieldSource::Constant {
+        constant: "Genki II".into(),
+    };
+    assert!(constant.keys().is_empty());
+    assert_eq!(constant.render(&card), "Genki II");
+    let concat = FieldSource::Concat {
+        concat: vec!["reading".into(), "nuance".into(), "pos".into()],
+        separator: "<br>".into(),
+    };
+    assert_eq!(concat.render(&card), "よむ<br>verb");
+}
+
+#[test]
+fn sources_parse_from_yaml() {
+    let map: BTreeMap<String, FieldSource> = serde_yaml::from_str(
+        "SentFurigana: example_sentence_with_furigana\n\
+         MiscInfo: \"{reading} — {pos}\"\n\
+         Source: { constant: Genki II }\n\
+         Notes: { concat: [usage, nuance] }\n",
+    )
+    .unwrap();
+    assert_eq!(
+        map["SentFurigana"],
+        FieldSource::Key("example_sentence_with_furigana".into())
+    );
+    assert_eq!(
+        map["Notes"],
+        FieldSource::Concat {
+            concat: vec!["usage".into(), "nuance".into()],
+            separator: "<br>".into(),
+        }
+    );
+    assert_eq!(
+        field_map::generation_keys(&["Expression".into(), "MiscInfo".into()], &map),
+        vec![
+            "Expression",
+            "reading",
+            "pos",
+            "usage",
+            "nuance",
+            "example_sentence_with_furigana"
+        ]
+    );
+}
+
+#[tokio::test]
+async fn model_keys_are_mapped_to_note_fields() {
+    let mut h = Harness::new().await;
+    h.anki
+        .state()
+        .models
+        .get_mut(NOTE_TYPE)
+        .unwrap()
+        .push("Source".into());
+    h.config.field_map = serde_yaml::from_str(
+        "Meaning: { concat: [meaning, nuance], separator: '; ' }\n\
+         Example: \"{example_sentence} ({translation})\"\n\
+         Source: { constant: Genki II }\n",
+    )
+    .unwrap();
+    h.ollama.reply_json(json!({
+        "Grammar": "ておく",
+        "meaning": "do in advance",
+        "nuance": "preparation",
+        "example_sentence": "買っておく。",
+        "translation": "I'll buy it beforehand.",
+    }));
+
+    h.engine().generate(&h.request("ておく")).await.unwrap();
+
+    let request = &h.ollama.generation_requests()[0];
+    assert_eq!(
+        request["format"]["required"],
+        json!([
+            "Grammar",
+            "meaning",
+            "nuance",
+            "example_sentence",
+            "translation"
+        ])
+    );
+    let note = &h.anki.notes()[0];
+    assert_eq!(note.fields["Grammar"], "ておく");
+    assert_eq!(note.fields["Meaning"], "do in advance; preparation");
+    assert_eq!(
+        note.fields["Example"],
+        "買っておく。 (I'll buy it beforehand.)"
+    );
+    assert_eq!(note.fields["Source"], "Genki II");
+    assert!(!note.fields.contains_key("nuance"));
+}
+
+#[tokio::test]
+async fn unknown_mapped_field_fails_preflight() {
+    let mut h = Harness::new().await;
+    h.config.field_map = BTreeMap::from([("Exmaple".to_string(), FieldSource::Key("x".into()))]);
+
+    let err = h.engine().generate(&h.request("ておく")).await.unwrap_err();
+
+    assert!(matches!(err, AppError::FieldMismatch(_)));
+    assert!(err.to_string().contains("did you mean 'Example'"));
+}
+
+#[tokio::test]
+async fn semantic_dedup_compares_the_renamed_first_field() {
+    let mut h = Harness::new().await;
+    h.config.field_map = serde_yaml::from_str("Grammar: expression\n").unwrap();
+    h.config.dedup.enabled = true;
+    h.config.dedup.action = DedupAction::Skip;
+    h.anki
+        .add_existing_note(DECK, NOTE_TYPE, &[("Grammar", "てしまう")]);
+    h.ollama.set_embedding("てしまう", &[1.0, 0.0, 0.1]);
+    h.ollama.set_embedding("ちゃう", &[0.98, 0.05, 0.12]);
+    h.ollama.reply_json(json!({
+        "expression": "ちゃう",
+        "Meaning": "end up doing",
+        "Example": "食べちゃう。",
+    }));
+
+    let err = h
+        .engine()
+        .with_dedup(SemanticDedup::new(h.config.dedup.clone()).unwrap())
+        .generate(&h.request("ちゃう"))
+        .await
+        .unwrap_err();
+
+    assert!(matches!(err, AppError::Duplicate(ref m) if m.contains("てしまう")));
+    assert_eq!(h.anki.notes().len(), 1);
+}
+
+#[tokio::test]
+async fn fields_events_carry_the_mapped_note_fields() {
+    let mut h = Harness::new().await;
+    h.config.field_map = serde_yaml::from_str("Grammar: expression\n").unwrap();
+    h.config.duplicates.policy = DuplicatePolicy::Allow;
+    let reply = json!({
+        "expression": "ておく",
+        "Meaning": "do in advance",
+        "Example": "買っておく。",
+    });
+    h.ollama.reply_json(reply.clone());
+    h.ollama.reply_json(reply);
+
+    let (output, events) = Output::collecting();
+    let engine = h.engine().with_output(output);
+    engine.generate(&h.request("ておく")).await.unwrap();
+    let items = vec!["ておく".to_string()];
+    engine.batch(&h.request(""), &items).await.unwrap();
+
+    let events = events.lock().unwrap();
+    let fields: Vec<_> = events
+        .iter()
+        .filter(|e| e["event"] == "fields")
+        .map(|e| e["fields"].clone())
+        .collect();
+    assert_eq!(fields.len(), 2);
+    assert_eq!(fields[0], fields[1]);
+    assert_eq!(fields[0]["Grammar"], "ておく");
+    assert!(fields[0].get("expression").is_none());
+}
+
+#[tokio::test]
+async fn rules_and_processing_written_for_a_mapped_field_apply_to_its_key() {
+    let mut h = Harness::new().await;
+    h.config.field_map = serde_yaml::from_str("Grammar: expression\n").unwrap();
+    h.config.validation = [("Grammar".to_string(), vec![Rule::ContainsJapanese])].into();
+    h.config.postprocess.fields = [("Grammar".to_string(), vec![Processor::StripQuotes])].into();
+    h.ollama.reply_json(json!({
+        "expression": "te oku",
+        "Meaning": "do in advance",
+        "Example": "買っておく。",
+    }));
+    h.ollama.reply_json(json!({
+        "expression": "\"ておく\"",
+        "Meaning": "do in advance",
+        "Example": "買っておく。",
+    }));
+
+    h.engine().generate(&h.request("ておく")).await.unwrap();
+
+    assert_eq!(h.ollama.generation_requests().len(), 2);
+    assert_eq!(h.anki.notes()[0].fields["Grammar"], "ておく");
+}
+
+#[tokio::test]
+async fn rules_for_unknown_or_templated_fields_are_rejected() {
+    let mut h = Harness::new().await;
+    h.config.validation = [("Exmple".to_string(), vec![Rule::ContainsKey])].into();
+
+    let err = h.engine().generate(&h.request("ておく")).await.unwrap_err();
+    assert!(matches!(err, AppError::Config(ref m) if m.contains("did you mean 'Example'")));
+
+    h.config.validation.clear();
+    h.config.field_map = serde_yaml::from_str("Example: \"{sentence} ({reading})\"\n").unwrap();
+    h.config.postprocess.fields = [("Example".to_string(), vec![Processor::Markdown])].into();
+
+    let err = h.engine().generate(&h.request("ておく")).await.unwrap_err();
+    assert!(
+        matches!(err, AppError::Config(ref m) if m.contains("keys it reads: sentence, reading"))
+    );
+    assert!(h.ollama.generation_requests().is_empty());
+}
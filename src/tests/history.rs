@@ -0,0 +1,113 @@
+use chrono::{Duration, Local, Utc};
+
+use super::{DECK, Harness, card};
+use crate::config::DuplicatePolicy;
+use crate::history::{self, HistoryFilter};
+use crate::types::StoredHistory;
+
+#[tokio::test]
+async fn next_records_metadata_for_filtering() {
+    let h = Harness::new().await;
+    h.ollama.reply_json(card("ておく"));
+    h.ollama.reply_json(card("ながら"));
+
+    let engine = h.engine();
+    engine.next(&h.request("JLPT N3 grammar")).await.unwrap();
+    engine.generate(&h.request("ながら")).await.unwrap();
+
+    let items = history::items(&h.history());
+    assert_eq!(items.len(), 2);
+    assert_eq!(items[0].item, "ておく");
+    assert_eq!(items[0].deck.as_deref(), Some(DECK));
+    assert_eq!(items[0].command.as_deref(), Some("next"));
+    assert!(items[0].note_id.is_some());
+
+    let n3 = HistoryFilter {
+        topic: Some("n3".into()),
+        ..Default::default()
+    };
+    let matching: Vec<_> = items.iter().filter(|e| n3.matches(e)).collect();
+    assert_eq!(matching.len(), 1);
+    assert_eq!(matching[0].item, "ておく");
+
+    let subdeck = HistoryFilter {
+        deck: Some("japanese".into()),
+        since: Some(Local::now().date_naive()),
+        ..Default::default()
+    };
+    assert!(items.iter().all(|e| subdeck.matches(e)));
+    let tomorrow = HistoryFilter {
+        since: Some((Local::now() + Duration::days(1)).date_naive()),
+        ..Default::default()
+    };
+    assert!(!items.iter().any(|e| tomorrow.matches(e)));
+}
+
+#[tokio::test]
+async fn removed_items_can_be_suggested_again() {
+    let mut h = Harness::new().await;
+    // The first card was rejected but is still in the mock collection
+    h.config.duplicates.policy = DuplicatePolicy::Allow;
+    h.ollama.reply_json(card("ておく"));
+    h.ollama.reply_json(card("ておく"));
+
+    let engine = h.engine();
+    let req = h.request("JLPT N3 grammar");
+    engine.next(&req).await.unwrap();
+
+    let removed = engine.remove_history(&["ＴＥおく".into(), "ておく".into()]).unwrap();
+    assert_eq!(removed, vec!["ておく"]);
+    assert!(h.history().entries.is_empty());
+
+    engine.next(&req).await.unwrap();
+    let requests = h.ollama.generation_requests();
+    let second_prompt = requests[1]["messages"][1]["content"].as_str().unwrap();
+    assert!(!second_prompt.contains("ておく"));
+    assert_eq!(h.history().used_items, vec!["ておく"]);
+}
+
+#[test]
+fn histories_without_entries_still_load() {
+    let history: StoredHistory =
+        serde_json::from_str(r#"{"used_items": ["ておく", "ながら"], "runs": []}"#).unwrap();
+
+    let items = history::items(&history);
+    assert_eq!(items.len(), 2);
+    assert!(items[1].added_at.is_none());
+    assert!(HistoryFilter::default().matches(&items[1]));
+    let by_deck = HistoryFilter {
+        deck: Some(DECK.into()),
+        ..Default::default()
+    };
+    assert!(!by_deck.matches(&items[1]));
+
+    // Nothing new is written for them
+    let saved = serde_json::to_value(&history).unwrap();
+    assert!(saved.get("entries").is_none());
+}
+
+#[test]
+fn import_skips_known_items() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("items.txt");
+    std::fs::write(&path, "ておく\n\nＮＡＧＡＲＡ\nばかり\nばかり\n").unwrap();
+
+    let mut history = StoredHistory {
+        used_items: vec!["ておく".into(), "nagara".into()],
+        ..Default::default()
+    };
+    let imported = history::read_import(&path).unwrap();
+    assert_eq!(history::merge(&mut history, &imported, false), 1);
+    assert_eq!(history.used_items, vec!["ておく", "nagara", "ばかり"]);
+
+    let mut exported = StoredHistory::default();
+    exported.record(crate::types::HistoryEntry {
+        item: "ておく".into(),
+        added_at: Some(Utc::now()),
+        deck: Some(DECK.into()),
+        ..Default::default()
+    });
+    assert_eq!(history::merge(&mut history, &exported, true), 1);
+    assert_eq!(history.used_items, vec!["ておく"]);
+    assert_eq!(history.entries[0].deck.as_deref(), Some(DECK));
+}
@@ -0,0 +1,115 @@
+use std::path::PathBuf;
+
+use serde_json::json;
+
+use super::{Harness, NOTE_TYPE, card};
+use crate::config::OllamaApi;
+use crate::errors::AppError;
+use crate::image::{self, SourceImage};
+
+/// Not a real PNG; nothing between us and the mocks decodes it.
+const PNG: &[u8] = b"\x89PNG\r\n\x1a\nmanga panel";
+const PNG_BASE64: &str = "iVBORw0KGgptYW5nYSBwYW5lbA==";
+
+fn write_image(h: &Harness, name: &str) -> PathBuf {
+    let path = h.dir.path().join(name);
+    std::fs::write(&path, PNG).unwrap();
+    path
+}
+
+/// The note type gains a field for the source image.
+fn with_picture_field(h: &mut Harness) {
+    h.anki
+        .state()
+        .models
+        .get_mut(NOTE_TYPE)
+        .unwrap()
+        .push("Picture".into());
+    h.config.image_field = Some("Picture".into());
+}
+
+#[tokio::test]
+async fn generate_sends_image_and_stores_it_in_the_note() {
+    let mut h = Harness::new().await;
+    with_picture_field(&mut h);
+    h.ollama.reply_json(card("ておく"));
+
+    let mut req = h.request("grammar in this panel");
+    req.images = vec![write_image(&h, "panel.png")];
+    h.engine().generate(&req).await.unwrap();
+
+    let requests = h.ollama.generation_requests();
+    let user = &requests[0]["messages"][1];
+    assert_eq!(user["images"], json!([PNG_BASE64]));
+    assert!(user["content"].as_str().unwrap().contains("attached image"));
+    // The model isn't asked for the image field
+    assert_eq!(
+        requests[0]["format"]["required"],
+        json!(["Grammar", "Meaning", "Example"])
+    );
+
+    let media = h.anki.state().media.clone();
+    assert_eq!(media.len(), 1);
+    let (filename, data) = media.iter().next().unwrap();
+    assert_eq!(data, PNG_BASE64);
+    assert!(filename.starts_with("anki_gen_") && filename.ends_with(".png"));
+    assert_eq!(
+        h.anki.notes()[0].fields["Picture"],
+        format!("<img src=\"{}\">", filename)
+    );
+}
+
+#[tokio::test]
+async fn batch_items_can_be_images() {
+    let mut h = Harness::new().await;
+    h.config.ollama_api = OllamaApi::Generate;
+    h.ollama.reply_json(card("ておく"));
+    h.ollama.reply_json(card("ながら"));
+    let panel = write_image(&h, "panel.png");
+
+    let items = vec![
+        format!("{} | grammar in this panel", panel.display()),
+        "ながら".to_string(),
+        "missing.png".to_string(),
+    ];
+    let report = h.engine().batch(&h.request(""), &items).await.unwrap();
+
+    assert_eq!(report.succeeded, 2);
+    assert_eq!(report.failed, 1);
+    assert!(report.failures[0].error.contains("missing.png"));
+
+    let requests = h.ollama.generation_requests();
+    assert_eq!(requests[0]["images"], json!([PNG_BASE64]));
+    assert!(requests[0]["prompt"].as_str().unwrap().contains("grammar in this panel"));
+    assert!(requests[1].get("images").is_none());
+    // No image field configured, so nothing is stored
+    assert!(h.anki.state().media.is_empty());
+}
+
+#[tokio::test]
+async fn unknown_image_field_fails_preflight() {
+    let mut h = Harness::new().await;
+    h.config.image_field = Some("Pictrue".into());
+
+    let err = h.engine().generate(&h.request("ておく")).await.unwrap_err();
+
+    assert!(matches!(err, AppError::FieldMismatch(_)));
+    assert!(h.ollama.generation_requests().is_empty());
+}
+
+#[test]
+fn batch_items_are_split_into_image_and_description() {
+    assert_eq!(
+        image::parse_item("scans/p12.PNG | ば かり"),
+        ("ば かり".to_string(), Some(PathBuf::from("scans/p12.PNG")))
+    );
+    assert_eq!(
+        image::parse_item("p12.jpg"),
+        ("the attached image".to_string(), Some(PathBuf::from("p12.jpg")))
+    );
+    assert_eq!(image::parse_item("ておく"), ("ておく".to_string(), None));
+    assert_eq!(image::parse_item("A | B"), ("A | B".to_string(), None));
+
+    let err = SourceImage::load(&PathBuf::from("notes.txt")).err().unwrap();
+    assert_eq!(err.exit_code(), 14);
+}
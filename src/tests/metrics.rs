@@ -0,0 +1,134 @@
+use chrono::{TimeZone, Utc};
+use serde_json::json;
+
+use super::{Harness, card};
+use super::mock_ollama::PROMPT_EVAL_COUNT;
+use crate::config::Rule;
+use crate::metrics::{self, CardMetrics, GroupBy};
+use crate::types::RunRecord;
+
+#[tokio::test]
+async fn batch_records_metrics_per_card() {
+    let mut h = Harness::new().await;
+    h.config.http.max_retries = 1;
+    h.config.validation = [("Example".to_string(), vec![Rule::ContainsKey])].into();
+    // ておく: a server error, then a misspelled key
+    h.ollama.reply_status(503);
+    h.ollama.reply_json(json!({
+        "Grammar": "ておく", "Meanig": "do in advance", "Example": "準備しておく。",
+    }));
+    // ながら: breaks a validation rule once
+    h.ollama.reply_json(json!({
+        "Grammar": "ながら", "Meaning": "while", "Example": "歩きます。",
+    }));
+    h.ollama.reply_json(card("ながら"));
+    // ばかり: misses a field
+    h.ollama
+        .reply_json(json!({ "Grammar": "ばかり", "Meaning": "only" }));
+
+    let items = ["ておく".to_string(), "ながら".into(), "ばかり".into()];
+    let report = h.engine().batch(&h.request(""), &items).await.unwrap();
+
+    let cards = &h.history().runs[0].cards;
+    let summary: Vec<(&str, u32, u32, u32, bool)> = cards
+        .iter()
+        .map(|c| {
+            let counts = (c.retries, c.fuzzy_fixes, c.validation_failures);
+            (c.item.as_str(), counts.0, counts.1, counts.2, c.failed)
+        })
+        .collect();
+    assert_eq!(
+        summary,
+        vec![
+            ("ておく", 1, 1, 0, false),
+            ("ながら", 1, 0, 1, false),
+            ("ばかり", 0, 0, 1, true),
+        ]
+    );
+    assert_eq!(cards[1].prompt_tokens, 2 * PROMPT_EVAL_COUNT);
+    assert_eq!(cards[0].deck, "Japanese");
+    assert!(
+        cards
+            .iter()
+            .all(|c| c.prompt_chars > 0 && c.eval_tokens > 0)
+    );
+    assert_eq!(cards[0].tokens_per_second(), Some(50.0));
+
+    assert_eq!(report.metrics.cards, 3);
+    assert_eq!(report.metrics.failed, 1);
+    assert_eq!(report.metrics.retries, 2);
+    assert_eq!(report.cards[0].metrics, cards[0]);
+}
+
+#[tokio::test]
+async fn failed_generate_and_next_still_record_the_run() {
+    let h = Harness::new().await;
+    h.ollama.reply_content("not json at all");
+    h.ollama
+        .reply_json(json!({ "Grammar": "ばかり", "Meaning": "only" }));
+
+    let engine = h.engine();
+    engine.generate(&h.request("ておく")).await.unwrap_err();
+    engine.next(&h.request("N3 grammar")).await.unwrap_err();
+
+    let runs = h.history().runs;
+    let summary: Vec<(&str, usize, bool)> = runs
+        .iter()
+        .map(|r| (r.command.as_str(), r.cards_added, r.cards[0].failed))
+        .collect();
+    assert_eq!(summary, vec![("generate", 0, true), ("next", 0, true)]);
+    assert!(runs[1].cards[0].eval_tokens > 0);
+    assert!(h.history().used_items.is_empty());
+}
+
+fn run(command: &str, model: &str, day: u32, cards: &[(&str, u64)]) -> RunRecord {
+    RunRecord {
+        started_at: Utc.with_ymd_and_hms(2026, 5, day, 12, 0, 0).unwrap(),
+        command: command.into(),
+        model: model.into(),
+        options: Default::default(),
+        keep_alive: None,
+        cards_added: cards.len(),
+        interrupted: false,
+        cards: cards
+            .iter()
+            .map(|(deck, ms)| CardMetrics {
+                wall_ms: *ms,
+                eval_tokens: 10,
+                eval_ms: *ms,
+                ..CardMetrics::new("item", deck)
+            })
+            .collect(),
+    }
+}
+
+#[test]
+fn stats_group_cards_across_runs() {
+    let runs = vec![
+        run("batch", "llama3", 1, &[("Japanese", 1000), ("Vocab", 3000)]),
+        run("next", "qwen2.5", 2, &[("Japanese", 500)]),
+        run("regen", "llama3", 3, &[("", 2000)]),
+    ];
+
+    let by_model = metrics::aggregate(&runs, &[GroupBy::Model], None, None);
+    assert_eq!(by_model["llama3"].cards, 3);
+    assert_eq!(by_model["llama3"].seconds_per_card(), 2.0);
+    assert_eq!(by_model["qwen2.5"].tokens_per_second(), Some(20.0));
+
+    let by_deck = metrics::aggregate(&runs, &[GroupBy::Command, GroupBy::Deck], None, None);
+    let keys: Vec<&str> = by_deck.keys().map(String::as_str).collect();
+    assert_eq!(
+        keys,
+        [
+            "batch / Japanese",
+            "batch / Vocab",
+            "next / Japanese",
+            "regen / -"
+        ]
+    );
+
+    let since = chrono::NaiveDate::from_ymd_opt(2026, 5, 2);
+    let recent = metrics::aggregate(&runs, &[GroupBy::Model], since, since);
+    assert_eq!(recent.len(), 1);
+    assert_eq!(recent["qwen2.5"].cards, 1);
+}
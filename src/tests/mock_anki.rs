@@ -0,0 +1,346 @@
+//! In-memory AnkiConnect fake. Speaks the same JSON protocol over HTTP.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use axum::extract::State;
+use axum::routing::post;
+use axum::{Json, Router};
+use serde_json::{Value, json};
+use tokio::net::TcpListener;
+
+#[derive(Debug, Clone)]
+pub struct MockNote {
+    pub id: u64,
+    pub deck: String,
+    pub model: String,
+    pub fields: HashMap<String, String>,
+    pub tags: Vec<String>,
+}
+
+#[derive(Default)]
+pub struct AnkiState {
+    pub decks: Vec<String>,
+    pub models: HashMap<String, Vec<String>>,
+    pub notes: Vec<MockNote>,
+    /// Actions that should fail with the given error message.
+    pub failures: HashMap<String, String>,
+    /// Actions that should fail the given number of times, then succeed.
+    pub transient_failures: HashMap<String, (String, usize)>,
+    /// Actions answered only after a delay (the request still takes effect).
+    pub delays: HashMap<String, Duration>,
+    /// Files stored with `storeMediaFile`, name to base64 data.
+    pub media: HashMap<String, String>,
+    /// Every request received, in order.
+    pub requests: Vec<Value>,
+    next_id: u64,
+}
+
+#[derive(Clone)]
+pub struct MockAnki {
+    pub url: String,
+    state: Arc<Mutex<AnkiState>>,
+}
+
+impl MockAnki {
+    /// Start a fake with one deck and one note type.
+    pub async fn start(deck: &str, note_type: &str, fields: &[&str]) -> Self {
+        let mut state = AnkiState {
+            next_id: 1_700_000_000_000,
+            ..Default::default()
+        };
+        state.decks.push(deck.to_string());
+        state.models.insert(
+            note_type.to_string(),
+            fields.iter().map(|f| f.to_string()).collect(),
+        );
+        let state = Arc::new(Mutex::new(state));
+
+        let app = Router::new()
+            .route("/", post(handle))
+            .with_state(state.clone());
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        Self { url, state }
+    }
+
+    pub fn state(&self) -> std::sync::MutexGuard<'_, AnkiState> {
+        self.state.lock().unwrap()
+    }
+
+    pub fn notes(&self) -> Vec<MockNote> {
+        self.state().notes.clone()
+    }
+
+    pub fn add_existing_note(&self, deck: &str, model: &str, fields: &[(&str, &str)]) -> u64 {
+        let mut state = self.state();
+        state.next_id += 1;
+        let id = state.next_id;
+        state.notes.push(MockNote {
+            id,
+            deck: deck.to_string(),
+            model: model.to_string(),
+            fields: fields
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+            tags: Vec::new(),
+        });
+        id
+    }
+
+    pub fn fail_action(&self, action: &str, error: &str) {
+        self.state()
+            .failures
+            .insert(action.to_string(), error.to_string());
+    }
+
+    pub fn fail_action_times(&self, action: &str, error: &str, times: usize) {
+        self.state()
+            .transient_failures
+            .insert(action.to_string(), (error.to_string(), times));
+    }
+
+    pub fn delay_action(&self, action: &str, delay: Duration) {
+        self.state().delays.insert(action.to_string(), delay);
+    }
+
+    pub fn actions(&self) -> Vec<String> {
+        self.state()
+            .requests
+            .iter()
+            .filter_map(|r| r["action"].as_str().map(String::from))
+            .collect()
+    }
+}
+
+async fn handle(State(state): State<Arc<Mutex<AnkiState>>>, Json(req): Json<Value>) -> Json<Value> {
+    let action = req["action"].as_str().unwrap_or_default().to_string();
+    let delay = {
+        let mut state = state.lock().unwrap();
+        state.requests.push(req.clone());
+        state.delays.get(&action).copied()
+    };
+    if let Some(delay) = delay {
+        tokio::time::sleep(delay).await;
+    }
+
+    let mut state = state.lock().unwrap();
+    if let Some(err) = state.failures.get(&action) {
+        return Json(json!({ "result": null, "error": err }));
+    }
+    if let Some((err, times)) = state.transient_failures.get_mut(&action)
+        && *times > 0
+    {
+        *times -= 1;
+        return Json(json!({ "result": null, "error": err.clone() }));
+    }
+
+    let params = &req["params"];
+    let result = match action.as_str() {
+        "version" => Ok(json!(6)),
+        "deckNames" => Ok(json!(state.decks)),
+        "modelNames" => {
+            let mut names: Vec<&String> = state.models.keys().collect();
+            names.sort();
+            Ok(json!(names))
+        }
+        "modelFieldNames" => {
+            let name = params["modelName"].as_str().unwrap_or_default();
+            state
+                .models
+                .get(name)
+                .map(|f| json!(f))
+                .ok_or_else(|| format!("model was not found: {}", name))
+        }
+        "addNote" => add_note(&mut state, &params["note"]).map(|id| json!(id)),
+        "addNotes" => {
+            let notes = params["notes"].as_array().cloned().unwrap_or_default();
+            let ids: Vec<Value> = notes
+                .iter()
+                .map(|n| add_note(&mut state, n).map_or(Value::Null, |id| json!(id)))
+                .collect();
+            Ok(json!(ids))
+        }
+        "findNotes" => {
+            let query = params["query"].as_str().unwrap_or_default();
+            let ids: Vec<u64> = state
+                .notes
+                .iter()
+                .filter(|n| matches_query(n, query))
+                .map(|n| n.id)
+                .collect();
+            Ok(json!(ids))
+        }
+        "notesInfo" => {
+            let ids: Vec<u64> = serde_json::from_value(params["notes"].clone()).unwrap_or_default();
+            let infos: Vec<Value> = ids
+                .iter()
+                .filter_map(|id| state.notes.iter().find(|n| n.id == *id))
+                .map(|n| note_info(&state, n))
+                .collect();
+            Ok(json!(infos))
+        }
+        "createDeck" => {
+            let deck = params["deck"].as_str().unwrap_or_default().to_string();
+            // Like Anki, create missing parents of `A::B::C`
+            let parts: Vec<&str> = deck.split("::").collect();
+            for i in 1..=parts.len() {
+                let name = parts[..i].join("::");
+                if !state.decks.contains(&name) {
+                    state.decks.push(name);
+                }
+            }
+            Ok(json!(1_600_000_000_000u64 + state.decks.len() as u64))
+        }
+        "createModel" => {
+            let name = params["modelName"].as_str().unwrap_or_default().to_string();
+            if state.models.contains_key(&name) {
+                Err(format!("Model name already exists: {}", name))
+            } else {
+                let fields: Vec<String> =
+                    serde_json::from_value(params["inOrderFields"].clone()).unwrap_or_default();
+                state.models.insert(name.clone(), fields);
+                Ok(json!({ "name": name }))
+            }
+        }
+        "updateNoteFields" => {
+            let id = params["note"]["id"].as_u64().unwrap_or_default();
+            let fields: HashMap<String, String> =
+                serde_json::from_value(params["note"]["fields"].clone()).unwrap_or_default();
+            match state.notes.iter_mut().find(|n| n.id == id) {
+                Some(note) => {
+                    note.fields.extend(fields);
+                    Ok(Value::Null)
+                }
+                None => Err(format!("note was not found: {}", id)),
+            }
+        }
+        "storeMediaFile" => {
+            let filename = params["filename"].as_str().unwrap_or_default().to_string();
+            let data = params["data"].as_str().unwrap_or_default().to_string();
+            state.media.insert(filename.clone(), data);
+            Ok(json!(filename))
+        }
+        other => Err(format!("unsupported action: {}", other)),
+    };
+
+    match result {
+        Ok(result) => Json(json!({ "result": result, "error": null })),
+        Err(err) => Json(json!({ "result": null, "error": err })),
+    }
+}
+
+fn add_note(state: &mut AnkiState, note: &Value) -> Result<u64, String> {
+    let deck = note["deckName"].as_str().unwrap_or_default().to_string();
+    let model = note["modelName"].as_str().unwrap_or_default().to_string();
+
+    if !state.decks.contains(&deck) {
+        return Err(format!("deck was not found: {}", deck));
+    }
+    let Some(model_fields) = state.models.get(&model).cloned() else {
+        return Err(format!("model was not found: {}", model));
+    };
+
+    let fields: HashMap<String, String> =
+        serde_json::from_value(note["fields"].clone()).unwrap_or_default();
+    let first = model_fields
+        .first()
+        .and_then(|f| fields.get(f))
+        .cloned()
+        .unwrap_or_default();
+    if first.trim().is_empty() {
+        return Err("cannot create note because it is empty".into());
+    }
+
+    let options = &note["options"];
+    let allow_duplicate = options["allowDuplicate"].as_bool().unwrap_or(false);
+    let scope = &options["duplicateScopeOptions"];
+    let scope_deck = (options["duplicateScope"] == "deck")
+        .then(|| scope["deckName"].as_str().unwrap_or(&deck).to_string());
+    let check_children = scope["checkChildren"].as_bool().unwrap_or(false);
+    let check_all_models = scope["checkAllModels"].as_bool().unwrap_or(false);
+    let is_duplicate = state.notes.iter().any(|n| {
+        let in_scope = scope_deck.as_ref().is_none_or(|d| {
+            n.deck == *d || (check_children && n.deck.starts_with(&format!("{}::", d)))
+        });
+        let same_model = n.model == model || (scope_deck.is_some() && check_all_models);
+        in_scope && same_model && model_fields.first().and_then(|f| n.fields.get(f)) == Some(&first)
+    });
+    if is_duplicate && !allow_duplicate {
+        return Err("cannot create note because it is a duplicate".into());
+    }
+
+    let tags: Vec<String> = serde_json::from_value(note["tags"].clone()).unwrap_or_default();
+    state.next_id += 1;
+    let id = state.next_id;
+    state.notes.push(MockNote {
+        id,
+        deck,
+        model,
+        fields,
+        tags,
+    });
+    Ok(id)
+}
+
+/// Supports the subset of the search syntax the client uses: `deck:"X"`, `note:"Y"`, `nid:N`.
+fn matches_query(note: &MockNote, query: &str) -> bool {
+    let mut rest = query.trim();
+    while !rest.is_empty() {
+        let (term, tail) = split_term(rest);
+        rest = tail.trim_start();
+
+        // Both `key:"value"` and `"key:value"`
+        let term = term.strip_prefix('"').unwrap_or(term);
+        let Some((key, value)) = term.split_once(':') else {
+            continue;
+        };
+        let value = value.trim_matches('"');
+        let ok = match key {
+            "deck" => note.deck == value,
+            "note" => note.model == value,
+            "nid" => value.split(',').any(|id| id == note.id.to_string()),
+            field => note.fields.get(field).is_some_and(|v| v == value),
+        };
+        if !ok {
+            return false;
+        }
+    }
+    true
+}
+
+fn split_term(input: &str) -> (&str, &str) {
+    let mut in_quotes = false;
+    for (i, c) in input.char_indices() {
+        match c {
+            '"' => in_quotes = !in_quotes,
+            ' ' if !in_quotes => return (&input[..i], &input[i..]),
+            _ => {}
+        }
+    }
+    (input, "")
+}
+
+fn note_info(state: &AnkiState, note: &MockNote) -> Value {
+    let order = state.models.get(&note.model).cloned().unwrap_or_default();
+    let fields: serde_json::Map<String, Value> = order
+        .iter()
+        .enumerate()
+        .map(|(i, name)| {
+            let value = note.fields.get(name).cloned().unwrap_or_default();
+            (name.clone(), json!({ "value": value, "order": i }))
+        })
+        .collect();
+
+    json!({
+        "noteId": note.id,
+        "modelName": note.model,
+        "tags": note.tags,
+        "fields": fields,
+        "cards": [],
+    })
+}
@@ -0,0 +1,217 @@
+//! Scripted Ollama fake. Streams NDJSON replies in small, arbitrarily split chunks.
+
+use std::collections::{HashMap, VecDeque};
+use std::convert::Infallible;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use axum::body::{Body, Bytes};
+use axum::extract::State;
+use axum::http::{StatusCode, Uri};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use serde_json::{Value, json};
+use tokio::net::TcpListener;
+
+/// Bytes per streamed body chunk. Small and odd so lines and UTF-8 sequences get split.
+const CHUNK_BYTES: usize = 7;
+/// Prompt tokens reported in the final chunk of every content reply.
+pub const PROMPT_EVAL_COUNT: u64 = 120;
+/// Generation time reported per token, in nanoseconds.
+pub const NANOS_PER_TOKEN: u64 = 20_000_000;
+
+enum Reply {
+    /// Assistant content, streamed as chat or generate chunks depending on the endpoint.
+    Content(String),
+    /// NDJSON lines sent verbatim.
+    Lines(Vec<String>),
+    Status(u16),
+    /// No answer for this long, to trip client timeouts.
+    Stall(Duration),
+}
+
+#[derive(Default)]
+pub struct OllamaState {
+    replies: VecDeque<Reply>,
+    pub models: Vec<String>,
+    pub embeddings: HashMap<String, Vec<f32>>,
+    /// (path, body) of every request received, in order.
+    pub requests: Vec<(String, Value)>,
+}
+
+#[derive(Clone)]
+pub struct MockOllama {
+    pub url: String,
+    state: Arc<Mutex<OllamaState>>,
+}
+
+impl MockOllama {
+    pub async fn start(models: &[&str]) -> Self {
+        let state = Arc::new(Mutex::new(OllamaState {
+            models: models.iter().map(|m| m.to_string()).collect(),
+            ..Default::default()
+        }));
+
+        let app = Router::new()
+            .route("/api/tags", get(tags))
+            .route("/api/chat", post(generate))
+            .route("/api/generate", post(generate))
+            .route("/api/embeddings", post(embeddings))
+            .with_state(state.clone());
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        Self { url, state }
+    }
+
+    pub fn state(&self) -> std::sync::MutexGuard<'_, OllamaState> {
+        self.state.lock().unwrap()
+    }
+
+    /// Queue a reply whose content is the given JSON object.
+    pub fn reply_json(&self, value: Value) -> &Self {
+        self.reply_content(&value.to_string())
+    }
+
+    pub fn reply_content(&self, content: &str) -> &Self {
+        self.state()
+            .replies
+            .push_back(Reply::Content(content.to_string()));
+        self
+    }
+
+    /// Queue raw NDJSON lines, e.g. to send malformed chunks.
+    pub fn reply_lines(&self, lines: &[&str]) -> &Self {
+        self.state()
+            .replies
+            .push_back(Reply::Lines(lines.iter().map(|l| l.to_string()).collect()));
+        self
+    }
+
+    pub fn reply_status(&self, status: u16) -> &Self {
+        self.state().replies.push_back(Reply::Status(status));
+        self
+    }
+
+    pub fn reply_stall(&self, delay: Duration) -> &Self {
+        self.state().replies.push_back(Reply::Stall(delay));
+        self
+    }
+
+    pub fn set_embedding(&self, text: &str, vector: &[f32]) {
+        self.state()
+            .embeddings
+            .insert(text.to_string(), vector.to_vec());
+    }
+
+    /// Bodies of the generation requests (`/api/chat` and `/api/generate`).
+    pub fn generation_requests(&self) -> Vec<Value> {
+        self.state()
+            .requests
+            .iter()
+            .filter(|(path, _)| path == "/api/chat" || path == "/api/generate")
+            .map(|(_, body)| body.clone())
+            .collect()
+    }
+
+    pub fn pending_replies(&self) -> usize {
+        self.state().replies.len()
+    }
+}
+
+async fn tags(State(state): State<Arc<Mutex<OllamaState>>>) -> Json<Value> {
+    let state = state.lock().unwrap();
+    let models: Vec<Value> = state.models.iter().map(|m| json!({ "name": m })).collect();
+    Json(json!({ "models": models }))
+}
+
+async fn generate(
+    State(state): State<Arc<Mutex<OllamaState>>>,
+    uri: Uri,
+    Json(body): Json<Value>,
+) -> Response {
+    let path = uri.path().to_string();
+    let reply = {
+        let mut state = state.lock().unwrap();
+        state.requests.push((path.clone(), body));
+        state.replies.pop_front()
+    };
+
+    let lines = match reply {
+        Some(Reply::Content(content)) => content_lines(&path, &content),
+        Some(Reply::Lines(lines)) => lines,
+        Some(Reply::Status(status)) => {
+            return StatusCode::from_u16(status).unwrap().into_response();
+        }
+        Some(Reply::Stall(delay)) => {
+            tokio::time::sleep(delay).await;
+            return StatusCode::GATEWAY_TIMEOUT.into_response();
+        }
+        None => {
+            return (StatusCode::INTERNAL_SERVER_ERROR, "no scripted reply").into_response();
+        }
+    };
+
+    let mut body = lines.join("\n").into_bytes();
+    body.push(b'\n');
+    let chunks: Vec<Result<Bytes, Infallible>> = body
+        .chunks(CHUNK_BYTES)
+        .map(|c| Ok(Bytes::copy_from_slice(c)))
+        .collect();
+    Body::from_stream(futures_util::stream::iter(chunks)).into_response()
+}
+
+/// Split content into a few tokens and wrap each in the endpoint's chunk format.
+fn content_lines(path: &str, content: &str) -> Vec<String> {
+    let chars: Vec<char> = content.chars().collect();
+    let mut lines: Vec<String> = chars
+        .chunks(5)
+        .map(|piece| {
+            let piece: String = piece.iter().collect();
+            chunk_line(path, &piece, false)
+        })
+        .collect();
+    // One token per content chunk
+    let tokens = lines.len() as u64;
+    let mut last: Value = serde_json::from_str(&chunk_line(path, "", true)).unwrap();
+    last["prompt_eval_count"] = json!(PROMPT_EVAL_COUNT);
+    last["eval_count"] = json!(tokens);
+    last["eval_duration"] = json!(tokens * NANOS_PER_TOKEN);
+    lines.push(last.to_string());
+    lines
+}
+
+fn chunk_line(path: &str, text: &str, done: bool) -> String {
+    if path == "/api/chat" {
+        json!({ "message": { "role": "assistant", "content": text }, "done": done }).to_string()
+    } else {
+        json!({ "response": text, "done": done }).to_string()
+    }
+}
+
+async fn embeddings(
+    State(state): State<Arc<Mutex<OllamaState>>>,
+    Json(body): Json<Value>,
+) -> Json<Value> {
+    let mut state = state.lock().unwrap();
+    let text = body["prompt"].as_str().unwrap_or_default().to_string();
+    state.requests.push(("/api/embeddings".into(), body));
+
+    let vector = state
+        .embeddings
+        .get(&text)
+        .cloned()
+        .unwrap_or_else(|| fallback_embedding(&text));
+    Json(json!({ "embedding": vector }))
+}
+
+/// Deterministic, mostly dissimilar vectors for texts without a scripted embedding.
+fn fallback_embedding(text: &str) -> Vec<f32> {
+    let mut vector = vec![0.0; 16];
+    for (i, c) in text.chars().enumerate() {
+        vector[(c as usize + i) % 16] += 1.0;
+    }
+    vector
+}
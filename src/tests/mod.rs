@@ -0,0 +1,136 @@
+//! End-to-end tests for `Engine` against in-process fakes of Ollama and AnkiConnect.
+
+mod cassette;
+mod duplicates;
+mod engine;
+mod errors;
+mod field_map;
+mod history;
+mod images;
+mod metrics;
+mod mock_anki;
+mod mock_ollama;
+mod notetype;
+mod postprocess;
+mod prompt_budget;
+mod regen;
+mod retry;
+mod review;
+mod server;
+mod shutdown;
+mod siblings;
+mod static_fields;
+mod storage;
+mod validation;
+
+use std::path::PathBuf;
+
+use serde_json::json;
+use tempfile::TempDir;
+
+use crate::anki_client::AnkiConnectClient;
+use crate::config::Config;
+use crate::engine::Engine;
+use crate::model_client::OllamaClient;
+use crate::storage::FileStorage;
+use crate::types::{CardRequest, JournalEntry, StoredHistory};
+
+use mock_anki::MockAnki;
+use mock_ollama::MockOllama;
+
+pub const DECK: &str = "Japanese";
+pub const NOTE_TYPE: &str = "Grammar";
+pub const FIELDS: &[&str] = &["Grammar", "Meaning", "Example"];
+
+/// A model reply for a grammar card.
+pub fn card(grammar: &str) -> serde_json::Value {
+    json!({
+        "Grammar": grammar,
+        "Meaning": format!("meaning of {}", grammar),
+        "Example": format!("例文：{}。", grammar),
+    })
+}
+
+/// A pair of fakes plus a config pointing at them and at a temp storage dir.
+pub struct Harness {
+    pub anki: MockAnki,
+    pub ollama: MockOllama,
+    pub config: Config,
+    pub dir: TempDir,
+}
+
+impl Harness {
+    pub async fn new() -> Self {
+        let anki = MockAnki::start(DECK, NOTE_TYPE, FIELDS).await;
+        let ollama = MockOllama::start(&["llama3:latest"]).await;
+        let dir = tempfile::tempdir().unwrap();
+
+        let mut config = Config {
+            ollama_url: ollama.url.clone(),
+            anki_url: anki.url.clone(),
+            deck: Some(DECK.to_string()),
+            note_type: NOTE_TYPE.to_string(),
+            fields: FIELDS.iter().map(|f| f.to_string()).collect(),
+            storage_path: dir.path().join("history.json").display().to_string(),
+            ..Config::default()
+        };
+        config.dedup.cache_path = dir.path().join("embeddings.json").display().to_string();
+        // Tests that exercise retries turn them on, with a short backoff
+        config.http.max_retries = 0;
+        config.http.retry_backoff_ms = 1;
+
+        Self {
+            anki,
+            ollama,
+            config,
+            dir,
+        }
+    }
+
+    pub fn model(&self) -> OllamaClient {
+        OllamaClient::new(self.config.ollama_url.clone(), self.config.model.clone())
+            .with_api(self.config.ollama_api)
+            .with_http(&self.config.http)
+            .unwrap()
+            .with_options(self.config.options.clone(), self.config.keep_alive.clone())
+    }
+
+    pub fn engine(&self) -> Engine {
+        self.engine_with(self.model())
+    }
+
+    pub fn engine_with(&self, model: OllamaClient) -> Engine {
+        let anki = AnkiConnectClient::new(self.config.anki_url.clone())
+            .with_http(&self.config.http)
+            .unwrap();
+        let storage = FileStorage::new(PathBuf::from(&self.config.storage_path));
+        Engine::new(model, anki, storage, self.config.clone())
+    }
+
+    pub fn request(&self, description: &str) -> CardRequest {
+        CardRequest {
+            description: description.to_string(),
+            fields: self.config.fields.clone(),
+            note_type: self.config.note_type.clone(),
+            deck: DECK.to_string(),
+            optional_fields: self.config.optional_fields,
+            images: Vec::new(),
+        }
+    }
+
+    pub fn history(&self) -> StoredHistory {
+        FileStorage::new(PathBuf::from(&self.config.storage_path))
+            .load_history()
+            .unwrap()
+    }
+
+    /// Lines of the batch journal.
+    pub fn journal(&self) -> Vec<JournalEntry> {
+        let path = FileStorage::new(PathBuf::from(&self.config.storage_path)).journal_path();
+        std::fs::read_to_string(path)
+            .unwrap_or_default()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect()
+    }
+}
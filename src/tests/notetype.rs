@@ -0,0 +1,71 @@
+use serde_json::json;
+
+use super::Harness;
+use crate::errors::AppError;
+use crate::notetype::NoteTypeDefinition;
+
+#[tokio::test]
+async fn missing_nested_deck_is_created_when_enabled() {
+    let mut h = Harness::new().await;
+    h.config.create_missing_deck = true;
+    h.ollama.reply_json(json!({
+        "Grammar": "ておく", "Meaning": "do in advance", "Example": "準備しておく。",
+    }));
+    let mut req = h.request("ておく");
+    req.deck = "Japanese::Grammar::N3".to_string();
+
+    h.engine().generate(&req).await.unwrap();
+
+    assert_eq!(
+        h.anki.state().decks,
+        vec!["Japanese", "Japanese::Grammar", "Japanese::Grammar::N3"]
+    );
+    assert_eq!(h.anki.notes()[0].deck, "Japanese::Grammar::N3");
+}
+
+#[tokio::test]
+async fn note_type_is_created_from_definition_file() {
+    let h = Harness::new().await;
+    let mut definition = NoteTypeDefinition::load_from_file(
+        std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("notetype.example.yaml"),
+    )
+    .unwrap();
+    definition.name = "Grammar (anki_gen)".to_string();
+
+    let engine = h.engine();
+    engine.create_note_type(&definition).await.unwrap();
+
+    let request = h
+        .anki
+        .state()
+        .requests
+        .iter()
+        .find(|r| r["action"] == "createModel")
+        .cloned()
+        .unwrap();
+    let params = &request["params"];
+    assert_eq!(params["modelName"], "Grammar (anki_gen)");
+    assert_eq!(
+        params["inOrderFields"],
+        json!(["Grammar", "Meaning", "Example", "JLPT"])
+    );
+    assert_eq!(params["cardTemplates"][0]["Name"], "Recognition");
+    assert!(params["css"].as_str().unwrap().contains(".card"));
+
+    let err = engine.create_note_type(&definition).await.unwrap_err();
+    assert!(matches!(err, AppError::Anki(ref m) if m.contains("already exists")));
+}
+
+#[test]
+fn definitions_without_templates_are_rejected() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("bad.json");
+    std::fs::write(
+        &path,
+        r#"{"name": "X", "fields": ["Front"], "templates": []}"#,
+    )
+    .unwrap();
+
+    let err = NoteTypeDefinition::load_from_file(&path).unwrap_err();
+    assert!(matches!(err, AppError::Config(ref m) if m.contains("at least one card template")));
+}
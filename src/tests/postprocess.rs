@@ -0,0 +1,98 @@
+use std::collections::HashMap;
+
+use serde_json::json;
+
+use super::Harness;
+use crate::config::{PostprocessConfig, Processor};
+use crate::postprocess::run;
+
+fn process(processor: Processor, text: &str) -> String {
+    run(processor, text, &PostprocessConfig::default())
+}
+
+#[test]
+fn markdown_becomes_inline_html() {
+    assert_eq!(
+        process(Processor::Markdown, "**食べる** to eat\nmore"),
+        "<strong>食べる</strong> to eat<br>more"
+    );
+    assert_eq!(
+        process(Processor::Markdown, "- one\n- two"),
+        "<ul>\n<li>one</li>\n<li>two</li>\n</ul>"
+    );
+}
+
+#[test]
+fn sanitize_keeps_allowlisted_tags_only() {
+    assert_eq!(
+        process(
+            Processor::Sanitize,
+            r#"<b onclick="x()">bold</b><script>alert(1)</script><font color="red">red</font> a < b"#
+        ),
+        "<b>bold</b>red a &lt; b"
+    );
+}
+
+#[test]
+fn strips_wrapping_quotes_and_fences() {
+    assert_eq!(process(Processor::StripQuotes, " \"食べる\" "), "食べる");
+    assert_eq!(
+        process(Processor::StripQuotes, r#""a" and "b""#),
+        r#""a" and "b""#
+    );
+    assert_eq!(
+        process(Processor::StripCodeFences, "```html\n<b>x</b>\n```"),
+        "<b>x</b>"
+    );
+    assert_eq!(process(Processor::NewlinesToBr, "a\r\nb\n"), "a<br>b");
+}
+
+#[test]
+fn furigana_round_trips_through_ruby() {
+    let ruby = process(
+        Processor::FuriganaToRuby,
+        "私は 日本[にほん]に 行[い]きます[1]",
+    );
+    assert_eq!(
+        ruby,
+        "私は<ruby>日本<rt>にほん</rt></ruby>に<ruby>行<rt>い</rt></ruby>きます[1]"
+    );
+    assert_eq!(
+        process(Processor::RubyToFurigana, &ruby),
+        "私は 日本[にほん]に 行[い]きます[1]"
+    );
+    assert_eq!(
+        process(
+            Processor::RubyToFurigana,
+            "<ruby>漢<rp>(</rp><rt>かん</rt><rp>)</rp>字<rt>じ</rt></ruby>"
+        ),
+        "漢[かん]字[じ]"
+    );
+}
+
+#[tokio::test]
+async fn chains_run_per_field_before_adding_note() {
+    let mut h = Harness::new().await;
+    h.config.postprocess.default = vec![Processor::StripQuotes, Processor::Sanitize];
+    h.config.postprocess.fields = HashMap::from([(
+        "Example".to_string(),
+        vec![Processor::Markdown, Processor::FuriganaToRuby],
+    )]);
+    h.ollama.reply_json(json!({
+        "Grammar": "\"ておく\"",
+        "Meaning": "do <i>in advance</i><img src=x>",
+        "Example": "準備[じゅんび]を**しておく**",
+    }));
+
+    let card = h.engine().generate(&h.request("ておく")).await.unwrap();
+
+    let note = &h.anki.notes()[0];
+    assert_eq!(note.fields["Grammar"], "ておく");
+    assert_eq!(note.fields["Meaning"], "do <i>in advance</i>");
+    assert_eq!(
+        note.fields["Example"],
+        "<ruby>準備<rt>じゅんび</rt></ruby>を<strong>しておく</strong>"
+    );
+    assert_eq!(card.fields["Grammar"], "ておく");
+    assert_eq!(h.history().used_items, vec!["ておく"]);
+}
@@ -0,0 +1,121 @@
+use std::collections::HashMap;
+
+use crate::prompt_budget::{ExclusionList, estimate_tokens};
+use crate::types::{HistoryEntry, StoredHistory};
+
+fn items(names: &[&str]) -> Vec<String> {
+    names.iter().map(|n| n.to_string()).collect()
+}
+
+fn groups(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+    pairs
+        .iter()
+        .map(|(item, group)| (item.to_string(), group.to_string()))
+        .collect()
+}
+
+#[test]
+fn tokens_are_estimated_per_ascii_chunk_and_per_other_char() {
+    assert_eq!(estimate_tokens(""), 0);
+    assert_eq!(estimate_tokens("abcd"), 1);
+    assert_eq!(estimate_tokens("abcde"), 2);
+    assert_eq!(estimate_tokens("ておく"), 3);
+    assert_eq!(estimate_tokens("～ておく form"), 6);
+}
+
+#[test]
+fn empty_history_lists_nothing() {
+    let list = ExclusionList::select(&[], &HashMap::new(), "JLPT N3 grammar", 100);
+
+    assert!(list.listed.is_empty());
+    assert_eq!(list.omitted, 0);
+    assert!(list.summary.is_none());
+    assert_eq!(list.render(), "none yet");
+}
+
+#[test]
+fn history_within_budget_is_listed_in_full() {
+    let used = items(&["ておく", "ながら", "ばかり"]);
+
+    let list = ExclusionList::select(&used, &HashMap::new(), "JLPT N3 grammar", 12);
+
+    assert_eq!(list.listed, used);
+    assert_eq!(list.omitted, 0);
+    assert!(list.summary.is_none());
+    assert_eq!(list.render(), "ておく, ながら, ばかり");
+}
+
+#[test]
+fn budget_smaller_than_one_entry_summarises_everything() {
+    let used = items(&["ておく", "ながら"]);
+    let groups = groups(&[("ておく", "\"JLPT N3 grammar\"")]);
+
+    let list = ExclusionList::select(&used, &groups, "JLPT N3 grammar", 1);
+
+    assert!(list.listed.is_empty());
+    assert_eq!(list.omitted, 2);
+    assert_eq!(list.summary.as_deref(), Some("\"JLPT N3 grammar\" (1), other (1)"));
+    assert!(
+        list.render()
+            .starts_with("none yet\n...plus 2 earlier items not listed here")
+    );
+}
+
+#[test]
+fn recent_items_come_first_then_the_most_relevant() {
+    // Each costs 4 tokens, except わけ at 3
+    let used = items(&["ておく", "ながら", "ばかり", "ために", "わけ"]);
+
+    // Half of 11 for recent items fits only わけ; ばかり matches the topic, and
+    // ために is the most recent of the rest
+    let list = ExclusionList::select(&used, &HashMap::new(), "ばかりの使い方", 11);
+
+    assert_eq!(list.listed, items(&["ばかり", "ために", "わけ"]));
+    assert_eq!(list.omitted, 2);
+    assert_eq!(list.summary.as_deref(), Some("other (2)"));
+}
+
+#[test]
+fn summary_groups_by_topic_or_deck_largest_first() {
+    let used: Vec<String> = (0..16).map(|i| format!("item{}", i)).collect();
+    let mut pairs = vec![
+        ("item0", "\"JLPT N2 grammar\""),
+        ("item1", "deck \"Japanese\""),
+        ("item2", "deck \"Japanese\""),
+        ("item3", "deck \"Japanese\""),
+    ];
+    let topics: Vec<String> = (0..11).map(|i| format!("\"topic {:02}\"", i)).collect();
+    let names: Vec<String> = (4..15).map(|i| format!("item{}", i)).collect();
+    pairs.extend(names.iter().zip(&topics).map(|(n, t)| (n.as_str(), t.as_str())));
+
+    let list = ExclusionList::select(&used, &groups(&pairs), "", 0);
+
+    let summary = list.summary.clone().unwrap();
+    assert!(
+        summary.starts_with("deck \"Japanese\" (3), \"JLPT N2 grammar\" (1), \"topic 00\" (1)")
+    );
+    // Twelve groups are named; the last topic and item15, which has none, are "other"
+    assert!(summary.ends_with("\"topic 09\" (1), other (2)"));
+    assert!(list.render().contains("by what they were generated for"));
+    assert_eq!(list.omitted, used.len());
+}
+
+#[test]
+fn items_are_grouped_by_next_topic_then_deck() {
+    let mut history = StoredHistory::default();
+    let entry = |item: &str, deck: Option<&str>, topic: Option<&str>| HistoryEntry {
+        item: item.into(),
+        deck: deck.map(String::from),
+        topic: topic.map(String::from),
+        ..Default::default()
+    };
+    history.record(entry("ておく", Some("Japanese"), Some("JLPT N3 grammar")));
+    history.record(entry("ながら", Some("Japanese"), None));
+    history.used_items.push("ばかり".into());
+
+    let groups = history.item_groups();
+
+    assert_eq!(groups["ておく"], "\"JLPT N3 grammar\"");
+    assert_eq!(groups["ながら"], "deck \"Japanese\"");
+    assert!(!groups.contains_key("ばかり"));
+}
@@ -0,0 +1,88 @@
+use serde_json::json;
+
+use super::{DECK, Harness, NOTE_TYPE};
+use crate::errors::AppError;
+
+fn existing(h: &Harness) -> u64 {
+    h.anki.add_existing_note(
+        DECK,
+        NOTE_TYPE,
+        &[
+            ("Grammar", "ておく"),
+            ("Meaning", "do in advance"),
+            ("Example", "old example"),
+        ],
+    )
+}
+
+#[tokio::test]
+async fn regenerates_only_the_listed_fields() {
+    let h = Harness::new().await;
+    let id = existing(&h);
+    h.ollama.reply_json(json!({ "Example": "準備しておく。" }));
+
+    let results = h
+        .engine()
+        .regen(&id.to_string(), &["Example".to_string()])
+        .await
+        .unwrap();
+
+    assert_eq!(results[0].note_id, Some(id));
+    let note = &h.anki.notes()[0];
+    assert_eq!(note.fields["Example"], "準備しておく。");
+    assert_eq!(note.fields["Meaning"], "do in advance");
+
+    let request = &h.ollama.generation_requests()[0];
+    assert_eq!(request["format"]["required"], json!(["Example"]));
+    let prompt = request["messages"][1]["content"].as_str().unwrap();
+    assert!(prompt.contains("Grammar: ておく\nMeaning: do in advance"));
+    assert!(prompt.contains("Example: old example"));
+
+    let edits = h.history().edits;
+    assert_eq!(edits.len(), 1);
+    assert_eq!(edits[0].previous["Example"], "old example");
+    assert!(h.history().used_items.is_empty());
+}
+
+#[tokio::test]
+async fn revert_restores_previous_values() {
+    let h = Harness::new().await;
+    let id = existing(&h);
+    h.ollama.reply_json(json!({ "Example": "準備しておく。" }));
+
+    let engine = h.engine();
+    engine
+        .regen(r#"Grammar:ておく"#, &["Example".to_string()])
+        .await
+        .unwrap();
+    engine.revert(id).await.unwrap();
+
+    assert_eq!(h.anki.notes()[0].fields["Example"], "old example");
+    assert!(h.history().edits[0].reverted);
+    assert!(matches!(
+        engine.revert(id).await.unwrap_err(),
+        AppError::NotFound(_)
+    ));
+}
+
+#[tokio::test]
+async fn unknown_fields_are_rejected_before_generating() {
+    let h = Harness::new().await;
+    let id = existing(&h);
+
+    let err = h
+        .engine()
+        .regen(&id.to_string(), &["Sentence".to_string()])
+        .await
+        .unwrap_err();
+
+    assert!(matches!(err, AppError::FieldMismatch(ref m) if m.contains("no field 'Sentence'")));
+    assert!(h.ollama.generation_requests().is_empty());
+
+    let err = h
+        .engine()
+        .regen("123", &["Example".to_string()])
+        .await
+        .unwrap_err();
+    assert!(matches!(err, AppError::NotFound(ref m) if m.contains("Note 123 not found")));
+}
@@ -0,0 +1,114 @@
+use std::time::Duration;
+
+use super::{Harness, card};
+use crate::errors::AppError;
+use crate::retry::RetryPolicy;
+
+#[tokio::test]
+async fn ollama_server_errors_are_retried() {
+    let mut h = Harness::new().await;
+    h.config.http.max_retries = 2;
+    h.ollama.reply_status(503);
+    h.ollama.reply_json(card("ておく"));
+
+    h.engine().generate(&h.request("ておく")).await.unwrap();
+
+    assert_eq!(h.ollama.generation_requests().len(), 2);
+    assert_eq!(h.anki.notes().len(), 1);
+}
+
+#[tokio::test]
+async fn retries_give_up_after_the_limit() {
+    let mut h = Harness::new().await;
+    h.config.http.max_retries = 1;
+    h.ollama.reply_status(500);
+    h.ollama.reply_status(500);
+    h.ollama.reply_json(card("ておく"));
+
+    let err = h.engine().generate(&h.request("ておく")).await.unwrap_err();
+
+    assert!(matches!(err, AppError::Model(ref m) if m.contains("500")));
+    assert_eq!(h.ollama.generation_requests().len(), 2);
+}
+
+#[tokio::test]
+async fn unavailable_collection_is_retried() {
+    let mut h = Harness::new().await;
+    h.config.http.max_retries = 3;
+    h.anki
+        .fail_action_times("addNote", "collection is not available", 2);
+    h.ollama.reply_json(card("ておく"));
+
+    h.engine().generate(&h.request("ておく")).await.unwrap();
+
+    let adds = h.anki.actions().iter().filter(|a| *a == "addNote").count();
+    assert_eq!(adds, 3);
+    assert_eq!(h.anki.notes().len(), 1);
+}
+
+#[tokio::test]
+async fn add_note_is_not_retried_after_a_timeout() {
+    let mut h = Harness::new().await;
+    h.config.http.max_retries = 3;
+    h.config.http.total_timeout_secs = 0.3;
+    h.anki.delay_action("addNote", Duration::from_secs(1));
+    h.ollama.reply_json(card("ておく"));
+
+    let err = h.engine().generate(&h.request("ておく")).await.unwrap_err();
+
+    assert!(matches!(
+        err,
+        AppError::Timeout {
+            service: "AnkiConnect",
+            ..
+        }
+    ));
+    let adds = h.anki.actions().iter().filter(|a| *a == "addNote").count();
+    assert_eq!(adds, 1);
+}
+
+#[tokio::test]
+async fn idempotent_actions_are_retried_after_a_timeout() {
+    let mut h = Harness::new().await;
+    h.config.http.max_retries = 1;
+    h.config.http.total_timeout_secs = 0.3;
+    h.anki.delay_action("deckNames", Duration::from_secs(1));
+
+    let err = h.engine().generate(&h.request("ておく")).await.unwrap_err();
+
+    assert!(matches!(err, AppError::Timeout { .. }));
+    let lookups = h
+        .anki
+        .actions()
+        .iter()
+        .filter(|a| *a == "deckNames")
+        .count();
+    assert_eq!(lookups, 2);
+}
+
+#[test]
+fn backoff_doubles_up_to_the_cap() {
+    let config = crate::config::HttpConfig {
+        retry_backoff_ms: 100,
+        max_backoff_ms: 350,
+        ..Default::default()
+    };
+    let policy = RetryPolicy::from_config(&config);
+
+    let delays: Vec<u128> = (1..=4).map(|n| policy.backoff(n).as_millis()).collect();
+    assert_eq!(delays, vec![100, 200, 350, 350]);
+}
+
+#[tokio::test]
+async fn ollama_timeouts_are_not_retried() {
+    let mut h = Harness::new().await;
+    h.config.http.max_retries = 2;
+    h.config.http.total_timeout_secs = 0.3;
+    h.ollama.reply_stall(Duration::from_secs(1));
+    h.ollama.reply_json(card("ておく"));
+
+    let err = h.engine().generate(&h.request("ておく")).await.unwrap_err();
+
+    assert!(matches!(err, AppError::Timeout { service: "Ollama", .. }));
+    assert_eq!(h.ollama.generation_requests().len(), 1);
+}
@@ -0,0 +1,97 @@
+use serde_json::{Value, json};
+
+use super::Harness;
+use crate::config::ReviewAction;
+use crate::engine::Engine;
+use crate::errors::AppError;
+use crate::review::Reviewer;
+
+fn card(meaning: &str) -> Value {
+    json!({ "Grammar": "ておく", "Meaning": meaning, "Example": "準備しておく。" })
+}
+
+fn reviewed_engine(h: &mut Harness, action: ReviewAction) -> Engine {
+    h.config.review.enabled = true;
+    h.config.review.action = action;
+    h.config.review.queue_path = h.dir.path().join("review.json").display().to_string();
+    h.engine()
+        .with_reviewer(Reviewer::new(h.model(), h.config.review.clone()))
+}
+
+#[tokio::test]
+async fn passing_cards_are_added_untagged() {
+    let mut h = Harness::new().await;
+    h.ollama.reply_json(card("do in advance"));
+    h.ollama.reply_json(json!({ "score": 9, "issues": [] }));
+
+    let engine = reviewed_engine(&mut h, ReviewAction::Tag);
+    engine.generate(&h.request("ておく")).await.unwrap();
+
+    let requests = h.ollama.generation_requests();
+    let review_prompt = requests[1]["messages"][1]["content"].as_str().unwrap();
+    assert!(review_prompt.contains("Meaning: do in advance"));
+    assert_eq!(
+        requests[1]["format"]["required"],
+        json!(["score", "issues"])
+    );
+    assert!(h.anki.notes()[0].tags.is_empty());
+}
+
+#[tokio::test]
+async fn low_scores_are_tagged() {
+    let mut h = Harness::new().await;
+    h.ollama.reply_json(card("to eat"));
+    h.ollama
+        .reply_json(json!({ "score": 3, "issues": ["Meaning is wrong"] }));
+
+    let engine = reviewed_engine(&mut h, ReviewAction::Tag);
+    engine.generate(&h.request("ておく")).await.unwrap();
+
+    assert_eq!(h.anki.notes()[0].tags, vec!["anki_gen::needs_review"]);
+}
+
+#[tokio::test]
+async fn low_scores_are_regenerated_with_the_issues() {
+    let mut h = Harness::new().await;
+    h.ollama.reply_json(card("to eat"));
+    h.ollama
+        .reply_json(json!({ "score": 3, "issues": ["Meaning is wrong"] }));
+    h.ollama.reply_json(card("do in advance"));
+    h.ollama.reply_json(json!({ "score": 8, "issues": [] }));
+
+    let engine = reviewed_engine(&mut h, ReviewAction::Regenerate);
+    engine.generate(&h.request("ておく")).await.unwrap();
+
+    let note = &h.anki.notes()[0];
+    assert_eq!(note.fields["Meaning"], "do in advance");
+    assert!(note.tags.is_empty());
+    let retry = &h.ollama.generation_requests()[2]["messages"];
+    assert!(retry[2]["content"].as_str().unwrap().contains("to eat"));
+    assert!(
+        retry[3]["content"]
+            .as_str()
+            .unwrap()
+            .contains("Meaning is wrong")
+    );
+}
+
+#[tokio::test]
+async fn low_scores_are_held_for_manual_review() {
+    let mut h = Harness::new().await;
+    h.ollama.reply_json(card("to eat"));
+    h.ollama
+        .reply_json(json!({ "score": 2.5, "issues": ["Meaning is wrong"] }));
+
+    let engine = reviewed_engine(&mut h, ReviewAction::Hold);
+    let err = engine.generate(&h.request("ておく")).await.unwrap_err();
+
+    assert!(matches!(err, AppError::HeldForReview(_)));
+    assert!(h.anki.notes().is_empty());
+    assert!(h.history().used_items.is_empty());
+    let queue: Value =
+        serde_json::from_str(&std::fs::read_to_string(&h.config.review.queue_path).unwrap())
+            .unwrap();
+    assert_eq!(queue[0]["item"], "ておく");
+    assert_eq!(queue[0]["fields"]["Meaning"], "to eat");
+    assert_eq!(queue[0]["issues"], json!(["Meaning is wrong"]));
+}
@@ -0,0 +1,178 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use serde_json::{Value, json};
+use tokio::net::TcpListener;
+
+use super::{Harness, card};
+use crate::server::{self, AppState};
+
+/// Start the API on an ephemeral port. Returns its base URL.
+async fn start(h: &Harness) -> String {
+    let state = Arc::new(AppState::new(h.engine(), h.config.clone()));
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    tokio::spawn(async move { axum::serve(listener, server::router(state)).await.unwrap() });
+    url
+}
+
+/// Poll a queued job until it has finished.
+async fn wait_for_job(client: &reqwest::Client, url: &str, mut job: Value) -> Value {
+    let id = job["id"].as_u64().unwrap();
+    for _ in 0..100 {
+        job = client
+            .get(format!("{}/jobs/{}", url, id))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        if job["status"] == "completed" || job["status"] == "failed" {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    job
+}
+
+#[tokio::test]
+async fn generate_returns_note_id() {
+    let h = Harness::new().await;
+    h.ollama.reply_json(card("ておく"));
+    let url = start(&h).await;
+
+    let response = reqwest::Client::new()
+        .post(format!("{}/generate", url))
+        .json(&json!({ "description": "ておく" }))
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), 200);
+    let body: Value = response.json().await.unwrap();
+    assert_eq!(body["note_id"], json!(h.anki.notes()[0].id));
+    assert_eq!(body["fields"]["Grammar"], "ておく");
+}
+
+#[tokio::test]
+async fn duplicate_is_a_conflict() {
+    let mut h = Harness::new().await;
+    h.config.next_max_attempts = 1;
+    h.ollama.reply_json(card("ておく"));
+    h.ollama.reply_json(card("ておく"));
+    let url = start(&h).await;
+    let client = reqwest::Client::new();
+
+    for expected in [200, 409] {
+        let response = client
+            .post(format!("{}/next", url))
+            .json(&json!({ "description": "JLPT N3 grammar" }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), expected);
+    }
+}
+
+#[tokio::test]
+async fn batch_job_reports_note_ids_when_completed() {
+    let h = Harness::new().await;
+    h.ollama.reply_json(card("ておく"));
+    h.ollama.reply_content("not json");
+    let url = start(&h).await;
+    let client = reqwest::Client::new();
+
+    let response = client
+        .post(format!("{}/batch", url))
+        .json(&json!({ "items": ["ておく", "ながら"] }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 202);
+    let job = wait_for_job(&client, &url, response.json().await.unwrap()).await;
+
+    assert_eq!(job["status"], "completed");
+    assert_eq!(job["report"]["succeeded"], 1);
+    assert_eq!(
+        job["report"]["cards"][0]["note_id"],
+        json!(h.anki.notes()[0].id)
+    );
+    assert_eq!(job["report"]["failures"][0]["item"], "ながら");
+
+    let history: Value = client
+        .get(format!("{}/history", url))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(history["used_items"], json!(["ておく"]));
+
+    let missing = client.get(format!("{}/jobs/99", url)).send().await.unwrap();
+    assert_eq!(missing.status(), 404);
+}
+
+#[tokio::test]
+async fn failed_batch_job_keeps_its_report() {
+    let h = Harness::new().await;
+    h.ollama.reply_content("not json");
+    h.ollama.reply_content("still not json");
+    let url = start(&h).await;
+    let client = reqwest::Client::new();
+
+    let response = client
+        .post(format!("{}/batch", url))
+        .json(&json!({ "items": ["ておく", "ながら"] }))
+        .send()
+        .await
+        .unwrap();
+    let job = wait_for_job(&client, &url, response.json().await.unwrap()).await;
+
+    assert_eq!(job["status"], "failed");
+    assert_eq!(job["error"], "All batch items failed");
+    assert_eq!(job["report"]["failed"], 2);
+    assert_eq!(job["report"]["failures"][1]["item"], "ながら");
+}
+
+#[tokio::test]
+async fn generate_takes_base64_images_not_paths() {
+    let h = Harness::new().await;
+    h.ollama.reply_json(card("ておく"));
+    let url = start(&h).await;
+    let client = reqwest::Client::new();
+    // PNG signature followed by anything
+    let png = "iVBORw0KGgptYW5nYSBwYW5lbA==";
+
+    let response = client
+        .post(format!("{}/generate", url))
+        .json(&json!({ "description": "grammar in this panel", "images": [png] }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 200);
+    assert_eq!(
+        h.ollama.generation_requests()[0]["messages"][1]["images"],
+        json!([png])
+    );
+
+    let path = h.dir.path().join("secret.png");
+    std::fs::write(&path, b"\x89PNG\r\n\x1a\nsecret").unwrap();
+    let response = client
+        .post(format!("{}/generate", url))
+        .json(&json!({ "description": "ておく", "images": [path] }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 400);
+
+    let response = client
+        .post(format!("{}/batch", url))
+        .json(&json!({ "items": [format!("{} | ておく", path.display())] }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 400);
+    assert_eq!(h.ollama.generation_requests().len(), 1);
+}
@@ -0,0 +1,124 @@
+use std::time::Duration;
+
+use super::{Harness, card};
+use crate::shutdown::Shutdown;
+use crate::types::JournalStatus;
+
+#[tokio::test]
+async fn batch_finishes_the_current_item_and_saves_history() {
+    let h = Harness::new().await;
+    for item in ["ておく", "ながら", "ばかり"] {
+        h.ollama.reply_json(card(item));
+    }
+    // Interrupt while the first note is being added
+    h.anki.delay_action("addNote", Duration::from_millis(200));
+    let shutdown = Shutdown::new();
+    let anki = h.anki.clone();
+    let trigger = shutdown.clone();
+    tokio::spawn(async move {
+        while !anki.actions().iter().any(|a| a == "addNote") {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        trigger.request();
+    });
+
+    let items = ["ておく", "ながら", "ばかり"].map(String::from);
+    let report = h
+        .engine()
+        .with_shutdown(shutdown)
+        .batch(&h.request(""), &items)
+        .await
+        .unwrap();
+
+    assert!(report.interrupted);
+    assert_eq!((report.succeeded, report.failed), (1, 0));
+    assert_eq!(h.anki.notes().len(), 1);
+    assert_eq!(h.ollama.generation_requests().len(), 1);
+
+    let history = h.history();
+    assert_eq!(history.used_items, vec!["ておく"]);
+    assert!(history.runs[0].interrupted);
+    assert_eq!(history.runs[0].cards_added, 1);
+
+    let journal = h.journal();
+    let statuses: Vec<_> = journal.iter().map(|e| (e.item.as_str(), e.status)).collect();
+    assert_eq!(
+        statuses,
+        vec![
+            ("ておく", JournalStatus::Attempted),
+            ("ておく", JournalStatus::Succeeded),
+        ]
+    );
+    assert_eq!(journal[1].note_id, Some(h.anki.notes()[0].id));
+}
+
+#[tokio::test]
+async fn batch_journal_records_failed_items() {
+    let h = Harness::new().await;
+    h.ollama.reply_json(card("ておく"));
+    h.ollama.reply_content("not json at all");
+
+    let items = ["ておく", "ながら"].map(String::from);
+    h.engine().batch(&h.request(""), &items).await.unwrap();
+
+    let journal = h.journal();
+    let statuses: Vec<_> = journal.iter().map(|e| (e.item.as_str(), e.status)).collect();
+    assert_eq!(
+        statuses,
+        vec![
+            ("ておく", JournalStatus::Attempted),
+            ("ておく", JournalStatus::Succeeded),
+            ("ながら", JournalStatus::Attempted),
+            ("ながら", JournalStatus::Failed),
+        ]
+    );
+    assert!(journal[3].error.as_deref().unwrap().contains("not valid JSON"));
+    assert!(journal.iter().all(|e| e.run == journal[0].run));
+}
+
+#[tokio::test]
+async fn batch_interrupted_before_any_item_is_not_an_error() {
+    let h = Harness::new().await;
+    let shutdown = Shutdown::new();
+    shutdown.request();
+
+    let items = ["ておく".to_string()];
+    let report = h
+        .engine()
+        .with_shutdown(shutdown)
+        .batch(&h.request(""), &items)
+        .await
+        .unwrap();
+
+    assert!(report.interrupted);
+    assert!(h.ollama.generation_requests().is_empty());
+    assert!(h.history().runs[0].interrupted);
+}
+
+#[tokio::test]
+async fn aborted_batch_keeps_items_already_added() {
+    let h = Harness::new().await;
+    for item in ["ておく", "ながら", "ばかり"] {
+        h.ollama.reply_json(card(item));
+    }
+    h.anki.delay_action("addNote", Duration::from_millis(200));
+
+    let engine = h.engine();
+    let request = h.request("");
+    let run = tokio::spawn(async move {
+        let items = ["ておく", "ながら", "ばかり"].map(String::from);
+        engine.batch(&request, &items).await
+    });
+    // The second item is generated only once the first is added and recorded
+    while h.ollama.generation_requests().len() < 2 {
+        tokio::time::sleep(Duration::from_millis(5)).await;
+    }
+    // Like a second Ctrl-C: the run stops without reaching the end of the batch
+    run.abort();
+    assert!(run.await.unwrap_err().is_cancelled());
+
+    let history = h.history();
+    assert_eq!(history.used_items, vec!["ておく"]);
+    assert_eq!(history.entries[0].note_id, Some(h.anki.notes()[0].id));
+    assert!(history.runs.is_empty());
+}
@@ -0,0 +1,129 @@
+use std::collections::BTreeMap;
+
+use serde_json::json;
+
+use super::{DECK, Harness, NOTE_TYPE, card};
+use crate::config::{DuplicatePolicy, NoteMapping};
+use crate::errors::AppError;
+use crate::field_map::FieldSource;
+use crate::types::NoteAction;
+
+const REVERSE: &str = "Vocab EN→JP";
+
+/// A reverse note type whose fields come from the generated card.
+fn with_reverse_sibling(h: &mut Harness, fields: &[(&str, &str)]) {
+    {
+        let mut state = h.anki.state();
+        state.decks.push("Japanese::Reverse".into());
+        state
+            .models
+            .insert(REVERSE.into(), vec!["Front".into(), "Back".into()]);
+    }
+    h.config.siblings = vec![NoteMapping {
+        deck: Some("Japanese::Reverse".into()),
+        note_type: REVERSE.into(),
+        fields: fields
+            .iter()
+            .map(|(field, key)| (field.to_string(), FieldSource::Key(key.to_string())))
+            .collect::<BTreeMap<_, _>>(),
+    }];
+}
+
+#[tokio::test]
+async fn one_generation_fills_main_and_sibling_notes() {
+    let mut h = Harness::new().await;
+    with_reverse_sibling(&mut h, &[("Front", "Meaning"), ("Back", "Grammar")]);
+    h.ollama.reply_json(json!({
+        "Grammar": "ておく",
+        "Meaning": "do in advance",
+        "Example": "買っておく。",
+    }));
+
+    let card = h.engine().generate(&h.request("ておく")).await.unwrap();
+
+    assert_eq!(h.ollama.generation_requests().len(), 1);
+    let notes = h.anki.notes();
+    assert_eq!(notes.len(), 2);
+    assert_eq!(notes[0].model, NOTE_TYPE);
+    assert_eq!(notes[0].deck, DECK);
+    assert_eq!(notes[1].model, REVERSE);
+    assert_eq!(notes[1].deck, "Japanese::Reverse");
+    assert_eq!(notes[1].fields["Front"], notes[0].fields["Meaning"]);
+    assert_eq!(notes[1].fields["Back"], "ておく");
+
+    assert_eq!(card.siblings.len(), 1);
+    assert_eq!(card.siblings[0].note_id, Some(notes[1].id));
+    assert_eq!(card.siblings[0].action, NoteAction::Added);
+    assert_eq!(h.history().used_items, vec!["ておく"]);
+}
+
+#[tokio::test]
+async fn sibling_keys_are_added_to_the_generation() {
+    let mut h = Harness::new().await;
+    with_reverse_sibling(&mut h, &[("Front", "English"), ("Back", "Grammar")]);
+    h.ollama.reply_json(json!({
+        "Grammar": "ながら",
+        "Meaning": "while",
+        "Example": "歩きながら。",
+        "English": "while doing",
+    }));
+
+    let items = vec!["ながら".to_string()];
+    let report = h.engine().batch(&h.request(""), &items).await.unwrap();
+
+    assert_eq!(report.succeeded, 1);
+    let request = &h.ollama.generation_requests()[0];
+    assert_eq!(
+        request["format"]["required"],
+        json!(["Grammar", "Meaning", "Example", "English"])
+    );
+    let notes = h.anki.notes();
+    assert!(!notes[0].fields.contains_key("English"));
+    assert_eq!(notes[1].fields["Front"], "while doing");
+}
+
+#[tokio::test]
+async fn unknown_sibling_field_fails_before_generation() {
+    let mut h = Harness::new().await;
+    with_reverse_sibling(&mut h, &[("Frnt", "Meaning")]);
+
+    let err = h.engine().generate(&h.request("ておく")).await.unwrap_err();
+
+    assert!(matches!(err, AppError::FieldMismatch(_)));
+    assert!(h.ollama.generation_requests().is_empty());
+}
+
+#[tokio::test]
+async fn failed_sibling_is_reported_without_failing_the_card() {
+    let mut h = Harness::new().await;
+    with_reverse_sibling(&mut h, &[("Front", "Meaning"), ("Back", "Grammar")]);
+    h.anki.add_existing_note(
+        "Japanese::Reverse",
+        REVERSE,
+        &[("Front", "meaning of ておく"), ("Back", "ておく")],
+    );
+    h.ollama.reply_json(card("ておく"));
+
+    let card = h.engine().generate(&h.request("ておく")).await.unwrap();
+
+    assert!(card.note_id.is_some());
+    assert_eq!(card.siblings[0].action, NoteAction::Failed);
+    assert!(card.siblings[0].error.as_deref().unwrap().contains("already exists"));
+    assert_eq!(h.anki.notes().len(), 2);
+    assert_eq!(h.history().used_items, vec!["ておく"]);
+}
+
+#[tokio::test]
+async fn siblings_of_a_skipped_duplicate_are_skipped() {
+    let mut h = Harness::new().await;
+    h.config.duplicates.policy = DuplicatePolicy::Skip;
+    with_reverse_sibling(&mut h, &[("Front", "Meaning"), ("Back", "Grammar")]);
+    h.anki.add_existing_note(DECK, NOTE_TYPE, &[("Grammar", "ておく")]);
+    h.ollama.reply_json(card("ておく"));
+
+    let card = h.engine().generate(&h.request("ておく")).await.unwrap();
+
+    assert_eq!(card.action, NoteAction::Skipped);
+    assert!(card.siblings.is_empty());
+    assert_eq!(h.anki.notes().len(), 1);
+}
@@ -0,0 +1,100 @@
+use std::path::Path;
+
+use chrono::Local;
+use serde_json::json;
+
+use super::{Harness, NOTE_TYPE, card};
+use crate::errors::AppError;
+use crate::types::BatchItem;
+
+/// The note type gains fields the model shouldn't fill.
+fn with_static_fields(h: &mut Harness, yaml: &str) {
+    h.anki.state().models.get_mut(NOTE_TYPE).unwrap().extend([
+        "Source".into(),
+        "JLPT".into(),
+        "Audio".into(),
+    ]);
+    h.config.fields.push("Source".into());
+    h.config.static_fields = serde_yaml::from_str(yaml).unwrap();
+}
+
+#[tokio::test]
+async fn static_fields_bypass_the_model() {
+    let mut h = Harness::new().await;
+    with_static_fields(
+        &mut h,
+        "Source: \"anki_gen {command} {date:%Y-%m}\"\n\
+         Audio: { command: 'printf \"[sound:%s.mp3]\" \"$ANKI_GEN_ITEM\"' }\n",
+    );
+    h.ollama.reply_json(card("ておく"));
+
+    h.engine().generate(&h.request("ておく")).await.unwrap();
+
+    let request = &h.ollama.generation_requests()[0];
+    assert_eq!(
+        request["format"]["required"],
+        json!(["Grammar", "Meaning", "Example"])
+    );
+    let prompt = request["messages"][1]["content"].as_str().unwrap();
+    assert!(!prompt.contains("Source") && !prompt.contains("Audio"));
+
+    let note = &h.anki.notes()[0];
+    assert_eq!(
+        note.fields["Source"],
+        format!("anki_gen generate {}", Local::now().format("%Y-%m"))
+    );
+    assert_eq!(note.fields["Audio"], "[sound:ておく.mp3]");
+}
+
+#[tokio::test]
+async fn batch_rows_fill_static_fields() {
+    let mut h = Harness::new().await;
+    with_static_fields(
+        &mut h,
+        "JLPT: \"{column:JLPT}\"\nSource: \"{batch_file}\"\n",
+    );
+    h.ollama.reply_json(card("ておく"));
+
+    let mut row = BatchItem::new("ておく");
+    row.columns.insert("JLPT".into(), "N4".into());
+    let report = h
+        .engine()
+        .batch_items(&h.request(""), &[row], Some(Path::new("lists/n4.tsv")))
+        .await
+        .unwrap();
+
+    assert_eq!(report.succeeded, 1);
+    let note = &h.anki.notes()[0];
+    assert_eq!(note.fields["JLPT"], "N4");
+    assert_eq!(note.fields["Source"], "n4.tsv");
+}
+
+#[tokio::test]
+async fn failing_commands_and_unknown_placeholders_are_errors() {
+    let mut h = Harness::new().await;
+    with_static_fields(
+        &mut h,
+        "Source: manual\nAudio: { command: 'echo no voice >&2; exit 3' }\n",
+    );
+    h.ollama.reply_json(card("ておく"));
+
+    let err = h.engine().generate(&h.request("ておく")).await.unwrap_err();
+    assert!(matches!(err, AppError::StaticField(_)));
+    assert!(err.to_string().contains("no voice"));
+
+    h.config.static_fields = serde_yaml::from_str("Source: \"{batch}\"").unwrap();
+    h.ollama.reply_json(card("ておく"));
+    let err = h.engine().generate(&h.request("ておく")).await.unwrap_err();
+    assert!(matches!(err, AppError::Config(_)));
+    assert!(h.anki.notes().is_empty());
+}
+
+#[test]
+fn tsv_rows_keep_their_columns() {
+    let rows = crate::parse_rows("item\tJLPT\tnote\nておく\tN4\n\n\tN3\nながら\tN3\textra\n");
+    assert_eq!(rows.len(), 2);
+    assert_eq!(rows[0].item, "ておく");
+    assert_eq!(rows[0].columns["JLPT"], "N4");
+    assert!(!rows[0].columns.contains_key("note"));
+    assert_eq!(rows[1].columns["note"], "extra");
+}
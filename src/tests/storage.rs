@@ -0,0 +1,75 @@
+use std::sync::Arc;
+
+use crate::storage::FileStorage;
+
+#[test]
+fn concurrent_updates_are_not_lost() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("history.json");
+
+    let handles: Vec<_> = (0..8)
+        .map(|t| {
+            let storage = Arc::new(FileStorage::new(path.clone()));
+            std::thread::spawn(move || {
+                for i in 0..10 {
+                    storage
+                        .update(|h| h.used_items.push(format!("{}-{}", t, i)))
+                        .unwrap();
+                }
+            })
+        })
+        .collect();
+    for handle in handles {
+        handle.join().unwrap();
+    }
+
+    let history = FileStorage::new(path).load_history().unwrap();
+    assert_eq!(history.used_items.len(), 80);
+}
+
+#[test]
+fn corrupt_history_is_recovered_from_backup() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("history.json");
+    let storage = FileStorage::new(path.clone());
+    storage
+        .update(|h| h.used_items.push("ておく".into()))
+        .unwrap();
+    storage
+        .update(|h| h.used_items.push("ながら".into()))
+        .unwrap();
+
+    // A crash or another tool left half a file behind
+    std::fs::write(&path, r#"{"used_items": ["ておく", "なが"#).unwrap();
+
+    let history = storage.load_history().unwrap();
+    assert_eq!(history.used_items, vec!["ておく"]);
+
+    storage
+        .update(|h| h.used_items.push("ばかり".into()))
+        .unwrap();
+    let history = storage.load_history().unwrap();
+    assert_eq!(history.used_items, vec!["ておく", "ばかり"]);
+}
+
+#[test]
+fn writes_leave_no_temp_files() {
+    let dir = tempfile::tempdir().unwrap();
+    let storage = FileStorage::new(dir.path().join("history.json"));
+    storage
+        .update(|h| h.used_items.push("ておく".into()))
+        .unwrap();
+    storage
+        .update(|h| h.used_items.push("ながら".into()))
+        .unwrap();
+
+    let mut names: Vec<String> = std::fs::read_dir(dir.path())
+        .unwrap()
+        .map(|e| e.unwrap().file_name().to_string_lossy().into_owned())
+        .collect();
+    names.sort();
+    assert_eq!(
+        names,
+        vec!["history.json", "history.json.bak", "history.json.lock"]
+    );
+}
@@ -0,0 +1,150 @@
+use std::collections::HashMap;
+
+use serde_json::json;
+
+use super::Harness;
+use crate::config::{Config, Rule};
+use crate::errors::AppError;
+use crate::types::CardFields;
+use crate::validation::check;
+
+fn fields(pairs: &[(&str, &str)]) -> CardFields {
+    pairs
+        .iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect()
+}
+
+fn rules(pairs: &[(&str, Vec<Rule>)]) -> HashMap<String, Vec<Rule>> {
+    pairs
+        .iter()
+        .map(|(k, v)| (k.to_string(), v.clone()))
+        .collect()
+}
+
+#[test]
+fn reports_field_and_rule_for_each_violation() {
+    let rules = rules(&[
+        (
+            "Example",
+            vec![
+                Rule::ContainsJapanese,
+                Rule::ContainsKey,
+                Rule::MaxLength(10),
+            ],
+        ),
+        ("JLPT", vec![Rule::JlptLevel]),
+        ("Meaning", vec![Rule::NotEqual("Grammar".into())]),
+    ]);
+    let card = fields(&[
+        ("Grammar", "～ておく"),
+        ("Meaning", "～ておく"),
+        ("Example", "I prepared it in advance."),
+        ("JLPT", "N6"),
+    ]);
+
+    let violations = check(&rules, &card, Some("Grammar"));
+    let names: Vec<(&str, &str)> = violations
+        .iter()
+        .map(|v| (v.field.as_str(), v.rule))
+        .collect();
+    assert_eq!(
+        names,
+        vec![
+            ("Example", "contains_japanese"),
+            ("Example", "contains_key"),
+            ("Example", "max_length"),
+            ("JLPT", "jlpt_level"),
+            ("Meaning", "not_equal"),
+        ]
+    );
+    assert_eq!(
+        violations[1].to_string(),
+        "field 'Example' failed contains_key: must contain the key expression 'ておく'"
+    );
+}
+
+#[test]
+fn passing_and_empty_values_are_not_reported() {
+    let rules = rules(&[
+        (
+            "Example",
+            vec![
+                Rule::ContainsKey,
+                Rule::Pattern("。$".parse().unwrap()),
+                Rule::MinLength(5),
+            ],
+        ),
+        ("Notes", vec![Rule::MinLength(3)]),
+        ("JLPT", vec![Rule::OneOf(vec!["N3".into(), "N2".into()])]),
+    ]);
+    let card = fields(&[
+        ("Grammar", "～ておく"),
+        ("Example", "準備しておく。"),
+        ("Notes", ""),
+        ("JLPT", "N3"),
+    ]);
+
+    assert!(check(&rules, &card, Some("Grammar")).is_empty());
+}
+
+#[tokio::test]
+async fn violations_are_sent_back_for_a_retry() {
+    let mut h = Harness::new().await;
+    h.config.validation = rules(&[("Example", vec![Rule::ContainsKey])]);
+    h.ollama.reply_json(json!({
+        "Grammar": "ておく", "Meaning": "do in advance", "Example": "準備します。",
+    }));
+    h.ollama.reply_json(json!({
+        "Grammar": "ておく", "Meaning": "do in advance", "Example": "準備しておく。",
+    }));
+
+    h.engine().generate(&h.request("ておく")).await.unwrap();
+
+    assert_eq!(h.anki.notes()[0].fields["Example"], "準備しておく。");
+    let retry = &h.ollama.generation_requests()[1]["messages"];
+    assert_eq!(retry[2]["role"], "assistant");
+    assert!(
+        retry[2]["content"]
+            .as_str()
+            .unwrap()
+            .contains("準備します。")
+    );
+    assert!(
+        retry[3]["content"]
+            .as_str()
+            .unwrap()
+            .contains("field 'Example' failed contains_key")
+    );
+}
+
+#[tokio::test]
+async fn gives_up_after_max_attempts() {
+    let mut h = Harness::new().await;
+    h.config.validation = rules(&[("Meaning", vec![Rule::ContainsJapanese])]);
+    h.config.validation_max_attempts = 2;
+    for _ in 0..2 {
+        h.ollama.reply_json(json!({
+            "Grammar": "ておく", "Meaning": "do in advance", "Example": "準備しておく。",
+        }));
+    }
+
+    let err = h.engine().generate(&h.request("ておく")).await.unwrap_err();
+
+    assert!(
+        matches!(err, AppError::Validation(ref m) if m.contains("'Meaning' failed contains_japanese"))
+    );
+    assert!(h.anki.notes().is_empty());
+    assert_eq!(h.ollama.pending_replies(), 0);
+}
+
+#[test]
+fn invalid_pattern_is_a_config_error() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("config.yaml");
+    std::fs::write(&path, "validation:\n  Example:\n    - !pattern \"(unclosed\"\n").unwrap();
+
+    let err = Config::load_from_file(&path).unwrap_err();
+
+    assert!(matches!(err, AppError::Config(ref m) if m.contains("invalid pattern /(unclosed/")));
+}
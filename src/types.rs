@@ -1,21 +1,285 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+use std::path::PathBuf;
 
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 
+use crate::config::ModelOptions;
+use crate::metrics::{CardMetrics, MetricsSummary};
+
 /// What the user asks for when generating a card.
+#[derive(Clone, Debug)]
 pub struct CardRequest {
     pub description: String,
     pub fields: Vec<String>,
     pub note_type: String,
     pub deck: String,
     pub optional_fields: bool,
+    /// Images for a multimodal model to base the card on.
+    pub images: Vec<PathBuf>,
+}
+
+/// One batch item, with the other columns of its row when the batch file has them.
+#[derive(Clone, Debug, Default)]
+pub struct BatchItem {
+    pub item: String,
+    pub columns: BTreeMap<String, String>,
+}
+
+impl BatchItem {
+    pub fn new(item: impl Into<String>) -> Self {
+        Self {
+            item: item.into(),
+            columns: BTreeMap::new(),
+        }
+    }
+}
+
+/// Who a chat message is from.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    System,
+    User,
+    Assistant,
+}
+
+/// One message of a chat transcript sent to the model.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ChatMessage {
+    pub role: Role,
+    pub content: String,
+    /// Base64-encoded images, for multimodal models.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub images: Vec<String>,
+}
+
+impl ChatMessage {
+    pub fn system(content: impl Into<String>) -> Self {
+        Self {
+            role: Role::System,
+            content: content.into(),
+            images: Vec::new(),
+        }
+    }
+
+    pub fn user(content: impl Into<String>) -> Self {
+        Self {
+            role: Role::User,
+            content: content.into(),
+            images: Vec::new(),
+        }
+    }
+
+    pub fn assistant(content: impl Into<String>) -> Self {
+        Self {
+            role: Role::Assistant,
+            content: content.into(),
+            images: Vec::new(),
+        }
+    }
 }
 
 /// The model's output — field name to field value.
 pub type CardFields = HashMap<String, String>;
 
+/// What happened to a generated card in Anki.
+#[derive(Serialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum NoteAction {
+    #[default]
+    Added,
+    /// Empty fields of an existing duplicate were filled in.
+    Updated,
+    /// A duplicate, left alone.
+    Skipped,
+    /// Adding the note failed; only reported for sibling notes.
+    Failed,
+}
+
+/// A card produced by `generate`, `next` or one batch item.
+#[derive(Serialize, Clone, Debug)]
+pub struct CardResult {
+    pub item: String,
+    /// `None` on a dry run or for a skipped duplicate.
+    pub note_id: Option<u64>,
+    pub fields: CardFields,
+    pub action: NoteAction,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub siblings: Vec<SiblingNote>,
+    pub metrics: CardMetrics,
+}
+
+/// A note added alongside the main note of a card, from the same generated fields.
+#[derive(Serialize, Clone, Debug)]
+pub struct SiblingNote {
+    pub deck: String,
+    pub note_type: String,
+    pub note_id: Option<u64>,
+    pub action: NoteAction,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// A failed batch item, as reported in the summary.
+#[derive(Serialize, Clone, Debug)]
+pub struct FailedItem {
+    pub item: String,
+    pub error: String,
+}
+
+/// Outcome of a batch run.
+#[derive(Serialize, Clone, Debug, Default)]
+pub struct BatchReport {
+    pub succeeded: usize,
+    /// Duplicates left alone under the `skip` policy.
+    pub skipped: usize,
+    pub failed: usize,
+    pub total: usize,
+    pub cards: Vec<CardResult>,
+    pub failures: Vec<FailedItem>,
+    /// Stopped early by Ctrl-C or SIGTERM; later items were not started.
+    pub interrupted: bool,
+    /// Totals over the items attempted, failed ones included.
+    pub metrics: MetricsSummary,
+}
+
+/// One line of the batch journal, appended as each item is started and finished.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct JournalEntry {
+    /// When the batch started; the lines of one run share it.
+    pub run: DateTime<Utc>,
+    pub at: DateTime<Utc>,
+    pub item: String,
+    pub status: JournalStatus,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub note_id: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum JournalStatus {
+    /// Generation started; an item with no later line was cut off.
+    Attempted,
+    /// The note was added or updated.
+    Succeeded,
+    /// A duplicate, left alone.
+    Skipped,
+    Failed,
+}
+
 /// History of items already generated (persisted to disk).
 #[derive(Serialize, Deserialize, Default)]
 pub struct StoredHistory {
+    /// Item names `next` and the duplicate check avoid. The canonical list; older
+    /// versions read and write only this.
     pub used_items: Vec<String>,
+    /// Where and when each item was generated. Items recorded before entries existed
+    /// have none.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub entries: Vec<HistoryEntry>,
+    #[serde(default)]
+    pub runs: Vec<RunRecord>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub edits: Vec<FieldEdit>,
+}
+
+impl StoredHistory {
+    /// Mark an item as used, with its metadata.
+    pub fn record(&mut self, entry: HistoryEntry) {
+        self.used_items.push(entry.item.clone());
+        self.entries.push(entry);
+    }
+
+    /// What each item was generated for: its `next` topic, or else its deck. Items
+    /// recorded before entries existed have neither.
+    pub fn item_groups(&self) -> HashMap<String, String> {
+        self.entries
+            .iter()
+            .filter_map(|e| {
+                let group = match (&e.topic, &e.deck) {
+                    (Some(topic), _) => format!("\"{}\"", topic),
+                    (None, Some(deck)) => format!("deck \"{}\"", deck),
+                    (None, None) => return None,
+                };
+                Some((e.item.clone(), group))
+            })
+            .collect()
+    }
+}
+
+/// Metadata about one used item. Everything but the name is optional, so items from
+/// older histories can be listed the same way.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct HistoryEntry {
+    pub item: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub added_at: Option<DateTime<Utc>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub command: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deck: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub note_type: Option<String>,
+    /// The `next` description the item was generated for.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub topic: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub note_id: Option<u64>,
+}
+
+/// Fields of an existing note overwritten by `regen`, with their previous values
+/// so the edit can be reverted.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct FieldEdit {
+    pub edited_at: DateTime<Utc>,
+    pub note_id: u64,
+    pub previous: CardFields,
+    pub new: CardFields,
+    #[serde(default)]
+    pub reverted: bool,
+}
+
+/// Metadata about one command run: which model and options produced the cards.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct RunRecord {
+    pub started_at: DateTime<Utc>,
+    pub command: String,
+    pub model: String,
+    #[serde(default, skip_serializing_if = "ModelOptions::is_empty")]
+    pub options: ModelOptions,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub keep_alive: Option<String>,
+    pub cards_added: usize,
+    /// Stopped early by Ctrl-C or SIGTERM.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub interrupted: bool,
+    /// Metrics of each card generated (or failed) in the run.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub cards: Vec<CardMetrics>,
+}
+
+/// Result of checking Ollama (and the configured model) and AnkiConnect.
+#[derive(Serialize, Debug, Clone)]
+pub struct CheckReport {
+    pub model: String,
+    pub ollama_ok: bool,
+    pub model_found: bool,
+    pub models: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ollama_error: Option<String>,
+    pub anki_ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub anki_version: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub anki_error: Option<String>,
+}
+
+impl CheckReport {
+    /// Both services reachable. A missing model is only a warning.
+    pub fn ok(&self) -> bool {
+        self.ollama_ok && self.anki_ok
+    }
 }
@@ -0,0 +1,127 @@
+//! Declarative per-field rules, checked on model output before a card is accepted.
+
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::config::Rule;
+use crate::dedup::normalize_key;
+use crate::types::CardFields;
+
+const JLPT_LEVELS: &[&str] = &["N5", "N4", "N3", "N2", "N1"];
+
+/// A field whose value broke a rule.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Violation {
+    pub field: String,
+    pub rule: &'static str,
+    pub detail: String,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "field '{}' failed {}: {}",
+            self.field, self.rule, self.detail
+        )
+    }
+}
+
+impl Rule {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Rule::Pattern(_) => "pattern",
+            Rule::MinLength(_) => "min_length",
+            Rule::MaxLength(_) => "max_length",
+            Rule::ContainsJapanese => "contains_japanese",
+            Rule::ContainsKey => "contains_key",
+            Rule::OneOf(_) => "one_of",
+            Rule::JlptLevel => "jlpt_level",
+            Rule::NotEqual(_) => "not_equal",
+        }
+    }
+}
+
+fn is_japanese(c: char) -> bool {
+    matches!(c,
+        '\u{3040}'..='\u{30FF}'   // hiragana, katakana
+        | '\u{3400}'..='\u{4DBF}' // CJK extension A
+        | '\u{4E00}'..='\u{9FFF}' // CJK unified ideographs
+        | '\u{FF66}'..='\u{FF9F}' // half-width katakana
+        | '々')
+}
+
+/// Check every field with content against its rules. `key_field` names the field
+/// holding the card's key expression, for `contains_key`.
+pub fn check(
+    rules: &HashMap<String, Vec<Rule>>,
+    fields: &CardFields,
+    key_field: Option<&str>,
+) -> Vec<Violation> {
+    let key = key_field
+        .and_then(|k| fields.get(k))
+        .map(|v| normalize_key(v))
+        .filter(|k| !k.is_empty());
+
+    let mut names: Vec<&String> = rules.keys().collect();
+    names.sort();
+
+    let mut violations = Vec::new();
+    for name in names {
+        let Some(value) = fields.get(name).filter(|v| !v.trim().is_empty()) else {
+            continue;
+        };
+        for rule in &rules[name] {
+            if let Some(detail) = check_rule(rule, name, value, key.as_deref(), key_field, fields) {
+                violations.push(Violation {
+                    field: name.clone(),
+                    rule: rule.name(),
+                    detail,
+                });
+            }
+        }
+    }
+    violations
+}
+
+/// `None` if the value passes, otherwise what is wrong with it.
+fn check_rule(
+    rule: &Rule,
+    name: &str,
+    value: &str,
+    key: Option<&str>,
+    key_field: Option<&str>,
+    fields: &CardFields,
+) -> Option<String> {
+    let value = value.trim();
+    match rule {
+        Rule::Pattern(pattern) => {
+            (!pattern.is_match(value)).then(|| format!("must match /{}/", pattern.as_str()))
+        }
+        Rule::MinLength(min) => {
+            let len = value.chars().count();
+            (len < *min).then(|| format!("{} characters, at least {} required", len, min))
+        }
+        Rule::MaxLength(max) => {
+            let len = value.chars().count();
+            (len > *max).then(|| format!("{} characters, at most {} allowed", len, max))
+        }
+        Rule::ContainsJapanese => {
+            (!value.chars().any(is_japanese)).then(|| "must contain Japanese text".to_string())
+        }
+        Rule::ContainsKey => {
+            // The key field trivially contains itself
+            let key = key.filter(|_| key_field != Some(name))?;
+            (!normalize_key(value).contains(key))
+                .then(|| format!("must contain the key expression '{}'", key))
+        }
+        Rule::OneOf(allowed) => (!allowed.iter().any(|a| a == value))
+            .then(|| format!("'{}' is not one of {}", value, allowed.join(", "))),
+        Rule::JlptLevel => (!JLPT_LEVELS.contains(&value))
+            .then(|| format!("'{}' is not one of {}", value, JLPT_LEVELS.join(", "))),
+        Rule::NotEqual(other) => fields
+            .get(other)
+            .filter(|o| o.trim() == value)
+            .map(|_| format!("must differ from field '{}'", other)),
+    }
+}